@@ -0,0 +1,241 @@
+//! # Buddy Allocator
+//!
+//! Allocates physically contiguous runs of `2^order` frames. A block of order `n` is always
+//! aligned to its own size, so that its buddy, i.e. the other half of the block of order `n + 1`,
+//! is found by flipping a single bit of its address. Freed blocks are coalesced with their buddy
+//! whenever it is free as well.
+//!
+//! The free lists are threaded through the free blocks themselves, accessed through the mapping of
+//! the whole physical memory.
+
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use spin::Mutex;
+
+use crate::allocator::BUDDY_POOL_SIZE;
+use crate::arch::paging::PageSize;
+use crate::arch::paging::Size4KiB;
+use crate::arch::PhysicalAddress;
+use crate::arch::PhysicalFrame;
+use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::physical_to_virtual;
+
+/// Maximal order of blocks, i.e. blocks of `2^MAX_ORDER` frames (4MiB).
+pub const MAX_ORDER: usize = 10;
+
+/// Global buddy allocator.
+pub static BUDDY_ALLOCATOR: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::new());
+
+/// Header written at the start of a free block, linking it to the next free block of same order.
+struct FreeBlock {
+  next: Option<PhysicalAddress>,
+}
+
+pub struct BuddyAllocator {
+  free_lists:  [Option<PhysicalAddress>; MAX_ORDER + 1],
+  free_blocks: [usize; MAX_ORDER + 1],
+  /// Count of frames managed by the allocator.
+  frames:      usize,
+}
+
+impl BuddyAllocator {
+  pub const fn new() -> Self {
+    Self {
+      free_lists:  [None; MAX_ORDER + 1],
+      free_blocks: [0; MAX_ORDER + 1],
+      frames:      0,
+    }
+  }
+
+  /// Return the smallest order whose blocks hold `count` frames.
+  #[inline]
+  pub const fn order_of(count: usize) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize
+  }
+
+  /// Give the frames in `[start, end)` to the allocator.
+  ///
+  /// ## Safety
+  /// The frames must be unused, and must not be given to any other allocator.
+  pub unsafe fn add_range(&mut self, start: PhysicalFrame, end: PhysicalFrame) {
+    let mut address = start.start_address().as_raw();
+    let end = end.start_address().as_raw();
+    while address < end {
+      // The largest block aligned at `address` which fits in the range.
+      let mut order = MAX_ORDER.min((address / Size4KiB::SIZE).trailing_zeros() as usize);
+      while address + (Size4KiB::SIZE << order) > end {
+        order -= 1;
+      }
+      self.frames += 1 << order;
+      self.push(PhysicalAddress::new(address), order);
+      address += Size4KiB::SIZE << order;
+    }
+  }
+
+  /// Allocate a block of `2^order` contiguous frames, and return its first frame.
+  pub fn allocate(&mut self, order: usize) -> Option<PhysicalFrame> {
+    if order > MAX_ORDER {
+      return None;
+    }
+    // Split the smallest free block which is large enough.
+    let found = (order..=MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+    let block = self.pop(found)?;
+    for lower in (order..found).rev() {
+      self.push(block + (Size4KiB::SIZE << lower), lower);
+    }
+    Some(PhysicalFrame::containing_address(block))
+  }
+
+  /// Give back a block of `2^order` frames, and coalesce it with its buddies.
+  ///
+  /// ## Safety
+  /// The block must be allocated from this allocator with the same order, and no longer in use.
+  pub unsafe fn deallocate(&mut self, frame: PhysicalFrame, order: usize) {
+    let mut block = frame.start_address();
+    let mut order = order;
+    while order < MAX_ORDER {
+      let buddy = PhysicalAddress::new(block.as_raw() ^ (Size4KiB::SIZE << order));
+      if !self.remove(buddy, order) {
+        break;
+      }
+      block = block.min(buddy);
+      order += 1;
+    }
+    self.push(block, order);
+  }
+
+  /// Return the count of free blocks of each order.
+  pub fn statistics(&self) -> BuddyStatistics {
+    BuddyStatistics {
+      free_blocks: self.free_blocks,
+      frames:      self.frames,
+    }
+  }
+
+  #[inline]
+  fn header(block: PhysicalAddress) -> *mut FreeBlock {
+    physical_to_virtual(block).as_mut_ptr()
+  }
+
+  fn push(&mut self, block: PhysicalAddress, order: usize) {
+    unsafe {
+      Self::header(block).write(FreeBlock {
+        next: self.free_lists[order],
+      });
+    }
+    self.free_lists[order] = Some(block);
+    self.free_blocks[order] += 1;
+  }
+
+  fn pop(&mut self, order: usize) -> Option<PhysicalAddress> {
+    let block = self.free_lists[order]?;
+    self.free_lists[order] = unsafe { (*Self::header(block)).next };
+    self.free_blocks[order] -= 1;
+    Some(block)
+  }
+
+  /// Remove the block from the free list of the order, return `false` if it's not free.
+  fn remove(&mut self, block: PhysicalAddress, order: usize) -> bool {
+    let mut link = &mut self.free_lists[order] as *mut Option<PhysicalAddress>;
+    unsafe {
+      while let Some(current) = *link {
+        if current == block {
+          *link = (*Self::header(current)).next;
+          self.free_blocks[order] -= 1;
+          return true;
+        }
+        link = &mut (*Self::header(current)).next;
+      }
+    }
+    false
+  }
+}
+
+impl Default for BuddyAllocator {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// The free blocks are only reachable through the allocator, which is guarded by a lock.
+unsafe impl Send for BuddyAllocator {}
+
+/// Statistics of the buddy allocator.
+#[derive(Debug, Clone, Copy)]
+pub struct BuddyStatistics {
+  /// Count of free blocks of each order.
+  pub free_blocks: [usize; MAX_ORDER + 1],
+  /// Count of frames managed by the allocator.
+  pub frames:      usize,
+}
+
+impl BuddyStatistics {
+  /// Count of free frames.
+  pub fn free_frames(&self) -> usize {
+    self.free_blocks.iter().enumerate().map(|(order, count)| count << order).sum()
+  }
+}
+
+impl Display for BuddyStatistics {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+    writeln!(f, "{} of {} frames free", self.free_frames(), self.frames)?;
+    for (order, count) in self.free_blocks.iter().enumerate() {
+      writeln!(f, "  order {:>2} ({:>5} KiB): {}", order, 4 << order, count)?;
+    }
+    Ok(())
+  }
+}
+
+/// Take a pool of contiguous frames from the frame allocator, and give it to the global buddy
+/// allocator. The pool shrinks by half each time there is not enough contiguous memory.
+pub fn init_buddy() {
+  let mut count = BUDDY_POOL_SIZE / Size4KiB::SIZE as usize;
+  while count > 0 {
+    let start = match FRAME_ALLOCATOR.lock().as_mut() {
+      Some(allocator) => allocator.allocate_contiguous(count),
+      None => panic!("init_buddy: frame allocator not initialized"),
+    };
+    if let Some(start) = start {
+      unsafe {
+        BUDDY_ALLOCATOR.lock().add_range(start, start + count as u64);
+      }
+      return;
+    }
+    count /= 2;
+  }
+}
+
+/// Allocate `2^order` contiguous frames from the global buddy allocator.
+pub fn allocate_frames(order: usize) -> Option<PhysicalFrame> {
+  BUDDY_ALLOCATOR.lock().allocate(order)
+}
+
+/// Give back `2^order` contiguous frames to the global buddy allocator.
+///
+/// ## Safety
+/// The frames must be allocated by `allocate_frames` with the same order, and no longer in use.
+pub unsafe fn deallocate_frames(frame: PhysicalFrame, order: usize) {
+  BUDDY_ALLOCATOR.lock().deallocate(frame, order)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_buddy_coalesce() {
+    let mut buddy = BUDDY_ALLOCATOR.lock();
+    let before = buddy.statistics();
+
+    let a = buddy.allocate(0).unwrap();
+    let b = buddy.allocate(3).unwrap();
+    assert!(b.start_address().as_raw().is_multiple_of(Size4KiB::SIZE << 3));
+    unsafe {
+      buddy.deallocate(a, 0);
+      buddy.deallocate(b, 3);
+    }
+
+    assert_eq!(before.free_blocks, buddy.statistics().free_blocks);
+  }
+}
@@ -6,18 +6,35 @@ use core::ptr;
 use core::ptr::NonNull;
 
 use linked_list_allocator::Heap;
-use spin::Mutex;
 
+use crate::allocator::SlabAllocator;
+use crate::allocator::KERNEL_HEAP_MAX_SIZE;
 use crate::allocator::KERNEL_HEAP_SIZE;
+use crate::allocator::KERNEL_HEAP_START;
+use crate::allocator::SLAB_ALLOCATOR;
+use crate::arch::paging::PageSize;
+use crate::arch::paging::PageTableFlags;
+use crate::arch::paging::Size4KiB;
+use crate::arch::Page;
 use crate::arch::VirtualAddress;
+use crate::mem::frame::FrameAllocator;
+use crate::mem::frame::FrameDeallocator;
+use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::mapper::MapToError;
+use crate::mem::KERNEL_MAPPER;
+use crate::println;
+use crate::support::sync::IrqSpinLock;
 
 /// Global heap allocator.
+///
+/// `&HEAP_ALLOCATOR` is also a handle for collections with explicit allocator, such as
+/// `Vec::new_in(&HEAP_ALLOCATOR)`.
 #[global_allocator]
-static HEAP_ALLOCATOR: MyAllocator = MyAllocator;
+pub static HEAP_ALLOCATOR: MyAllocator = MyAllocator;
 
-static HEAP: Mutex<Option<Heap>> = Mutex::new(None);
+static HEAP: IrqSpinLock<Option<Heap>> = IrqSpinLock::new(None);
 
-struct MyAllocator;
+pub struct MyAllocator;
 
 impl MyAllocator {
   /// ## Safety
@@ -26,36 +43,245 @@ impl MyAllocator {
   }
 }
 
-unsafe impl<'a> Allocator for &'a MyAllocator {
-  fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-    todo!()
+/// Map the initial kernel heap region and initialize the heap allocator on it.
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+  let start = VirtualAddress::new(KERNEL_HEAP_START);
+  let end = start + KERNEL_HEAP_SIZE as u64;
+  unsafe {
+    map_heap_pages(start, end)?;
+    MyAllocator::init(start, end);
   }
+  Ok(())
+}
 
-  unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-    todo!()
+/// Map fresh frames to the heap pages in `[start, end)`. Either all the pages are mapped, or none
+/// of them if it fails.
+///
+/// The kernel mapper is locked before the frame allocator.
+///
+/// ## Safety
+/// The pages must not be in use.
+unsafe fn map_heap_pages(
+  start: VirtualAddress,
+  end: VirtualAddress,
+) -> Result<(), MapToError<Size4KiB>> {
+  let mut mapper = KERNEL_MAPPER.lock();
+  let mapper = mapper.as_mut().expect("kernel mapper not initialized");
+  let mut allocator = FRAME_ALLOCATOR.lock();
+  let allocator = allocator.as_mut().expect("frame allocator not initialized");
+
+  let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+  let first = Page::<Size4KiB>::containing_address(start);
+  let count = (end - start) / Size4KiB::SIZE;
+  for i in 0..count {
+    let result = match allocator.allocate_frame() {
+      Some(frame) => mapper.map_to(first + i, frame, flags, allocator).inspect_err(|_| {
+        allocator.deallocate_frame(frame);
+      }),
+      None => Err(MapToError::FrameAllocationFailed),
+    };
+    match result {
+      Ok(flush) => flush.flush(),
+      Err(err) => {
+        // Roll back the pages mapped so far.
+        for j in 0..i {
+          if let Ok((frame, flush)) = mapper.unmap(first + j) {
+            flush.flush();
+            allocator.deallocate_frame(frame);
+          }
+        }
+        return Err(err);
+      }
+    }
   }
+  Ok(())
 }
 
-unsafe impl GlobalAlloc for MyAllocator {
-  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-    while let Some(ref mut heap) = *HEAP.lock() {
+/// Grow the heap to satisfy the allocation of `layout`, never beyond `KERNEL_HEAP_MAX_SIZE`.
+/// Return `false` if the heap can not grow any more.
+///
+/// ## Safety
+/// The heap must be the kernel heap, which ends at an unmapped page.
+unsafe fn grow_heap(heap: &mut Heap, layout: Layout) -> bool {
+  let remaining = KERNEL_HEAP_MAX_SIZE - heap.size();
+  if remaining == 0 {
+    return false;
+  }
+
+  // Grow by `KERNEL_HEAP_SIZE` at least, so that the heap doesn't grow page by page.
+  let wanted = (layout.size() + layout.align()).max(KERNEL_HEAP_SIZE);
+  let wanted = wanted.next_multiple_of(Size4KiB::SIZE as usize);
+  let by = wanted.min(remaining);
+
+  let top = VirtualAddress::from_ptr(heap.top());
+  match map_heap_pages(top, top + by as u64) {
+    Ok(()) => {
+      heap.extend(by);
+      true
+    }
+    Err(err) => {
+      println!("[ERROR] Failed to grow kernel heap: {:?}", err);
+      false
+    }
+  }
+}
+
+/// True if the address belongs to the kernel heap, rather than a slab.
+#[inline]
+fn is_heap_address(ptr: *mut u8) -> bool {
+  let address = ptr as u64;
+  (KERNEL_HEAP_START..KERNEL_HEAP_START + KERNEL_HEAP_MAX_SIZE as u64).contains(&address)
+}
+
+/// Allocate from the linked-list heap, growing it if needed. Return null if it fails.
+///
+/// ## Safety
+/// The layout must have non-zero size.
+unsafe fn allocate_from_heap(layout: Layout) -> *mut u8 {
+  if let Some(ref mut heap) = *HEAP.lock() {
+    loop {
       match heap.allocate_first_fit(layout) {
-        res => return res.ok().map_or(ptr::null_mut(), |alloc| alloc.as_ptr()),
+        Ok(alloc) => return alloc.as_ptr(),
         Err(()) => {
-          let size = heap.size();
-          // TODO
-          heap.extend(KERNEL_HEAP_SIZE)
+          if !grow_heap(heap, layout) {
+            println!(
+              "[ERROR] Kernel heap exhausted: size {} bytes, align {}, heap used {} of {} bytes.",
+              layout.size(),
+              layout.align(),
+              heap.used(),
+              heap.size()
+            );
+            // `handle_alloc_error` reports the failure then.
+            return ptr::null_mut();
+          }
         }
       }
     }
-    panic!("__rust_allocate: heap not initialized");
+  }
+  panic!("__rust_allocate: heap not initialized");
+}
+
+impl MyAllocator {
+  /// Move the block to the new layout. Slab objects stay in place if the new layout is served by
+  /// the same size class.
+  ///
+  /// ## Safety
+  /// The block must be allocated by this allocator with `old_layout`.
+  unsafe fn reallocate(
+    &self,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> Result<NonNull<[u8]>, AllocError> {
+    if old_layout.size() != 0 && !is_heap_address(ptr.as_ptr()) {
+      if let Some(size) = SlabAllocator::object_size(old_layout) {
+        if SlabAllocator::object_size(new_layout) == Some(size) {
+          return Ok(NonNull::slice_from_raw_parts(ptr, size));
+        }
+      }
+    }
+
+    let new_ptr = self.allocate(new_layout)?;
+    ptr::copy_nonoverlapping(
+      ptr.as_ptr(),
+      new_ptr.cast::<u8>().as_ptr(),
+      old_layout.size().min(new_layout.size()),
+    );
+    self.deallocate(ptr, old_layout);
+    Ok(new_ptr)
+  }
+}
+
+unsafe impl<'a> Allocator for &'a MyAllocator {
+  fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    if layout.size() == 0 {
+      let dangling = ptr::without_provenance_mut::<u8>(layout.align());
+      return Ok(NonNull::slice_from_raw_parts(
+        unsafe { NonNull::new_unchecked(dangling) },
+        0,
+      ));
+    }
+
+    // Fast path: small objects are served by the slab allocator, fall back to the heap if it
+    // runs out of frames.
+    if let Ok(ptr) = (&SLAB_ALLOCATOR).allocate(layout) {
+      return Ok(ptr);
+    }
+
+    let ptr = NonNull::new(unsafe { allocate_from_heap(layout) }).ok_or(AllocError)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+  }
+
+  unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() != 0 {
+      self.dealloc(ptr.as_ptr(), layout)
+    }
+  }
+
+  unsafe fn grow(
+    &self,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> Result<NonNull<[u8]>, AllocError> {
+    debug_assert!(new_layout.size() >= old_layout.size());
+    self.reallocate(ptr, old_layout, new_layout)
+  }
+
+  unsafe fn shrink(
+    &self,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> Result<NonNull<[u8]>, AllocError> {
+    debug_assert!(new_layout.size() <= old_layout.size());
+    self.reallocate(ptr, old_layout, new_layout)
+  }
+}
+
+unsafe impl GlobalAlloc for MyAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self.allocate(layout).map_or(ptr::null_mut(), |ptr| ptr.cast::<u8>().as_ptr())
   }
 
   unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    if !is_heap_address(ptr) {
+      return (&SLAB_ALLOCATOR).deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+
     if let Some(ref mut heap) = *HEAP.lock() {
       heap.deallocate(NonNull::new_unchecked(ptr), layout)
     } else {
       panic!("__rust_deallocate: heap not initialized");
     }
   }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    self
+      .reallocate(NonNull::new_unchecked(ptr), layout, new_layout)
+      .map_or(ptr::null_mut(), |ptr| ptr.cast::<u8>().as_ptr())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use alloc::vec::Vec;
+
+  use super::*;
+
+  #[test_case]
+  fn test_vec_new_in_grow_shrink() {
+    let mut vec = Vec::new_in(&HEAP_ALLOCATOR);
+    // Grow from slab objects to a heap block, beyond the largest size class.
+    for i in 0..4096u32 {
+      vec.push(i);
+    }
+    assert!(is_heap_address(vec.as_mut_ptr().cast()));
+
+    vec.truncate(4);
+    vec.shrink_to_fit();
+    assert_eq!(vec.capacity(), 4);
+    assert_eq!(vec, [0, 1, 2, 3]);
+  }
 }
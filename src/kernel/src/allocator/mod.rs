@@ -1,5 +1,24 @@
+mod buddy;
 mod heap;
 mod slab;
 
+pub use self::buddy::allocate_frames;
+pub use self::buddy::deallocate_frames;
+pub use self::buddy::init_buddy;
+pub use self::buddy::BuddyAllocator;
+pub use self::buddy::BuddyStatistics;
+pub use self::buddy::BUDDY_ALLOCATOR;
+pub use self::heap::init_heap;
+pub use self::heap::MyAllocator;
+pub use self::heap::HEAP_ALLOCATOR;
+pub use self::slab::SlabAllocator;
+pub use self::slab::SLAB_ALLOCATOR;
+
+/// Start address of kernel heap.
+pub const KERNEL_HEAP_START: u64 = 0x_4444_4444_0000;
 /// Size of kernel heap.
 pub const KERNEL_HEAP_SIZE: usize = 1024 * 1024;
+/// Maximum size that the kernel heap can grow to.
+pub const KERNEL_HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+/// Size of the physical memory pool of the buddy allocator.
+pub const BUDDY_POOL_SIZE: usize = 16 * 1024 * 1024;
@@ -1,18 +1,161 @@
+//! # Slab Allocator
+//!
+//! Small objects are allocated from caches of fixed size classes. Each cache carves 4KiB slabs,
+//! taken from the frame allocator, into objects of its size, and keeps the free objects in a list
+//! threaded through the objects themselves. The caches are locked with interrupts disabled, so
+//! that interrupt handlers may allocate and free objects.
+//!
+//! Since every size class is a power of two and slabs are page aligned, each object is aligned to
+//! its size.
+
 use core::alloc::AllocError;
 use core::alloc::Allocator;
 use core::alloc::Layout;
 use core::ptr::NonNull;
 
-pub struct SlabAllocator;
+use crate::mem::frame;
+use crate::mem::physical_to_virtual;
+use crate::println;
+use crate::support::sync::IrqSpinLock;
+
+/// Size of a slab.
+const SLAB_SIZE: usize = 4096;
+
+/// Object sizes of slab caches.
+const SIZE_CLASSES: [usize; 10] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Global slab allocator.
+pub static SLAB_ALLOCATOR: SlabAllocator = SlabAllocator::new();
+
+/// Header written in a free object, linking it to the next free object.
+struct FreeObject {
+  next: Option<NonNull<FreeObject>>,
+}
+
+/// Cache of objects of a single size class.
+struct SlabCache {
+  object_size: usize,
+  free_list:   Option<NonNull<FreeObject>>,
+  /// Count of slabs owned by the cache.
+  slabs:       usize,
+  /// Count of objects in use.
+  allocated:   usize,
+}
+
+// The objects are only reachable through the cache, which is guarded by a lock.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+  const fn new(object_size: usize) -> Self {
+    Self {
+      object_size,
+      free_list: None,
+      slabs: 0,
+      allocated: 0,
+    }
+  }
+
+  fn allocate(&mut self) -> Option<NonNull<u8>> {
+    if self.free_list.is_none() {
+      self.grow()?;
+    }
+    let object = self.free_list?;
+    self.free_list = unsafe { object.as_ref().next };
+    self.allocated += 1;
+    Some(object.cast())
+  }
+
+  /// ## Safety
+  /// The object must be allocated from this cache.
+  unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+    let object = ptr.cast::<FreeObject>();
+    object.as_ptr().write(FreeObject {
+      next: self.free_list,
+    });
+    self.free_list = Some(object);
+    self.allocated -= 1;
+  }
 
-impl SlabAllocator {}
+  /// Take a new slab from the frame allocator, and put its objects into the free list.
+  fn grow(&mut self) -> Option<()> {
+    let frame = frame::allocate_frame()?;
+    let start = physical_to_virtual(frame.start_address());
+    for i in (0..SLAB_SIZE / self.object_size).rev() {
+      let object = (start + (i * self.object_size) as u64).as_mut_ptr::<FreeObject>();
+      unsafe {
+        object.write(FreeObject {
+          next: self.free_list,
+        });
+        self.free_list = Some(NonNull::new_unchecked(object));
+      }
+    }
+    self.slabs += 1;
+    Some(())
+  }
+}
+
+pub struct SlabAllocator {
+  caches: [IrqSpinLock<SlabCache>; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+  pub const fn new() -> Self {
+    Self {
+      caches: [
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[0])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[1])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[2])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[3])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[4])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[5])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[6])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[7])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[8])),
+        IrqSpinLock::new(SlabCache::new(SIZE_CLASSES[9])),
+      ],
+    }
+  }
+
+  /// Return the index of the cache serving the layout, or `None` if it's too large for any.
+  #[inline]
+  fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&object_size| object_size >= size)
+  }
+
+  /// Return the size of objects serving the layout, or `None` if it's too large for any cache.
+  #[inline]
+  pub fn object_size(layout: Layout) -> Option<usize> {
+    Self::size_class(layout).map(|index| SIZE_CLASSES[index])
+  }
+
+  /// Print the usage of each cache.
+  pub fn dump(&self) {
+    for cache in self.caches.iter() {
+      let cache = cache.lock();
+      println!(
+        "[DEBUG] Slab cache {:>4} bytes: {} slabs, {} objects in use.",
+        cache.object_size, cache.slabs, cache.allocated
+      );
+    }
+  }
+}
+
+impl Default for SlabAllocator {
+  fn default() -> Self {
+    Self::new()
+  }
+}
 
 unsafe impl<'a> Allocator for &'a SlabAllocator {
   fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-    todo!()
+    let index = SlabAllocator::size_class(layout).ok_or(AllocError)?;
+    let ptr = self.caches[index].lock().allocate().ok_or(AllocError)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, SIZE_CLASSES[index]))
   }
 
   unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-    todo!()
+    let index = SlabAllocator::size_class(layout).expect("layout not served by slab allocator");
+    self.caches[index].lock().deallocate(ptr)
   }
 }
@@ -1,3 +1,12 @@
+pub use crate::arch::shared::irq::irqs;
+pub use crate::arch::shared::irq::register_irq;
+pub use crate::arch::shared::irq::unregister_irq;
+use crate::arch::shared::irq::Irq;
+use crate::arch::shared::irq::IrqError;
+
+/// Count of IRQs, i.e. the interrupt IDs of a GIC.
+pub const NR_IRQS: usize = 1020;
+
 /// Enable interrupts.
 ///
 /// # Safety
@@ -16,6 +25,36 @@ pub unsafe fn disable() {
   unsafe { core::arch::asm!("msr daifset, #2") }
 }
 
+/// True if IRQs are enabled, i.e. the I bit of DAIF is clear.
+#[inline(always)]
+pub fn are_enabled() -> bool {
+  let daif: u64;
+  unsafe { core::arch::asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack)) };
+  daif & (1 << 7) == 0
+}
+
+/// Save DAIF and disable interrupts, for [`restore`].
+#[inline(always)]
+pub fn save_and_disable() -> u64 {
+  let daif: u64;
+  unsafe {
+    core::arch::asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack));
+    disable();
+  }
+  daif
+}
+
+/// Restore the I bit of DAIF saved by [`save_and_disable`].
+///
+/// # Safety
+/// Enabling interrupts may run the handlers in the middle of a critical section.
+#[inline(always)]
+pub unsafe fn restore(daif: u64) {
+  if daif & (1 << 7) == 0 {
+    enable();
+  }
+}
+
 /// Halt instruction
 ///
 /// # Safety
@@ -30,3 +69,19 @@ pub fn pause() {
     core::arch::asm!("nop");
   }
 }
+
+/// Mask the IRQ on the interrupt controller. The GIC is not supported yet, so no IRQ can be
+/// registered.
+///
+/// # Safety
+pub unsafe fn mask_irq(_irq: Irq) -> Result<(), IrqError> {
+  Err(IrqError::Unsupported)
+}
+
+/// Unmask the IRQ on the interrupt controller. The GIC is not supported yet, so no IRQ can be
+/// registered.
+///
+/// # Safety
+pub unsafe fn unmask_irq(_irq: Irq) -> Result<(), IrqError> {
+  Err(IrqError::Unsupported)
+}
@@ -199,6 +199,24 @@ impl VirtualAddress {
     Self::new(ptr as *const () as u64)
   }
 
+  /// Align the address downwards to the given alignment.
+  #[inline]
+  pub fn align_down<S>(self, align: S) -> Self
+  where
+    S: Into<PtrWidth>,
+  {
+    Self::new(align_down(self.0, align.into()))
+  }
+
+  /// True if the address is aligned to the given alignment.
+  #[inline]
+  pub fn is_aligned<S>(self, align: S) -> bool
+  where
+    S: Into<PtrWidth>,
+  {
+    self.align_down(align) == self
+  }
+
   #[inline]
   pub const fn as_ptr<T>(self) -> *const T {
     self.as_raw() as *const T
@@ -295,6 +313,16 @@ impl<S: PageSize> PhysicalFrame<S> {
   }
 }
 
+impl<S: PageSize> Add<PtrWidth> for PhysicalFrame<S> {
+  type Output = Self;
+
+  /// Return the frame `rhs` frames after.
+  #[inline]
+  fn add(self, rhs: PtrWidth) -> Self::Output {
+    Self::containing_address(self.address + rhs * S::SIZE)
+  }
+}
+
 impl<S: PageSize> core::fmt::Debug for PhysicalFrame<S> {
   fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
     write!(
@@ -306,6 +334,60 @@ impl<S: PageSize> core::fmt::Debug for PhysicalFrame<S> {
   }
 }
 
+/// A virtual memory page of size `S`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page<S: PageSize = Size4KiB> {
+  address: VirtualAddress,
+  size:    PhantomData<S>,
+}
+
+impl<S: PageSize> Page<S> {
+  /// Size of the page.
+  pub const SIZE: PtrWidth = S::SIZE;
+
+  pub fn start_with(address: VirtualAddress) -> Result<Self, AddressNotAligned> {
+    if !address.is_aligned(S::SIZE) {
+      return Err(AddressNotAligned);
+    }
+    Ok(Self {
+      address,
+      size: PhantomData,
+    })
+  }
+
+  pub fn start_address(&self) -> VirtualAddress {
+    self.address
+  }
+
+  pub fn containing_address(address: VirtualAddress) -> Self {
+    Self {
+      address: address.align_down(S::SIZE),
+      size:    PhantomData,
+    }
+  }
+}
+
+impl<S: PageSize> Add<PtrWidth> for Page<S> {
+  type Output = Self;
+
+  /// Return the page `rhs` pages after.
+  #[inline]
+  fn add(self, rhs: PtrWidth) -> Self::Output {
+    Self::containing_address(self.address + rhs * S::SIZE)
+  }
+}
+
+impl<S: PageSize> core::fmt::Debug for Page<S> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "Page[{}]({:#x})",
+      S::DEBUG_STR,
+      self.start_address().as_raw()
+    )
+  }
+}
+
 /// Error occurred when get a page table entry frame.
 pub enum FrameError {
   FrameNotPresent,
@@ -6,12 +6,23 @@ use core::fmt::Debug;
 use core::fmt::Formatter;
 use core::todo;
 
-use crate::arch::shared::cpuid::__impl::NativeCpuIdReader;
+use bitflags::bitflags;
+
+pub use crate::arch::shared::cpuid::__impl::NativeCpuIdReader;
 
 const EAX_VENDOR_INFO: u32 = 0x0000_0000;
 const EAX_FEATURE_INFO: u32 = 0x0000_0001;
+const EAX_CACHE_PARAMETERS: u32 = 0x0000_0004;
+const EAX_STRUCTURED_EXTENDED_FEATURE_INFO: u32 = 0x0000_0007;
+const EAX_EXTENDED_TOPOLOGY_INFO: u32 = 0x0000_000B;
+const EAX_EXTENDED_STATE_INFO: u32 = 0x0000_000D;
+const EAX_V2_EXTENDED_TOPOLOGY_INFO: u32 = 0x0000_001F;
 
 const EAX_EXTENDED_FUNCTION_INFO: u32 = 0x8000_0000;
+const EAX_EXTENDED_PROCESSOR_FEATURE_INFO: u32 = 0x8000_0001;
+const EAX_BRAND_STRING: u32 = 0x8000_0002;
+const EAX_ADVANCED_POWER_MGMT_INFO: u32 = 0x8000_0007;
+const EAX_AMD_CACHE_PARAMETERS: u32 = 0x8000_001D;
 
 pub struct CpuId<R: CpuIdReader> {
   supported_leaves: u32,
@@ -22,7 +33,7 @@ pub struct CpuId<R: CpuIdReader> {
 
 impl Default for CpuId<NativeCpuIdReader> {
   fn default() -> Self {
-    todo!()
+    Self::with_cpuid_reader(NativeCpuIdReader)
   }
 }
 
@@ -65,6 +76,145 @@ impl<R: CpuIdReader> CpuId<R> {
       None
     }
   }
+
+  /// Get the processor signature and the feature flags of leaf `0x1`.
+  pub fn get_feature_info(&self) -> Option<FeatureInfo> {
+    if self.leaf_is_supported(EAX_FEATURE_INFO) {
+      let res = self.read.cpuid1(EAX_FEATURE_INFO);
+      Some(FeatureInfo {
+        eax: res.eax,
+        ebx: res.ebx,
+        ecx: FeatureInfoEcx::from_bits_retain(res.ecx),
+        edx: FeatureInfoEdx::from_bits_retain(res.edx),
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Get the structured extended feature flags of leaf `0x7`, sub-leaf 0.
+  pub fn get_extended_feature_info(&self) -> Option<ExtendedFeatures> {
+    if self.leaf_is_supported(EAX_STRUCTURED_EXTENDED_FEATURE_INFO) {
+      let res = self.read.cpuid2(EAX_STRUCTURED_EXTENDED_FEATURE_INFO, 0);
+      Some(ExtendedFeatures {
+        ebx: ExtendedFeaturesEbx::from_bits_retain(res.ebx),
+        ecx: ExtendedFeaturesEcx::from_bits_retain(res.ecx),
+        edx: ExtendedFeaturesEdx::from_bits_retain(res.edx),
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Get the XSAVE state components and sizes of leaf `0xD`.
+  pub fn get_extended_state_info(&self) -> Option<ExtendedStateInfo> {
+    if self.leaf_is_supported(EAX_EXTENDED_STATE_INFO) {
+      let main = self.read.cpuid2(EAX_EXTENDED_STATE_INFO, 0);
+      let sub = self.read.cpuid2(EAX_EXTENDED_STATE_INFO, 1);
+      Some(ExtendedStateInfo {
+        xcr0_supported: XFeatures::from_bits_retain(
+          u64::from(main.edx) << 32 | u64::from(main.eax),
+        ),
+        xsave_size:     main.ebx,
+        xsave_max_size: main.ecx,
+        xsave_features: XsaveFeatures::from_bits_retain(sub.eax),
+        xsaves_size:    sub.ebx,
+        xss_supported:  XFeatures::from_bits_retain(u64::from(sub.edx) << 32 | u64::from(sub.ecx)),
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Get the size and offset of the XSAVE state component, from 2 on.
+  pub fn get_extended_state_component(&self, component: u32) -> Option<ExtendedStateComponent> {
+    if component < 2 || !self.leaf_is_supported(EAX_EXTENDED_STATE_INFO) {
+      return None;
+    }
+    let res = self.read.cpuid2(EAX_EXTENDED_STATE_INFO, component);
+    if res.eax == 0 {
+      return None;
+    }
+    Some(ExtendedStateComponent {
+      size:       res.eax,
+      offset:     res.ebx,
+      supervisor: res.ecx & (1 << 0) != 0,
+      aligned:    res.ecx & (1 << 1) != 0,
+    })
+  }
+
+  /// Get the extended processor feature flags of leaf `0x8000_0001`.
+  pub fn get_extended_processor_feature_info(&self) -> Option<ExtendedProcessorFeatures> {
+    if self.leaf_is_supported(EAX_EXTENDED_PROCESSOR_FEATURE_INFO) {
+      let res = self.read.cpuid1(EAX_EXTENDED_PROCESSOR_FEATURE_INFO);
+      Some(ExtendedProcessorFeatures {
+        ecx: ExtendedProcessorFeaturesEcx::from_bits_retain(res.ecx),
+        edx: ExtendedProcessorFeaturesEdx::from_bits_retain(res.edx),
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Get the processor brand string of leaves `0x8000_0002` to `0x8000_0004`.
+  pub fn get_processor_brand_string(&self) -> Option<BrandString> {
+    if self.leaf_is_supported(EAX_BRAND_STRING + 2) {
+      let mut bytes = [0; 48];
+      for (leaf, chunk) in bytes.chunks_exact_mut(16).enumerate() {
+        let res = self.read.cpuid1(EAX_BRAND_STRING + leaf as u32);
+        for (register, value) in chunk.chunks_exact_mut(4).zip([res.eax, res.ebx, res.ecx, res.edx])
+        {
+          register.copy_from_slice(&value.to_le_bytes());
+        }
+      }
+      Some(BrandString { bytes })
+    } else {
+      None
+    }
+  }
+
+  /// Iterate over the caches, from leaf `0x4` on Intel or `0x8000_001D` on AMD.
+  pub fn get_cache_parameters(&self) -> Option<CacheParametersIter<'_, R>> {
+    let leaf = if self.leaf_is_supported(EAX_CACHE_PARAMETERS) {
+      EAX_CACHE_PARAMETERS
+    } else if self.leaf_is_supported(EAX_AMD_CACHE_PARAMETERS)
+      && self
+        .get_extended_processor_feature_info()
+        .is_some_and(|info| info.ecx().contains(ExtendedProcessorFeaturesEcx::TOPOLOGY_EXTENSIONS))
+    {
+      EAX_AMD_CACHE_PARAMETERS
+    } else {
+      return None;
+    };
+    Some(CacheParametersIter {
+      read: &self.read,
+      leaf,
+      index: 0,
+    })
+  }
+
+  /// Iterate over the topology levels of the current processor, from leaf `0x1F` if available,
+  /// otherwise `0xB`.
+  pub fn get_extended_topology_info(&self) -> Option<TopologyIter<'_, R>> {
+    [EAX_V2_EXTENDED_TOPOLOGY_INFO, EAX_EXTENDED_TOPOLOGY_INFO]
+      .into_iter()
+      .find(|&leaf| self.leaf_is_supported(leaf) && self.read.cpuid2(leaf, 0).ebx != 0)
+      .map(|leaf| TopologyIter {
+        read: &self.read,
+        leaf,
+        index: 0,
+      })
+  }
+
+  /// Get advanced power management info, e.g. invariant TSC.
+  pub fn get_advanced_power_mgmt_info(&self) -> Option<ApmInfo> {
+    if self.leaf_is_supported(EAX_ADVANCED_POWER_MGMT_INFO) {
+      let res = self.read.cpuid1(EAX_ADVANCED_POWER_MGMT_INFO);
+      Some(ApmInfo { edx: res.edx })
+    } else {
+      None
+    }
+  }
 }
 
 impl CpuId<NativeCpuIdReader> {
@@ -147,9 +297,21 @@ mod __impl {
   use super::*;
 
   #[derive(Copy, Clone)]
-  pub(super) struct NativeCpuIdReader;
+  pub struct NativeCpuIdReader;
 
   impl CpuIdReader for NativeCpuIdReader {
+    #[cfg(target_arch = "x86_64")]
+    fn cpuid2(&self, eax: u32, ecx: u32) -> CpuIdResult {
+      let res = unsafe { core::arch::x86_64::__cpuid_count(eax, ecx) };
+      CpuIdResult {
+        eax: res.eax,
+        ebx: res.ebx,
+        ecx: res.ecx,
+        edx: res.edx,
+      }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
     fn cpuid2(&self, eax: u32, ecx: u32) -> CpuIdResult {
       todo!()
     }
@@ -177,7 +339,7 @@ impl Vendor {
     match info.as_str() {
       "GenuineIntel" => Vendor::Intel,
       "AuthenticAMD" => Vendor::Amd,
-      _ => Vendor::Unknown(res.edx, res.ecx, res.edx),
+      _ => Vendor::Unknown(res.ebx, res.edx, res.ecx),
     }
   }
 }
@@ -215,15 +377,722 @@ impl core::fmt::Display for VendorInfo {
   }
 }
 
+/// Advanced power management info, leaf `0x8000_0007`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ApmInfo {
+  /// Value of EDX register.
+  edx: u32,
+}
+
+impl ApmInfo {
+  /// The TSC runs at a constant rate in all ACPI P-, C- and T-states.
+  pub fn has_invariant_tsc(&self) -> bool {
+    self.edx & (1 << 8) != 0
+  }
+}
+
+/// Processor signature and feature flags, leaf `0x1`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct FeatureInfo {
+  /// Value of EAX register, the processor signature.
+  eax: u32,
+  /// Value of EBX register.
+  ebx: u32,
+  ecx: FeatureInfoEcx,
+  edx: FeatureInfoEdx,
+}
+
+impl FeatureInfo {
+  pub fn stepping(&self) -> u8 {
+    (self.eax & 0xF) as u8
+  }
+
+  /// Model, including the extended model for the families 6 and 15.
+  pub fn model(&self) -> u8 {
+    let model = ((self.eax >> 4) & 0xF) as u8;
+    match self.base_family() {
+      0x6 | 0xF => model | (((self.eax >> 16) & 0xF) as u8) << 4,
+      _ => model,
+    }
+  }
+
+  /// Family, including the extended family for the family 15.
+  pub fn family(&self) -> u16 {
+    match self.base_family() {
+      0xF => 0xF + ((self.eax >> 20) & 0xFF) as u16,
+      family => u16::from(family),
+    }
+  }
+
+  fn base_family(&self) -> u8 {
+    ((self.eax >> 8) & 0xF) as u8
+  }
+
+  pub fn brand_index(&self) -> u8 {
+    self.ebx as u8
+  }
+
+  /// Size of the line flushed by `clflush`, in bytes.
+  pub fn cflush_line_size(&self) -> u16 {
+    ((self.ebx >> 8) & 0xFF) as u16 * 8
+  }
+
+  /// Maximum count of logical processor IDs in the package.
+  pub fn max_logical_processor_ids(&self) -> u8 {
+    (self.ebx >> 16) as u8
+  }
+
+  /// Initial APIC ID of the current processor.
+  pub fn initial_local_apic_id(&self) -> u8 {
+    (self.ebx >> 24) as u8
+  }
+
+  pub fn ecx(&self) -> FeatureInfoEcx {
+    self.ecx
+  }
+
+  pub fn edx(&self) -> FeatureInfoEdx {
+    self.edx
+  }
+}
+
+bitflags! {
+  /// Feature flags in ECX of leaf `0x1`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct FeatureInfoEcx: u32 {
+    const SSE3         = 1 << 0;
+    const PCLMULQDQ    = 1 << 1;
+    const DTES64       = 1 << 2;
+    const MONITOR      = 1 << 3;
+    const DS_CPL       = 1 << 4;
+    const VMX          = 1 << 5;
+    const SMX          = 1 << 6;
+    const EIST         = 1 << 7;
+    const TM2          = 1 << 8;
+    const SSSE3        = 1 << 9;
+    const CNXT_ID      = 1 << 10;
+    const SDBG         = 1 << 11;
+    const FMA          = 1 << 12;
+    const CMPXCHG16B   = 1 << 13;
+    const XTPR         = 1 << 14;
+    const PDCM         = 1 << 15;
+    const PCID         = 1 << 17;
+    const DCA          = 1 << 18;
+    const SSE41        = 1 << 19;
+    const SSE42        = 1 << 20;
+    const X2APIC       = 1 << 21;
+    const MOVBE        = 1 << 22;
+    const POPCNT       = 1 << 23;
+    const TSC_DEADLINE = 1 << 24;
+    const AESNI        = 1 << 25;
+    const XSAVE        = 1 << 26;
+    const OSXSAVE      = 1 << 27;
+    const AVX          = 1 << 28;
+    const F16C         = 1 << 29;
+    const RDRAND       = 1 << 30;
+    const HYPERVISOR   = 1 << 31;
+  }
+}
+
+bitflags! {
+  /// Feature flags in EDX of leaf `0x1`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct FeatureInfoEdx: u32 {
+    const FPU   = 1 << 0;
+    const VME   = 1 << 1;
+    const DE    = 1 << 2;
+    const PSE   = 1 << 3;
+    const TSC   = 1 << 4;
+    const MSR   = 1 << 5;
+    const PAE   = 1 << 6;
+    const MCE   = 1 << 7;
+    const CX8   = 1 << 8;
+    const APIC  = 1 << 9;
+    const SEP   = 1 << 11;
+    const MTRR  = 1 << 12;
+    const PGE   = 1 << 13;
+    const MCA   = 1 << 14;
+    const CMOV  = 1 << 15;
+    const PAT   = 1 << 16;
+    const PSE36 = 1 << 17;
+    const PSN   = 1 << 18;
+    const CLFSH = 1 << 19;
+    const DS    = 1 << 21;
+    const ACPI  = 1 << 22;
+    const MMX   = 1 << 23;
+    const FXSR  = 1 << 24;
+    const SSE   = 1 << 25;
+    const SSE2  = 1 << 26;
+    const SS    = 1 << 27;
+    const HTT   = 1 << 28;
+    const TM    = 1 << 29;
+    const PBE   = 1 << 31;
+  }
+}
+
+/// Structured extended feature flags, leaf `0x7` sub-leaf 0.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ExtendedFeatures {
+  ebx: ExtendedFeaturesEbx,
+  ecx: ExtendedFeaturesEcx,
+  edx: ExtendedFeaturesEdx,
+}
+
+impl ExtendedFeatures {
+  pub fn ebx(&self) -> ExtendedFeaturesEbx {
+    self.ebx
+  }
+
+  pub fn ecx(&self) -> ExtendedFeaturesEcx {
+    self.ecx
+  }
+
+  pub fn edx(&self) -> ExtendedFeaturesEdx {
+    self.edx
+  }
+}
+
+bitflags! {
+  /// Feature flags in EBX of leaf `0x7`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ExtendedFeaturesEbx: u32 {
+    const FSGSBASE          = 1 << 0;
+    const TSC_ADJUST        = 1 << 1;
+    const SGX               = 1 << 2;
+    const BMI1              = 1 << 3;
+    const HLE               = 1 << 4;
+    const AVX2              = 1 << 5;
+    const FDP_EXCPTN_ONLY   = 1 << 6;
+    const SMEP              = 1 << 7;
+    const BMI2              = 1 << 8;
+    const ERMS              = 1 << 9;
+    const INVPCID           = 1 << 10;
+    const RTM               = 1 << 11;
+    const RDT_M             = 1 << 12;
+    const DEPRECATE_FPU_CS  = 1 << 13;
+    const MPX               = 1 << 14;
+    const RDT_A             = 1 << 15;
+    const AVX512F           = 1 << 16;
+    const AVX512DQ          = 1 << 17;
+    const RDSEED            = 1 << 18;
+    const ADX               = 1 << 19;
+    const SMAP              = 1 << 20;
+    const AVX512_IFMA       = 1 << 21;
+    const CLFLUSHOPT        = 1 << 23;
+    const CLWB              = 1 << 24;
+    const PROCESSOR_TRACE   = 1 << 25;
+    const AVX512PF          = 1 << 26;
+    const AVX512ER          = 1 << 27;
+    const AVX512CD          = 1 << 28;
+    const SHA               = 1 << 29;
+    const AVX512BW          = 1 << 30;
+    const AVX512VL          = 1 << 31;
+  }
+}
+
+bitflags! {
+  /// Feature flags in ECX of leaf `0x7`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ExtendedFeaturesEcx: u32 {
+    const PREFETCHWT1      = 1 << 0;
+    const AVX512_VBMI      = 1 << 1;
+    const UMIP             = 1 << 2;
+    const PKU              = 1 << 3;
+    const OSPKE            = 1 << 4;
+    const WAITPKG          = 1 << 5;
+    const AVX512_VBMI2     = 1 << 6;
+    const CET_SS           = 1 << 7;
+    const GFNI             = 1 << 8;
+    const VAES             = 1 << 9;
+    const VPCLMULQDQ       = 1 << 10;
+    const AVX512_VNNI      = 1 << 11;
+    const AVX512_BITALG    = 1 << 12;
+    const TME              = 1 << 13;
+    const AVX512_VPOPCNTDQ = 1 << 14;
+    const LA57             = 1 << 16;
+    const RDPID            = 1 << 22;
+    const KL               = 1 << 23;
+    const CLDEMOTE         = 1 << 25;
+    const MOVDIRI          = 1 << 27;
+    const MOVDIR64B        = 1 << 28;
+    const ENQCMD           = 1 << 29;
+    const SGX_LC           = 1 << 30;
+    const PKS              = 1 << 31;
+  }
+}
+
+bitflags! {
+  /// Feature flags in EDX of leaf `0x7`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ExtendedFeaturesEdx: u32 {
+    const AVX512_4VNNIW       = 1 << 2;
+    const AVX512_4FMAPS       = 1 << 3;
+    const FSRM                = 1 << 4;
+    const UINTR               = 1 << 5;
+    const AVX512_VP2INTERSECT = 1 << 8;
+    const MD_CLEAR            = 1 << 10;
+    const SERIALIZE           = 1 << 14;
+    const HYBRID              = 1 << 15;
+    const TSXLDTRK            = 1 << 16;
+    const PCONFIG             = 1 << 18;
+    const CET_IBT             = 1 << 20;
+    const AMX_BF16            = 1 << 22;
+    const AVX512_FP16         = 1 << 23;
+    const AMX_TILE            = 1 << 24;
+    const AMX_INT8            = 1 << 25;
+    const IBRS_IBPB           = 1 << 26;
+    const STIBP               = 1 << 27;
+    const L1D_FLUSH           = 1 << 28;
+    const ARCH_CAPABILITIES   = 1 << 29;
+    const CORE_CAPABILITIES   = 1 << 30;
+    const SSBD                = 1 << 31;
+  }
+}
+
+/// XSAVE state components and sizes, leaf `0xD` sub-leaves 0 and 1.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ExtendedStateInfo {
+  /// Components which may be enabled in XCR0.
+  xcr0_supported: XFeatures,
+  /// Size of the XSAVE area for the components enabled in XCR0.
+  xsave_size:     u32,
+  /// Size of the XSAVE area for all the components supported in XCR0.
+  xsave_max_size: u32,
+  xsave_features: XsaveFeatures,
+  /// Size of the XSAVES area for the components enabled in XCR0 and IA32_XSS.
+  xsaves_size:    u32,
+  /// Components which may be enabled in IA32_XSS.
+  xss_supported:  XFeatures,
+}
+
+impl ExtendedStateInfo {
+  pub fn xcr0_supported(&self) -> XFeatures {
+    self.xcr0_supported
+  }
+
+  pub fn xsave_size(&self) -> u32 {
+    self.xsave_size
+  }
+
+  pub fn xsave_max_size(&self) -> u32 {
+    self.xsave_max_size
+  }
+
+  pub fn xsave_features(&self) -> XsaveFeatures {
+    self.xsave_features
+  }
+
+  pub fn xsaves_size(&self) -> u32 {
+    self.xsaves_size
+  }
+
+  pub fn xss_supported(&self) -> XFeatures {
+    self.xss_supported
+  }
+}
+
+/// An XSAVE state component, leaf `0xD` sub-leaves from 2 on.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ExtendedStateComponent {
+  /// Size in bytes.
+  pub size:       u32,
+  /// Offset in the standard format of the XSAVE area.
+  pub offset:     u32,
+  /// The component is managed through IA32_XSS, otherwise XCR0.
+  pub supervisor: bool,
+  /// The component is aligned to 64 bytes in the compacted format.
+  pub aligned:    bool,
+}
+
+bitflags! {
+  /// State components of XCR0 and IA32_XSS.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct XFeatures: u64 {
+    const X87          = 1 << 0;
+    const SSE          = 1 << 1;
+    const AVX          = 1 << 2;
+    const BNDREGS      = 1 << 3;
+    const BNDCSR       = 1 << 4;
+    const OPMASK       = 1 << 5;
+    const ZMM_HI256    = 1 << 6;
+    const HI16_ZMM     = 1 << 7;
+    const PT           = 1 << 8;
+    const PKRU         = 1 << 9;
+    const PASID        = 1 << 10;
+    const CET_U        = 1 << 11;
+    const CET_S        = 1 << 12;
+    const HDC          = 1 << 13;
+    const UINTR        = 1 << 14;
+    const LBR          = 1 << 15;
+    const HWP          = 1 << 16;
+    const AMX_TILECFG  = 1 << 17;
+    const AMX_TILEDATA = 1 << 18;
+  }
+}
+
+bitflags! {
+  /// XSAVE instructions, in EAX of leaf `0xD` sub-leaf 1.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct XsaveFeatures: u32 {
+    const XSAVEOPT = 1 << 0;
+    const XSAVEC   = 1 << 1;
+    const XGETBV1  = 1 << 2;
+    const XSAVES   = 1 << 3;
+    const XFD      = 1 << 4;
+  }
+}
+
+/// Extended processor feature flags, leaf `0x8000_0001`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ExtendedProcessorFeatures {
+  ecx: ExtendedProcessorFeaturesEcx,
+  edx: ExtendedProcessorFeaturesEdx,
+}
+
+impl ExtendedProcessorFeatures {
+  pub fn ecx(&self) -> ExtendedProcessorFeaturesEcx {
+    self.ecx
+  }
+
+  pub fn edx(&self) -> ExtendedProcessorFeaturesEdx {
+    self.edx
+  }
+}
+
+bitflags! {
+  /// Feature flags in ECX of leaf `0x8000_0001`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ExtendedProcessorFeaturesEcx: u32 {
+    const LAHF_SAHF           = 1 << 0;
+    const CMP_LEGACY          = 1 << 1;
+    const SVM                 = 1 << 2;
+    const EXT_APIC_SPACE      = 1 << 3;
+    const ALT_MOV_CR8         = 1 << 4;
+    const LZCNT               = 1 << 5;
+    const SSE4A               = 1 << 6;
+    const MISALIGNED_SSE      = 1 << 7;
+    const PREFETCHW           = 1 << 8;
+    const OSVW                = 1 << 9;
+    const IBS                 = 1 << 10;
+    const XOP                 = 1 << 11;
+    const SKINIT              = 1 << 12;
+    const WDT                 = 1 << 13;
+    const LWP                 = 1 << 15;
+    const FMA4                = 1 << 16;
+    const TCE                 = 1 << 17;
+    const TBM                 = 1 << 21;
+    const TOPOLOGY_EXTENSIONS = 1 << 22;
+    const PERFCTR_CORE        = 1 << 23;
+    const PERFCTR_NB          = 1 << 24;
+    const DATA_BREAKPOINT     = 1 << 26;
+    const PERF_TSC            = 1 << 27;
+    const PERFCTR_LLC         = 1 << 28;
+    const MONITORX            = 1 << 29;
+  }
+}
+
+bitflags! {
+  /// Feature flags in EDX of leaf `0x8000_0001`.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ExtendedProcessorFeaturesEdx: u32 {
+    const SYSCALL       = 1 << 11;
+    const NX            = 1 << 20;
+    const MMX_EXT       = 1 << 22;
+    const FXSR_OPT      = 1 << 25;
+    const PAGE_1GB      = 1 << 26;
+    const RDTSCP        = 1 << 27;
+    const LONG_MODE     = 1 << 29;
+    const AMD_3DNOW_EXT = 1 << 30;
+    const AMD_3DNOW     = 1 << 31;
+  }
+}
+
+/// Processor brand string, leaves `0x8000_0002` to `0x8000_0004`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct BrandString {
+  bytes: [u8; 48],
+}
+
+impl BrandString {
+  /// Return the brand string, without the NUL padding and the surrounding spaces.
+  pub fn as_str(&self) -> &str {
+    let len = self.bytes.iter().position(|&b| b == 0).unwrap_or(self.bytes.len());
+    core::str::from_utf8(&self.bytes[..len]).unwrap_or("").trim()
+  }
+}
+
+impl core::fmt::Display for BrandString {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// Type of a cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheType {
+  Data,
+  Instruction,
+  Unified,
+  Unknown(u8),
+}
+
+/// Parameters of a cache, leaf `0x4` or `0x8000_001D`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct CacheParameter {
+  eax: u32,
+  ebx: u32,
+  ecx: u32,
+  edx: u32,
+}
+
+impl CacheParameter {
+  pub fn cache_type(&self) -> CacheType {
+    match self.eax & 0x1F {
+      1 => CacheType::Data,
+      2 => CacheType::Instruction,
+      3 => CacheType::Unified,
+      other => CacheType::Unknown(other as u8),
+    }
+  }
+
+  pub fn level(&self) -> u8 {
+    ((self.eax >> 5) & 0x7) as u8
+  }
+
+  pub fn is_fully_associative(&self) -> bool {
+    self.eax & (1 << 9) != 0
+  }
+
+  /// Maximum count of logical processors sharing the cache.
+  pub fn max_cores_sharing(&self) -> u16 {
+    ((self.eax >> 14) & 0xFFF) as u16 + 1
+  }
+
+  pub fn coherency_line_size(&self) -> u32 {
+    (self.ebx & 0xFFF) + 1
+  }
+
+  pub fn physical_line_partitions(&self) -> u32 {
+    ((self.ebx >> 12) & 0x3FF) + 1
+  }
+
+  pub fn associativity(&self) -> u32 {
+    (self.ebx >> 22) + 1
+  }
+
+  pub fn sets(&self) -> u32 {
+    self.ecx + 1
+  }
+
+  /// The cache is inclusive of the lower levels.
+  pub fn is_inclusive(&self) -> bool {
+    self.edx & (1 << 1) != 0
+  }
+
+  /// Size of the cache in bytes.
+  pub fn size(&self) -> u64 {
+    u64::from(self.coherency_line_size())
+      * u64::from(self.physical_line_partitions())
+      * u64::from(self.associativity())
+      * u64::from(self.sets())
+  }
+}
+
+/// Iterator over the sub-leaves of the cache parameters, until the null cache type.
+pub struct CacheParametersIter<'a, R: CpuIdReader> {
+  read:  &'a R,
+  leaf:  u32,
+  index: u32,
+}
+
+impl<R: CpuIdReader> Iterator for CacheParametersIter<'_, R> {
+  type Item = CacheParameter;
+
+  fn next(&mut self) -> Option<CacheParameter> {
+    let res = self.read.cpuid2(self.leaf, self.index);
+    if res.eax & 0x1F == 0 {
+      return None;
+    }
+    self.index += 1;
+    Some(CacheParameter {
+      eax: res.eax,
+      ebx: res.ebx,
+      ecx: res.ecx,
+      edx: res.edx,
+    })
+  }
+}
+
+/// Type of a topology level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TopologyType {
+  Smt,
+  Core,
+  Module,
+  Tile,
+  Die,
+  Unknown(u8),
+}
+
+/// A topology level of the current processor, leaf `0xB` or `0x1F`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct TopologyLevel {
+  eax: u32,
+  ebx: u32,
+  ecx: u32,
+  edx: u32,
+}
+
+impl TopologyLevel {
+  /// Shift of the x2APIC ID to get the ID of the next level.
+  pub fn shift(&self) -> u8 {
+    (self.eax & 0x1F) as u8
+  }
+
+  /// Count of logical processors at this level.
+  pub fn processors(&self) -> u16 {
+    self.ebx as u16
+  }
+
+  pub fn level_number(&self) -> u8 {
+    self.ecx as u8
+  }
+
+  pub fn level_type(&self) -> TopologyType {
+    match (self.ecx >> 8) as u8 {
+      1 => TopologyType::Smt,
+      2 => TopologyType::Core,
+      3 => TopologyType::Module,
+      4 => TopologyType::Tile,
+      5 => TopologyType::Die,
+      other => TopologyType::Unknown(other),
+    }
+  }
+
+  /// x2APIC ID of the current processor.
+  pub fn x2apic_id(&self) -> u32 {
+    self.edx
+  }
+}
+
+/// Iterator over the sub-leaves of the topology, until the invalid level type.
+pub struct TopologyIter<'a, R: CpuIdReader> {
+  read:  &'a R,
+  leaf:  u32,
+  index: u32,
+}
+
+impl<R: CpuIdReader> Iterator for TopologyIter<'_, R> {
+  type Item = TopologyLevel;
+
+  fn next(&mut self) -> Option<TopologyLevel> {
+    let res = self.read.cpuid2(self.leaf, self.index);
+    if (res.ecx >> 8) & 0xFF == 0 {
+      return None;
+    }
+    self.index += 1;
+    Some(TopologyLevel {
+      eax: res.eax,
+      ebx: res.ebx,
+      ecx: res.ecx,
+      edx: res.edx,
+    })
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
-  #[test]
+  const BRAND: &[u8; 48] =
+    b"  Test CPU @ 1.00GHz\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+  fn canned_cpuid(eax: u32, ecx: u32) -> CpuIdResult {
+    let (eax, ebx, ecx, edx) = match (eax, ecx) {
+      // "GenuineIntel"
+      (0x0, _) => (0xB, 0x756E_6547, 0x6C65_746E, 0x4965_6E69),
+      // Family 6, model 0x9E, stepping 10.
+      (0x1, _) => (
+        0x0009_06EA,
+        0x0310_0800,
+        (1 << 21) | (1 << 24),
+        (1 << 9) | (1 << 25),
+      ),
+      // L1 data cache, 64 sets of 8 ways of 64-byte lines, shared by 2 logical processors.
+      (0x4, 0) => (0x0000_4121, 0x01C0_003F, 63, 0),
+      (0x4, _) => (0, 0, 0, 0),
+      (0x7, 0) => (0, (1 << 7) | (1 << 20), 1 << 2, 0),
+      (0xB, 0) => (1, 2, 0x0100, 3),
+      (0xB, 1) => (4, 8, 0x0201, 3),
+      (0xB, _) => (0, 0, 0, 3),
+      (0x8000_0000, _) => (0x8000_0004, 0, 0, 0),
+      (0x8000_0001, _) => (0, 0, 0, (1 << 20) | (1 << 29)),
+      (leaf @ 0x8000_0002..=0x8000_0004, _) => {
+        let offset = (leaf - 0x8000_0002) as usize * 16;
+        let register = |i: usize| {
+          u32::from_le_bytes(BRAND[offset + i * 4..offset + i * 4 + 4].try_into().unwrap())
+        };
+        (register(0), register(1), register(2), register(3))
+      }
+      _ => (0, 0, 0, 0),
+    };
+    CpuIdResult { eax, ebx, ecx, edx }
+  }
+
+  #[test_case]
   fn test_cpu_id() {
     let cpu_id = CpuId::new();
     if let Some(v) = cpu_id.get_vendor_info() {
-      assert!(v.as)
+      assert!(!v.as_str().is_empty());
     }
   }
+
+  #[test_case]
+  fn test_decode_canned_cpu_id() {
+    let cpu_id = CpuId::with_cpuid_reader(canned_cpuid);
+    assert_eq!(cpu_id.get_vendor_info().unwrap().as_str(), "GenuineIntel");
+
+    let info = cpu_id.get_feature_info().unwrap();
+    assert_eq!(
+      (info.family(), info.model(), info.stepping()),
+      (6, 0x9E, 10)
+    );
+    assert_eq!(info.cflush_line_size(), 64);
+    assert_eq!(info.initial_local_apic_id(), 3);
+    assert!(info.ecx().contains(FeatureInfoEcx::X2APIC | FeatureInfoEcx::TSC_DEADLINE));
+    assert!(info.edx().contains(FeatureInfoEdx::APIC | FeatureInfoEdx::SSE));
+    assert!(!info.edx().contains(FeatureInfoEdx::FPU));
+
+    let extended = cpu_id.get_extended_feature_info().unwrap();
+    assert!(extended.ebx().contains(ExtendedFeaturesEbx::SMEP | ExtendedFeaturesEbx::SMAP));
+    assert!(extended.ecx().contains(ExtendedFeaturesEcx::UMIP));
+
+    let processor = cpu_id.get_extended_processor_feature_info().unwrap();
+    assert!(processor.edx().contains(ExtendedProcessorFeaturesEdx::NX));
+    assert_eq!(
+      cpu_id.get_processor_brand_string().unwrap().as_str(),
+      "Test CPU @ 1.00GHz"
+    );
+
+    let mut caches = cpu_id.get_cache_parameters().unwrap();
+    let l1 = caches.next().unwrap();
+    assert_eq!((l1.cache_type(), l1.level()), (CacheType::Data, 1));
+    assert_eq!((l1.max_cores_sharing(), l1.size()), (2, 32 * 1024));
+    assert!(caches.next().is_none());
+
+    let levels = cpu_id.get_extended_topology_info().unwrap();
+    let types = levels.map(|level| (level.level_type(), level.shift()));
+    assert!(types.eq([(TopologyType::Smt, 1), (TopologyType::Core, 4)]));
+
+    assert!(cpu_id.get_extended_state_info().is_none());
+  }
 }
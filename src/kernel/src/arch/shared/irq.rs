@@ -0,0 +1,227 @@
+//! # IRQ Handler Registration
+//!
+//! Drivers attach handlers to IRQs with `register_irq`. An IRQ may be shared by several devices,
+//! so its handlers are chained: all of them are called on each interrupt, and each one tells if
+//! its device raised the interrupt.
+//!
+//! The architecture dispatches the IRQs to `handle_irq`, and acknowledges the interrupt
+//! controller afterwards. An IRQ is unmasked when its first handler is registered, and masked
+//! again when its last handler is unregistered.
+
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use spin::Mutex;
+
+use crate::arch::interrupt;
+use crate::arch::interrupt::NR_IRQS;
+
+/// IRQ number, i.e. the input of the interrupt controller, not the vector.
+pub type Irq = u32;
+
+/// Handler of an IRQ, called in interrupt context with interrupts disabled.
+pub type IrqHandler = fn(irq: Irq) -> IrqReturn;
+
+/// Result of an IRQ handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqReturn {
+  /// The interrupt was raised by the device of the handler, and has been handled.
+  Handled,
+  /// The interrupt was not raised by the device of the handler.
+  None,
+}
+
+/// Error occurred when registering or unregistering an IRQ handler.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IrqError {
+  /// The IRQ is beyond the interrupt controller.
+  InvalidIrq(Irq),
+  /// A handler with the same name is already registered to the IRQ.
+  AlreadyRegistered(&'static str),
+  /// No handler with the name is registered to the IRQ.
+  NotRegistered(&'static str),
+  /// The interrupt controller of the architecture is not supported.
+  Unsupported,
+}
+
+/// A handler registered to an IRQ.
+#[derive(Clone, Copy)]
+struct IrqAction {
+  handler: IrqHandler,
+  name:    &'static str,
+}
+
+/// Handlers and counters of an IRQ.
+struct IrqDescriptor {
+  actions:   Mutex<Vec<IrqAction>>,
+  /// Count of interrupts.
+  count:     AtomicU64,
+  /// Count of interrupts which no handler claimed.
+  unhandled: AtomicU64,
+}
+
+impl IrqDescriptor {
+  const fn new() -> Self {
+    Self {
+      actions:   Mutex::new(Vec::new()),
+      count:     AtomicU64::new(0),
+      unhandled: AtomicU64::new(0),
+    }
+  }
+}
+
+static IRQ_DESCRIPTORS: [IrqDescriptor; NR_IRQS] = [const { IrqDescriptor::new() }; NR_IRQS];
+
+/// Run the closure with interrupts disabled, and restore the interrupt flag afterwards.
+///
+/// The IRQ handler lists are locked by `handle_irq`, so they must never be locked with interrupts
+/// enabled.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+  F: FnOnce() -> R,
+{
+  let flags = interrupt::save_and_disable();
+  let result = f();
+  unsafe { interrupt::restore(flags) };
+  result
+}
+
+#[inline]
+fn descriptor(irq: Irq) -> Result<&'static IrqDescriptor, IrqError> {
+  IRQ_DESCRIPTORS.get(irq as usize).ok_or(IrqError::InvalidIrq(irq))
+}
+
+/// Attach the handler to the IRQ, after the handlers already registered.
+pub fn register_irq(irq: Irq, handler: IrqHandler, name: &'static str) -> Result<(), IrqError> {
+  let descriptor = descriptor(irq)?;
+  without_interrupts(|| {
+    let mut actions = descriptor.actions.lock();
+    if actions.iter().any(|action| action.name == name) {
+      return Err(IrqError::AlreadyRegistered(name));
+    }
+    actions.push(IrqAction { handler, name });
+    if actions.len() == 1 {
+      if let Err(err) = unsafe { interrupt::unmask_irq(irq) } {
+        actions.pop();
+        return Err(err);
+      }
+    }
+    Ok(())
+  })
+}
+
+/// Detach the handler registered with the name from the IRQ.
+pub fn unregister_irq(irq: Irq, name: &'static str) -> Result<(), IrqError> {
+  let descriptor = descriptor(irq)?;
+  without_interrupts(|| {
+    let mut actions = descriptor.actions.lock();
+    let index = actions
+      .iter()
+      .position(|action| action.name == name)
+      .ok_or(IrqError::NotRegistered(name))?;
+    actions.remove(index);
+    if actions.is_empty() {
+      unsafe { interrupt::mask_irq(irq) }?;
+    }
+    Ok(())
+  })
+}
+
+/// Call the handlers of the IRQ. Called by the architecture in interrupt context, before the
+/// interrupt is acknowledged.
+pub fn handle_irq(irq: Irq) {
+  let Ok(descriptor) = descriptor(irq) else {
+    return;
+  };
+  descriptor.count.fetch_add(1, Ordering::Relaxed);
+
+  let actions = descriptor.actions.lock();
+  let mut handled = false;
+  for action in actions.iter() {
+    handled |= (action.handler)(irq) == IrqReturn::Handled;
+  }
+  if !handled {
+    descriptor.unhandled.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// Statistics and handler names of an IRQ.
+#[derive(Debug)]
+pub struct IrqInfo {
+  pub irq:       Irq,
+  pub count:     u64,
+  pub unhandled: u64,
+  pub handlers:  Vec<&'static str>,
+}
+
+impl Display for IrqInfo {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "{:>4}: {:>10} {:>10}",
+      self.irq, self.count, self.unhandled
+    )?;
+    for (i, name) in self.handlers.iter().enumerate() {
+      let separator = if i == 0 { " " } else { ", " };
+      write!(f, "{}{}", separator, name)?;
+    }
+    Ok(())
+  }
+}
+
+/// List the IRQs which have a handler or have been raised.
+pub fn irqs() -> Vec<IrqInfo> {
+  (0..NR_IRQS as Irq)
+    .filter_map(|irq| {
+      let descriptor = &IRQ_DESCRIPTORS[irq as usize];
+      let handlers: Vec<_> =
+        without_interrupts(|| descriptor.actions.lock().iter().map(|action| action.name).collect());
+      let count = descriptor.count.load(Ordering::Relaxed);
+      if handlers.is_empty() && count == 0 {
+        return None;
+      }
+      Some(IrqInfo {
+        irq,
+        count,
+        unhandled: descriptor.unhandled.load(Ordering::Relaxed),
+        handlers,
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_handler(_irq: Irq) -> IrqReturn {
+    IrqReturn::None
+  }
+
+  #[test_case]
+  fn test_register_irq() {
+    assert_eq!(
+      register_irq(NR_IRQS as Irq, test_handler, "test"),
+      Err(IrqError::InvalidIrq(NR_IRQS as Irq))
+    );
+    assert_eq!(register_irq(15, test_handler, "test"), Ok(()));
+    assert_eq!(
+      register_irq(15, test_handler, "test"),
+      Err(IrqError::AlreadyRegistered("test"))
+    );
+    assert_eq!(register_irq(15, test_handler, "test shared"), Ok(()));
+    assert_eq!(
+      irqs().iter().find(|info| info.irq == 15).unwrap().handlers,
+      ["test", "test shared"]
+    );
+    assert_eq!(unregister_irq(15, "test"), Ok(()));
+    assert_eq!(
+      unregister_irq(15, "test"),
+      Err(IrqError::NotRegistered("test"))
+    );
+    assert_eq!(unregister_irq(15, "test shared"), Ok(()));
+  }
+}
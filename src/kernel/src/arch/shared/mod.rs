@@ -1,2 +1,3 @@
 pub mod cpuid;
+pub mod irq;
 pub mod time;
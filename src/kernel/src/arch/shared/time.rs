@@ -1,4 +1,122 @@
-/// TODO: Use hept or else.
-pub fn counter() -> u128 {
-  0
+//! # System Tick and Clock Sources
+//!
+//! A periodic timer interrupt increments the jiffies counter `HZ` times per second. When the tick
+//! is stopped while idle, the jiffies catch up with the clock source instead.
+//!
+//! The monotonic counter is read from the best clock source registered, i.e. the one with the
+//! highest rating. The jiffies are the fallback clock source, with the resolution of a tick.
+
+use core::ptr;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::irq::without_interrupts;
+use crate::support::NanoSecond;
+
+/// Frequency of the system tick.
+pub const HZ: u64 = 1000;
+
+/// Count of ticks since the tick started.
+static JIFFIES: AtomicU64 = AtomicU64::new(0);
+
+/// Period of the tick in picoseconds, the hardware can rarely hit `1 / HZ` exactly.
+static TICK_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// A monotonic clock of nanosecond resolution.
+pub struct ClockSource {
+  pub name:   &'static str,
+  /// The clock source with the highest rating is used.
+  pub rating: u32,
+  /// Read the clock in nanoseconds, from an arbitrary origin.
+  pub read:   fn() -> NanoSecond,
+}
+
+/// Fallback clock source, which only advances with the tick.
+pub static JIFFIES_CLOCK_SOURCE: ClockSource = ClockSource {
+  name:   "jiffies",
+  rating: 1,
+  read:   jiffies_counter,
+};
+
+/// Clock source in use, the jiffies if null.
+static CLOCK_SOURCE: AtomicPtr<ClockSource> = AtomicPtr::new(ptr::null_mut());
+
+/// Offset between the clock source in use and the counter, which keeps the counter monotonic
+/// when the clock source changes.
+static CLOCK_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Return the count of ticks since the tick started.
+#[inline]
+pub fn jiffies() -> u64 {
+  JIFFIES.load(Ordering::Relaxed)
+}
+
+/// Set the period of the tick in picoseconds, before the tick starts.
+pub fn set_tick_period(picoseconds: u64) {
+  TICK_PERIOD.store(picoseconds, Ordering::Relaxed);
+}
+
+/// Return the period of the tick in picoseconds, or `1 / HZ` before it is set.
+#[inline]
+pub fn tick_period() -> u64 {
+  match TICK_PERIOD.load(Ordering::Relaxed) {
+    0 => 1_000_000_000_000 / HZ,
+    period => period,
+  }
+}
+
+/// Account a tick, called by the interrupt handler of the tick timer.
+#[inline]
+pub fn tick() {
+  JIFFIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Catch up the jiffies with the counter, after the tick was stopped. The jiffies never go back.
+pub fn update_jiffies() {
+  let now = counter() * 1000 / NanoSecond::from(tick_period());
+  JIFFIES.fetch_max(now as u64, Ordering::Relaxed);
+}
+
+/// Return the counter time, in nanoseconds, at which the jiffy starts.
+#[inline]
+pub fn jiffy_to_counter(jiffy: u64) -> NanoSecond {
+  NanoSecond::from(jiffy) * NanoSecond::from(tick_period()) / 1000
+}
+
+fn jiffies_counter() -> NanoSecond {
+  jiffy_to_counter(jiffies())
+}
+
+/// Return the clock source in use.
+#[inline]
+pub fn clock_source() -> &'static ClockSource {
+  let source = CLOCK_SOURCE.load(Ordering::Acquire);
+  if source.is_null() {
+    &JIFFIES_CLOCK_SOURCE
+  } else {
+    unsafe { &*source }
+  }
+}
+
+/// Switch to the clock source if its rating is higher than the one in use, and return true if
+/// switched. The counter goes on from its current value.
+pub fn register_clock_source(source: &'static ClockSource) -> bool {
+  without_interrupts(|| {
+    if source.rating <= clock_source().rating {
+      return false;
+    }
+    let now = counter() as i128;
+    CLOCK_OFFSET.store((now - (source.read)() as i128) as i64, Ordering::Relaxed);
+    CLOCK_SOURCE.store(source as *const _ as *mut _, Ordering::Release);
+    true
+  })
+}
+
+/// Return the nanoseconds elapsed since the tick started.
+pub fn counter() -> NanoSecond {
+  let source = clock_source();
+  let offset = CLOCK_OFFSET.load(Ordering::Relaxed);
+  ((source.read)() as i128 + offset as i128) as NanoSecond
 }
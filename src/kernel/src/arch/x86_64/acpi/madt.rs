@@ -0,0 +1,227 @@
+//! # MADT - Multiple APIC Description Table
+//!
+//! Describes the interrupt controllers: the local APIC of each processor, the I/O APICs, and how
+//! the legacy ISA IRQs are wired to the global system interrupts (GSI) of the I/O APICs.
+
+use alloc::vec::Vec;
+
+use crate::arch::x86_64::acpi::find_table;
+use crate::arch::x86_64::acpi::AcpiError;
+use crate::arch::PhysicalAddress;
+
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+/// Offset of the local APIC address, right after the table header.
+const LOCAL_APIC_ADDRESS_OFFSET: usize = 36;
+/// Offset of the flags.
+const FLAGS_OFFSET: usize = 40;
+/// Offset of the first interrupt controller structure.
+const ENTRIES_OFFSET: usize = 44;
+
+/// The system also has a PC-AT-compatible dual 8259 setup.
+const PCAT_COMPAT: u32 = 1 << 0;
+
+/// The processor is usable.
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+/// The processor can be enabled at runtime.
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// Processor UID matching all the processors in a local APIC NMI structure.
+const ALL_PROCESSORS: u32 = 0xFFFF_FFFF;
+
+const ENTRY_LOCAL_APIC: u8 = 0x0;
+const ENTRY_IO_APIC: u8 = 0x1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 0x2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 0x4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 0x5;
+const ENTRY_LOCAL_X2APIC: u8 = 0x9;
+const ENTRY_LOCAL_X2APIC_NMI: u8 = 0xA;
+
+/// Polarity of an interrupt input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+  ActiveHigh,
+  ActiveLow,
+}
+
+/// Trigger mode of an interrupt input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+  Edge,
+  Level,
+}
+
+/// Decode the MPS INTI flags, or `None` for the default of the bus.
+fn decode_inti_flags(flags: u16) -> (Option<Polarity>, Option<TriggerMode>) {
+  let polarity = match flags & 0b11 {
+    0b01 => Some(Polarity::ActiveHigh),
+    0b11 => Some(Polarity::ActiveLow),
+    _ => None,
+  };
+  let trigger = match (flags >> 2) & 0b11 {
+    0b01 => Some(TriggerMode::Edge),
+    0b11 => Some(TriggerMode::Level),
+    _ => None,
+  };
+  (polarity, trigger)
+}
+
+/// A processor and its local APIC.
+#[derive(Clone, Copy, Debug)]
+pub struct Processor {
+  /// ACPI processor UID.
+  pub uid:     u32,
+  pub apic_id: u32,
+  /// The processor is usable right now.
+  pub enabled: bool,
+}
+
+/// An I/O APIC.
+#[derive(Clone, Copy, Debug)]
+pub struct IoApicEntry {
+  pub id:       u8,
+  pub address:  PhysicalAddress,
+  /// First GSI handled by this I/O APIC.
+  pub gsi_base: u32,
+}
+
+/// An ISA IRQ which is not identity-mapped to the GSI, or with non-default polarity or trigger.
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptSourceOverride {
+  pub irq:      u8,
+  pub gsi:      u32,
+  pub polarity: Polarity,
+  pub trigger:  TriggerMode,
+}
+
+/// A local APIC LINT input wired to the NMI.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApicNmi {
+  /// ACPI processor UID, or `None` for all the processors.
+  pub uid:      Option<u32>,
+  /// LINT0 or LINT1.
+  pub lint:     u8,
+  pub polarity: Polarity,
+  pub trigger:  TriggerMode,
+}
+
+/// Interrupt controllers described by the MADT.
+#[derive(Debug)]
+pub struct Madt {
+  pub local_apic_address: PhysicalAddress,
+  /// The 8259 PICs are present, and must be disabled when the APICs are used.
+  pub pcat_compatible:    bool,
+  pub processors:         Vec<Processor>,
+  pub io_apics:           Vec<IoApicEntry>,
+  pub overrides:          Vec<InterruptSourceOverride>,
+  pub nmis:               Vec<LocalApicNmi>,
+}
+
+impl Madt {
+  /// Find and parse the MADT.
+  pub fn parse() -> Result<Self, AcpiError> {
+    let table = find_table(MADT_SIGNATURE)?;
+    let read_u8 = |offset| unsafe { table.read::<u8>(offset) }.unwrap_or(0);
+    let read_u16 = |offset| unsafe { table.read::<u16>(offset) }.unwrap_or(0);
+    let read_u32 = |offset| unsafe { table.read::<u32>(offset) }.unwrap_or(0);
+    let read_u64 = |offset| unsafe { table.read::<u64>(offset) }.unwrap_or(0);
+
+    let mut madt = Madt {
+      local_apic_address: PhysicalAddress::new(u64::from(read_u32(LOCAL_APIC_ADDRESS_OFFSET))),
+      pcat_compatible:    read_u32(FLAGS_OFFSET) & PCAT_COMPAT != 0,
+      processors:         Vec::new(),
+      io_apics:           Vec::new(),
+      overrides:          Vec::new(),
+      nmis:               Vec::new(),
+    };
+
+    let length = table.bytes().len();
+    let mut offset = ENTRIES_OFFSET;
+    while offset + 2 <= length {
+      let kind = read_u8(offset);
+      let entry_length = read_u8(offset + 1) as usize;
+      if entry_length < 2 || offset + entry_length > length {
+        break;
+      }
+
+      match kind {
+        ENTRY_LOCAL_APIC => {
+          let flags = read_u32(offset + 4);
+          if flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0 {
+            madt.processors.push(Processor {
+              uid:     u32::from(read_u8(offset + 2)),
+              apic_id: u32::from(read_u8(offset + 3)),
+              enabled: flags & PROCESSOR_ENABLED != 0,
+            });
+          }
+        }
+        ENTRY_LOCAL_X2APIC => {
+          let flags = read_u32(offset + 8);
+          if flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0 {
+            madt.processors.push(Processor {
+              uid:     read_u32(offset + 12),
+              apic_id: read_u32(offset + 4),
+              enabled: flags & PROCESSOR_ENABLED != 0,
+            });
+          }
+        }
+        ENTRY_IO_APIC => madt.io_apics.push(IoApicEntry {
+          id:       read_u8(offset + 2),
+          address:  PhysicalAddress::new(u64::from(read_u32(offset + 4))),
+          gsi_base: read_u32(offset + 8),
+        }),
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+          // ISA IRQs default to active high and edge-triggered.
+          let (polarity, trigger) = decode_inti_flags(read_u16(offset + 8));
+          madt.overrides.push(InterruptSourceOverride {
+            irq:      read_u8(offset + 3),
+            gsi:      read_u32(offset + 4),
+            polarity: polarity.unwrap_or(Polarity::ActiveHigh),
+            trigger:  trigger.unwrap_or(TriggerMode::Edge),
+          });
+        }
+        ENTRY_LOCAL_APIC_NMI | ENTRY_LOCAL_X2APIC_NMI => {
+          let (uid, flags, lint) = if kind == ENTRY_LOCAL_APIC_NMI {
+            let uid = read_u8(offset + 2);
+            let uid = if uid == 0xFF {
+              ALL_PROCESSORS
+            } else {
+              u32::from(uid)
+            };
+            (uid, read_u16(offset + 3), read_u8(offset + 5))
+          } else {
+            (
+              read_u32(offset + 4),
+              read_u16(offset + 2),
+              read_u8(offset + 8),
+            )
+          };
+          let (polarity, trigger) = decode_inti_flags(flags);
+          madt.nmis.push(LocalApicNmi {
+            uid: (uid != ALL_PROCESSORS).then_some(uid),
+            lint,
+            polarity: polarity.unwrap_or(Polarity::ActiveHigh),
+            trigger: trigger.unwrap_or(TriggerMode::Edge),
+          });
+        }
+        ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+          madt.local_apic_address = PhysicalAddress::new(read_u64(offset + 4));
+        }
+        _ => {}
+      }
+
+      offset += entry_length;
+    }
+
+    Ok(madt)
+  }
+
+  /// Return the ACPI processor UID of the local APIC.
+  pub fn processor_uid(&self, apic_id: u32) -> Option<u32> {
+    self
+      .processors
+      .iter()
+      .find(|processor| processor.apic_id == apic_id)
+      .map(|processor| processor.uid)
+  }
+}
@@ -0,0 +1,239 @@
+//! # ACPI
+//!
+//! Minimal parser of the static ACPI tables. The RSDP is searched in the BIOS memory areas, and
+//! the other tables are found through the RSDT, or the XSDT since ACPI 2.0.
+//!
+//! The tables live in memory reserved by the firmware, and are read through the mapping of the
+//! whole physical memory.
+
+use core::mem::size_of;
+use core::ptr;
+
+use spin::Mutex;
+
+use crate::arch::PhysicalAddress;
+use crate::mem::physical_to_virtual;
+use crate::println;
+
+pub mod madt;
+
+/// Signature of the RSDP.
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Size of the RSDP checksummed by the ACPI 1.0 checksum.
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Physical address of the segment of the EBDA, the extended BIOS data area.
+const EBDA_SEGMENT_POINTER: u64 = 0x40E;
+/// Size of the EBDA area which may contain the RSDP.
+const EBDA_SEARCH_SIZE: u64 = 0x400;
+/// BIOS read-only memory which may contain the RSDP.
+const BIOS_AREA_START: u64 = 0x000E_0000;
+const BIOS_AREA_END: u64 = 0x0010_0000;
+
+/// Root table of the static ACPI tables, found by `init_acpi`.
+static ROOT_TABLE: Mutex<Option<RootTable>> = Mutex::new(None);
+
+/// Error occurred when parsing the ACPI tables.
+#[derive(Debug)]
+pub enum AcpiError {
+  /// No RSDP is found in the BIOS memory areas.
+  RsdpNotFound,
+  /// The checksum of the table with the signature is wrong.
+  InvalidChecksum([u8; 4]),
+  /// The table with the signature is not found.
+  TableNotFound([u8; 4]),
+}
+
+/// RSDP - Root System Description Pointer.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct Rsdp {
+  signature:         [u8; 8],
+  checksum:          u8,
+  oem_id:            [u8; 6],
+  revision:          u8,
+  rsdt_address:      u32,
+  // Since ACPI 2.0.
+  length:            u32,
+  xsdt_address:      u64,
+  extended_checksum: u8,
+  reserved:          [u8; 3],
+}
+
+/// Header shared by all the system description tables.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+  pub signature:        [u8; 4],
+  pub length:           u32,
+  pub revision:         u8,
+  pub checksum:         u8,
+  pub oem_id:           [u8; 6],
+  pub oem_table_id:     [u8; 8],
+  pub oem_revision:     u32,
+  pub creator_id:       u32,
+  pub creator_revision: u32,
+}
+
+/// The RSDT, whose entries are 32-bit physical addresses, or the XSDT, whose entries are 64-bit.
+#[derive(Clone, Copy, Debug)]
+struct RootTable {
+  address:    PhysicalAddress,
+  entry_size: usize,
+}
+
+/// A system description table, mapped in the kernel address space.
+#[derive(Clone, Copy)]
+pub struct Sdt {
+  address: PhysicalAddress,
+  header:  SdtHeader,
+}
+
+impl Sdt {
+  /// ## Safety
+  /// The physical address must point to a system description table.
+  unsafe fn new(address: PhysicalAddress) -> Self {
+    let header = ptr::read_unaligned(physical_to_virtual(address).as_ptr::<SdtHeader>());
+    Self { address, header }
+  }
+
+  #[inline]
+  pub fn header(&self) -> &SdtHeader {
+    &self.header
+  }
+
+  #[inline]
+  pub fn signature(&self) -> [u8; 4] {
+    self.header.signature
+  }
+
+  /// Return the whole table, including the header.
+  #[inline]
+  pub fn bytes(&self) -> &'static [u8] {
+    let start = physical_to_virtual(self.address).as_ptr::<u8>();
+    unsafe { core::slice::from_raw_parts(start, self.header.length as usize) }
+  }
+
+  /// Return the table after the header.
+  #[inline]
+  pub fn data(&self) -> &'static [u8] {
+    &self.bytes()[size_of::<SdtHeader>()..]
+  }
+
+  /// Read a value of the table at the byte offset from the start of the header.
+  ///
+  /// ## Safety
+  /// `T` must be valid for any bit pattern.
+  #[inline]
+  pub unsafe fn read<T: Copy>(&self, offset: usize) -> Option<T> {
+    let bytes = self.bytes().get(offset..offset + size_of::<T>())?;
+    Some(ptr::read_unaligned(bytes.as_ptr() as *const T))
+  }
+
+  fn validate(&self) -> Result<(), AcpiError> {
+    if checksum(self.bytes()) == 0 {
+      Ok(())
+    } else {
+      Err(AcpiError::InvalidChecksum(self.signature()))
+    }
+  }
+}
+
+impl core::fmt::Debug for Sdt {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let length = self.header.length;
+    f.debug_struct("Sdt")
+      .field("signature", &core::str::from_utf8(&self.header.signature))
+      .field("address", &self.address)
+      .field("length", &length)
+      .finish()
+  }
+}
+
+#[inline]
+fn checksum(bytes: &[u8]) -> u8 {
+  bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Search the RSDP on 16-byte boundaries of the physical range.
+unsafe fn search_rsdp(start: u64, end: u64) -> Option<Rsdp> {
+  (start..end).step_by(16).find_map(|address| {
+    let bytes = physical_to_virtual(PhysicalAddress::new(address)).as_ptr::<u8>();
+    let signature = core::slice::from_raw_parts(bytes, RSDP_SIGNATURE.len());
+    if signature != RSDP_SIGNATURE {
+      return None;
+    }
+    if checksum(core::slice::from_raw_parts(bytes, RSDP_V1_LENGTH)) != 0 {
+      return None;
+    }
+    let rsdp = ptr::read_unaligned(bytes as *const Rsdp);
+    if rsdp.revision >= 2 && checksum(core::slice::from_raw_parts(bytes, rsdp.length as usize)) != 0
+    {
+      return None;
+    }
+    Some(rsdp)
+  })
+}
+
+/// Find the RSDP, and keep the root table for the lookup of the other tables.
+pub fn init_acpi() -> Result<(), AcpiError> {
+  let rsdp = unsafe {
+    let segment = ptr::read_volatile(
+      physical_to_virtual(PhysicalAddress::new(EBDA_SEGMENT_POINTER)).as_ptr::<u16>(),
+    );
+    let ebda = u64::from(segment) << 4;
+    search_rsdp(ebda, ebda + EBDA_SEARCH_SIZE)
+      .or_else(|| search_rsdp(BIOS_AREA_START, BIOS_AREA_END))
+      .ok_or(AcpiError::RsdpNotFound)?
+  };
+
+  let root = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+    RootTable {
+      address:    PhysicalAddress::new(rsdp.xsdt_address),
+      entry_size: size_of::<u64>(),
+    }
+  } else {
+    RootTable {
+      address:    PhysicalAddress::new(u64::from(rsdp.rsdt_address)),
+      entry_size: size_of::<u32>(),
+    }
+  };
+  let table = unsafe { Sdt::new(root.address) };
+  table.validate()?;
+
+  let revision = rsdp.revision;
+  println!(
+    "[INFO ] ACPI revision {}, root table {:?}.",
+    revision, table
+  );
+  *ROOT_TABLE.lock() = Some(root);
+  Ok(())
+}
+
+/// Iterate over the tables listed in the root table.
+pub fn tables() -> impl Iterator<Item = Sdt> {
+  let root = *ROOT_TABLE.lock();
+  let (table, entry_size) = match root {
+    Some(root) => (Some(unsafe { Sdt::new(root.address) }), root.entry_size),
+    None => (None, size_of::<u64>()),
+  };
+
+  table.into_iter().flat_map(move |table| {
+    table.data().chunks_exact(entry_size).map(move |entry| {
+      let address = match entry_size {
+        4 => u64::from(u32::from_le_bytes(entry.try_into().unwrap())),
+        _ => u64::from_le_bytes(entry.try_into().unwrap()),
+      };
+      unsafe { Sdt::new(PhysicalAddress::new(address)) }
+    })
+  })
+}
+
+/// Find the table with the signature, and verify its checksum.
+pub fn find_table(signature: &[u8; 4]) -> Result<Sdt, AcpiError> {
+  let table = tables()
+    .find(|table| &table.signature() == signature)
+    .ok_or(AcpiError::TableNotFound(*signature))?;
+  table.validate()?;
+  Ok(table)
+}
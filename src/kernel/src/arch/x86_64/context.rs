@@ -0,0 +1,92 @@
+//! # Thread Context
+//!
+//! A thread switched out keeps its callee-saved registers on its own stack, under the return
+//! address into `switch_context`, and only its stack pointer in its [`Context`]. The caller-saved
+//! registers are saved by the compiler around the call, and the extended state separately, see
+//! [`switch_fpu_state`](crate::arch::x86_64::fpu::switch_fpu_state).
+//!
+//! A new thread starts with a stack as if it were switched out, returning into `context_entry`,
+//! which calls its entry point.
+
+use core::arch::global_asm;
+
+use crate::arch::VirtualAddress;
+
+global_asm!(
+  r#"
+  .section .text
+  .global switch_context_asm
+  .global context_entry
+switch_context_asm:
+  push rbp
+  push rbx
+  push r12
+  push r13
+  push r14
+  push r15
+  mov [rdi], rsp
+  mov rsp, rsi
+  pop r15
+  pop r14
+  pop r13
+  pop r12
+  pop rbx
+  pop rbp
+  ret
+
+context_entry:
+  mov rdi, r12
+  call r13
+  ud2
+"#
+);
+
+extern "C" {
+  fn switch_context_asm(prev_rsp: *mut u64, next_rsp: u64);
+  static context_entry: u8;
+}
+
+/// Saved context of a thread which is not running.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+  rsp: u64,
+}
+
+impl Context {
+  /// Create the context of a thread which calls `entry(argument)` on the stack ending at the
+  /// address, aligned to 16 bytes.
+  ///
+  /// ## Safety
+  /// The stack must be mapped, and used by no one else.
+  pub unsafe fn new(
+    stack_end: VirtualAddress,
+    entry: extern "C" fn(argument: usize) -> !,
+    argument: usize,
+  ) -> Self {
+    // Popped by `switch_context_asm`: r15, r14, r13, r12, rbx, rbp, then the return address. The
+    // stack is aligned again once `context_entry` is entered, as before a call.
+    let frame = [
+      0,
+      0,
+      entry as *const () as u64,
+      argument as u64,
+      0,
+      0,
+      core::ptr::addr_of!(context_entry) as u64,
+    ];
+    let stack = stack_end.as_mut_ptr::<u64>().sub(frame.len());
+    core::ptr::copy_nonoverlapping(frame.as_ptr(), stack, frame.len());
+    Self { rsp: stack as u64 }
+  }
+}
+
+/// Save the context of the current thread into `prev`, and resume the thread of `next`. Returns
+/// once `prev` is resumed.
+///
+/// ## Safety
+/// Interrupts must be disabled, and `next` must be the context of a thread which is not running.
+#[inline]
+pub unsafe fn switch_context(prev: &mut Context, next: &Context) {
+  switch_context_asm(&mut prev.rsp, next.rsp);
+}
@@ -0,0 +1,94 @@
+//! # Processor Features
+//!
+//! Features of the processor which the bootloader may leave disabled are enabled early at boot.
+
+use core::arch::asm;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::cpuid::CpuId;
+use crate::arch::shared::cpuid::ExtendedFeaturesEbx;
+use crate::arch::shared::cpuid::ExtendedFeaturesEcx;
+use crate::arch::shared::cpuid::ExtendedProcessorFeaturesEdx;
+use crate::arch::shared::cpuid::FeatureInfoEdx;
+use crate::arch::x86_64::reg::Cr0Flags;
+use crate::arch::x86_64::reg::Cr4Flags;
+use crate::arch::x86_64::reg::Efer;
+use crate::arch::x86_64::reg::EferFlags;
+use crate::arch::x86_64::reg::CR0;
+use crate::arch::x86_64::reg::CR4;
+use crate::println;
+
+/// SMAP is enabled, so that the kernel must open a window to access user pages.
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable the no-execute flag of the page table entries, the write protection of read-only pages
+/// in the kernel, and the global pages which are kept in the TLB when the address space changes.
+pub fn init_paging_features() {
+  let cpu_id = CpuId::new();
+  let has_no_execute = cpu_id
+    .get_extended_processor_feature_info()
+    .is_some_and(|info| info.edx().contains(ExtendedProcessorFeaturesEdx::NX));
+  let has_global_pages = cpu_id
+    .get_feature_info()
+    .is_some_and(|info| info.edx().contains(FeatureInfoEdx::PGE));
+
+  unsafe {
+    if has_no_execute {
+      Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+    CR0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    if has_global_pages {
+      CR4::update(|flags| flags.insert(Cr4Flags::PAGE_GLOBAL));
+    }
+  }
+  println!(
+    "[INFO ] Paging features: NX {}, WP true, PGE {}.",
+    has_no_execute, has_global_pages
+  );
+}
+
+/// Forbid the kernel to execute user pages (SMEP) and to access them outside of the user access
+/// windows (SMAP), and forbid user mode to read the descriptor tables (UMIP).
+pub fn init_protection_features() {
+  let features = CpuId::new().get_extended_feature_info();
+  let has = |ebx: ExtendedFeaturesEbx| features.as_ref().is_some_and(|f| f.ebx().contains(ebx));
+  let smep = has(ExtendedFeaturesEbx::SMEP);
+  let smap = has(ExtendedFeaturesEbx::SMAP);
+  let umip = features.as_ref().is_some_and(|f| f.ecx().contains(ExtendedFeaturesEcx::UMIP));
+
+  unsafe {
+    CR4::update(|flags| {
+      flags.set(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PREVENTION, smep);
+      flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, smap);
+      flags.set(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION, umip);
+    });
+  }
+  SMAP_ENABLED.store(smap, Ordering::Relaxed);
+  println!(
+    "[INFO ] Protection features: SMEP {}, SMAP {}, UMIP {}.",
+    smep, smap, umip
+  );
+}
+
+/// Open a window where the kernel may access user pages, by setting RFLAGS.AC.
+///
+/// ## Safety
+/// The window must be closed with [`user_access_end`] right after the access.
+#[inline(always)]
+pub unsafe fn user_access_begin() {
+  if SMAP_ENABLED.load(Ordering::Relaxed) {
+    asm!("stac", options(nostack));
+  }
+}
+
+/// Close the window opened by [`user_access_begin`].
+///
+/// ## Safety
+/// The kernel must not be in the middle of a user access.
+#[inline(always)]
+pub unsafe fn user_access_end() {
+  if SMAP_ENABLED.load(Ordering::Relaxed) {
+    asm!("clac", options(nostack));
+  }
+}
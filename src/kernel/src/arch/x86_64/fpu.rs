@@ -0,0 +1,227 @@
+//! # FPU, SSE and AVX State
+//!
+//! The kernel is built with soft-float and never touches the x87, SSE or AVX registers, which
+//! belong to the threads. Each thread owns an extended state area, saved with `xsave` and restored
+//! with `xrstor` when the processor supports them, or with `fxsave` and `fxrstor` otherwise.
+//!
+//! The state is switched eagerly on each context switch. Lazy switching would trap on the first
+//! FPU instruction after a switch, which costs more than `xsaveopt` skipping the unmodified
+//! components on processors which use the FPU all the time anyway.
+
+use alloc::alloc::alloc_zeroed;
+use alloc::alloc::dealloc;
+use alloc::alloc::handle_alloc_error;
+use core::alloc::Layout;
+use core::arch::asm;
+use core::ptr::NonNull;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::cpuid::CpuId;
+use crate::arch::shared::cpuid::FeatureInfoEcx;
+use crate::arch::shared::cpuid::FeatureInfoEdx;
+use crate::arch::shared::cpuid::XFeatures;
+use crate::arch::shared::cpuid::XsaveFeatures;
+use crate::arch::x86_64::reg::Cr0Flags;
+use crate::arch::x86_64::reg::Cr4Flags;
+use crate::arch::x86_64::reg::CR0;
+use crate::arch::x86_64::reg::CR4;
+use crate::arch::x86_64::reg::XCR0;
+use crate::println;
+
+/// State components available to the threads, if supported.
+const USER_XFEATURES: XFeatures = XFeatures::X87
+  .union(XFeatures::SSE)
+  .union(XFeatures::AVX)
+  .union(XFeatures::OPMASK)
+  .union(XFeatures::ZMM_HI256)
+  .union(XFeatures::HI16_ZMM);
+
+/// Size of the `fxsave` area.
+const FXSAVE_SIZE: usize = 512;
+/// Alignment of the `xsave` area, `fxsave` needs 16.
+const STATE_ALIGN: usize = 64;
+
+/// Offset of the x87 control word in the legacy area.
+const FCW_OFFSET: usize = 0;
+/// Offset of MXCSR in the legacy area.
+const MXCSR_OFFSET: usize = 24;
+/// x87 control word after `fninit`: all the exceptions masked, double extended precision.
+const FCW_DEFAULT: u16 = 0x037F;
+/// MXCSR at reset: all the exceptions masked, round to nearest.
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+/// `xsave` and `xrstor` are used, otherwise `fxsave` and `fxrstor`.
+static XSAVE: AtomicBool = AtomicBool::new(false);
+/// `xsaveopt` is used instead of `xsave`.
+static XSAVEOPT: AtomicBool = AtomicBool::new(false);
+/// Size of the extended state area for the enabled components.
+static STATE_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_SIZE);
+
+/// Enable the x87 FPU, SSE and, with XSAVE, the AVX and AVX-512 state components.
+pub fn init_fpu() {
+  let cpu_id = CpuId::new();
+  let Some(info) = cpu_id.get_feature_info() else {
+    return;
+  };
+  let has_fxsr = info.edx().contains(FeatureInfoEdx::FXSR | FeatureInfoEdx::SSE);
+  let has_xsave = has_fxsr && info.ecx().contains(FeatureInfoEcx::XSAVE);
+
+  unsafe {
+    CR0::update(|flags| {
+      flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+      flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+    });
+    if has_fxsr {
+      CR4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    asm!("fninit", options(nomem, nostack));
+
+    if has_xsave {
+      CR4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+      let supported = cpu_id
+        .get_extended_state_info()
+        .map_or(XFeatures::X87 | XFeatures::SSE, |state| {
+          state.xcr0_supported()
+        });
+      XCR0::write(supported & USER_XFEATURES);
+    }
+  }
+
+  // The size of the area depends on the components enabled in XCR0.
+  if let Some(state) = has_xsave.then(|| cpu_id.get_extended_state_info()).flatten() {
+    STATE_SIZE.store(state.xsave_size() as usize, Ordering::Relaxed);
+    XSAVEOPT.store(
+      state.xsave_features().contains(XsaveFeatures::XSAVEOPT),
+      Ordering::Relaxed,
+    );
+    XSAVE.store(true, Ordering::Relaxed);
+  }
+  println!(
+    "[INFO ] FPU: {}, components {:?}, state {} bytes.",
+    if has_xsave { "xsave" } else { "fxsave" },
+    if has_xsave {
+      unsafe { XCR0::read() }
+    } else {
+      XFeatures::X87 | XFeatures::SSE
+    },
+    STATE_SIZE.load(Ordering::Relaxed)
+  );
+}
+
+/// Extended state of a thread: the x87, SSE, and enabled AVX registers.
+pub struct FpuState {
+  area:   NonNull<u8>,
+  layout: Layout,
+}
+
+unsafe impl Send for FpuState {}
+
+impl FpuState {
+  /// Allocate an area in the initial state.
+  pub fn new() -> Self {
+    let layout = Layout::from_size_align(STATE_SIZE.load(Ordering::Relaxed), STATE_ALIGN)
+      .expect("Invalid extended state size");
+    let area =
+      NonNull::new(unsafe { alloc_zeroed(layout) }).unwrap_or_else(|| handle_alloc_error(layout));
+    // The header of the `xsave` area is zero, so that `xrstor` initializes the components, except
+    // MXCSR which is always loaded.
+    unsafe {
+      area.as_ptr().add(FCW_OFFSET).cast::<u16>().write(FCW_DEFAULT);
+      area.as_ptr().add(MXCSR_OFFSET).cast::<u32>().write(MXCSR_DEFAULT);
+    }
+    Self { area, layout }
+  }
+
+  /// Save the registers of the current processor into the area.
+  ///
+  /// ## Safety
+  /// The FPU must be initialized.
+  pub unsafe fn save(&mut self) {
+    let area = self.area.as_ptr();
+    if !XSAVE.load(Ordering::Relaxed) {
+      asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+    } else if XSAVEOPT.load(Ordering::Relaxed) {
+      asm!(
+        "xsaveopt64 [{}]",
+        in(reg) area,
+        in("eax") u32::MAX,
+        in("edx") u32::MAX,
+        options(nostack, preserves_flags),
+      );
+    } else {
+      asm!(
+        "xsave64 [{}]",
+        in(reg) area,
+        in("eax") u32::MAX,
+        in("edx") u32::MAX,
+        options(nostack, preserves_flags),
+      );
+    }
+  }
+
+  /// Load the registers of the current processor from the area.
+  ///
+  /// ## Safety
+  /// The FPU must be initialized.
+  pub unsafe fn restore(&self) {
+    let area = self.area.as_ptr();
+    if XSAVE.load(Ordering::Relaxed) {
+      asm!(
+        "xrstor64 [{}]",
+        in(reg) area,
+        in("eax") u32::MAX,
+        in("edx") u32::MAX,
+        options(nostack, preserves_flags),
+      );
+    } else {
+      asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+  }
+
+  /// Return the MXCSR saved in the area.
+  pub fn mxcsr(&self) -> u32 {
+    unsafe { self.area.as_ptr().add(MXCSR_OFFSET).cast::<u32>().read() }
+  }
+}
+
+impl Default for FpuState {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Drop for FpuState {
+  fn drop(&mut self) {
+    unsafe { dealloc(self.area.as_ptr(), self.layout) };
+  }
+}
+
+/// Switch the extended state from the previous thread to the next one, on context switch.
+///
+/// ## Safety
+/// The FPU must be initialized, and `prev` must be the state of the thread switched out.
+pub unsafe fn switch_fpu_state(prev: &mut FpuState, next: &FpuState) {
+  prev.save();
+  next.restore();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_fpu_state_round_trip() {
+    let mut saved = FpuState::new();
+    let initial = FpuState::new();
+    unsafe {
+      saved.save();
+      initial.restore();
+      let mut state = FpuState::new();
+      state.save();
+      assert_eq!(state.mxcsr(), MXCSR_DEFAULT);
+      saved.restore();
+    }
+  }
+}
@@ -1,41 +1,96 @@
+use alloc::boxed::Box;
 use core::ptr::addr_of;
 
 use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::Segment;
+use x86_64::instructions::segmentation::CS;
+use x86_64::instructions::segmentation::DS;
+use x86_64::instructions::segmentation::SS;
+use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::Descriptor;
 use x86_64::structures::gdt::GlobalDescriptorTable;
+use x86_64::structures::gdt::SegmentSelector;
 use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
 
+use crate::arch::VirtualAddress;
 use crate::println;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of the double fault stack of each processor.
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// Create a TSS whose double fault stack ends at the address.
+fn new_tss(double_fault_stack_end: VirtAddr) -> TaskStateSegment {
+  let mut tss = TaskStateSegment::new();
+  tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack_end;
+  tss
+}
+
 lazy_static! {
   /// # TSS - Task State Segment
+  ///
+  /// TSS of the bootstrap processor, set up before the heap.
   static ref TSS: TaskStateSegment = {
-    let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-      const STACK_SIZE: usize = 4096 * 5;
-      static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-      let stack_start = x86_64::VirtAddr::from_ptr(unsafe { addr_of!(STACK) });
-      let stack_end = stack_start + STACK_SIZE as u64;
-      stack_end
-    };
-    tss
+    static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+    let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACK) });
+    new_tss(stack_start + DOUBLE_FAULT_STACK_SIZE as u64)
   };
 }
 
+/// Segment selectors of the GDT.
+struct Selectors {
+  code: SegmentSelector,
+  data: SegmentSelector,
+  tss:  SegmentSelector,
+}
+
+/// Create a GDT with the kernel segments and the TSS.
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+  let mut gdt = GlobalDescriptorTable::new();
+  let code = gdt.append(Descriptor::kernel_code_segment());
+  let data = gdt.append(Descriptor::kernel_data_segment());
+  let tss = gdt.append(Descriptor::tss_segment(tss));
+  (gdt, Selectors { code, data, tss })
+}
+
 lazy_static! {
   /// # GPT - Global Descriptor Table
-  static ref GDT: GlobalDescriptorTable = {
-    let mut gdt = GlobalDescriptorTable::new();
-    gdt.append(Descriptor::kernel_code_segment());
-    gdt.append(Descriptor::tss_segment(&TSS));
-    gdt
-  };
+  ///
+  /// GDT of the bootstrap processor.
+  static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
+}
+
+/// Load the GDT and the TSS on the current processor.
+fn load_gdt(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+  gdt.load();
+  // Reload the segment registers, the ones set by the bootloader refer to its own GDT.
+  // The TSS must be loaded so that the CPU switches to the interrupt stacks.
+  unsafe {
+    CS::set_reg(selectors.code);
+    DS::set_reg(selectors.data);
+    SS::set_reg(selectors.data);
+    load_tss(selectors.tss);
+  }
 }
 
-pub fn init_gdt() {
+/// Set up the GDT and the TSS of the bootstrap processor, and return the TSS.
+pub fn init_gdt() -> &'static TaskStateSegment {
   println!("[INFO ] Initialize GDT.");
-  GDT.load();
+  let (gdt, selectors) = &*GDT;
+  load_gdt(gdt, selectors);
+  &TSS
+}
+
+/// Set up the GDT and the TSS of an application processor, whose double fault stack ends at the
+/// address, and return the TSS. The tables live as long as the processor, so they are never freed.
+pub fn init_ap_gdt(double_fault_stack_end: VirtualAddress) -> &'static TaskStateSegment {
+  let tss = Box::leak(Box::new(new_tss(VirtAddr::new(
+    double_fault_stack_end.as_raw(),
+  ))));
+  let (gdt, selectors) = Box::leak(Box::new(new_gdt(tss)));
+  load_gdt(gdt, selectors);
+  tss
 }
@@ -0,0 +1,210 @@
+//! # Local APIC Timer
+//!
+//! The local APIC timer raises an interrupt on the current processor, either when the TSC reaches
+//! a deadline in TSC-deadline mode, or when a count reaches zero in one-shot mode. The count runs
+//! at the bus frequency divided by 16, which is calibrated against the PIT.
+//!
+//! Once started, the APIC timer takes over the system tick from the PIT: each event programs the
+//! next one a tick later, except while idle, when the tick is stopped and the next event is the
+//! next kernel timer. The jiffies are then derived from the clock source.
+
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::cpuid::CpuId;
+use crate::arch::shared::cpuid::FeatureInfoEcx;
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::shared::irq::IrqError;
+use crate::arch::shared::time;
+use crate::arch::shared::time::clock_source;
+use crate::arch::shared::time::counter;
+use crate::arch::shared::time::JIFFIES_CLOCK_SOURCE;
+use crate::arch::x86_64::hw::pit;
+use crate::arch::x86_64::hw::tsc;
+use crate::arch::x86_64::interrupt::apic::LocalApic;
+use crate::arch::x86_64::interrupt::apic::Register;
+use crate::arch::x86_64::interrupt::apic::TIMER_VECTOR;
+use crate::arch::x86_64::reg::TscDeadline;
+use crate::println;
+use crate::support;
+use crate::support::NanoSecond;
+
+/// LVT timer: one-shot mode.
+const LVT_TIMER_ONE_SHOT: u32 = 0b00 << 17;
+/// LVT timer: TSC-deadline mode.
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+
+/// Divide configuration: divide the bus clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Duration of the calibration in microseconds.
+const CALIBRATION_TIME: u64 = 10_000;
+
+/// Error occurred when starting the APIC timer.
+#[derive(Debug)]
+pub enum ApicTimerError {
+  /// The local APIC is not enabled.
+  NoLocalApic,
+  /// The clock source is the tick itself, which cannot be stopped.
+  NoClockSource,
+  /// The count did not run during the calibration.
+  CalibrationFailed,
+  Irq(IrqError),
+}
+
+impl From<IrqError> for ApicTimerError {
+  fn from(err: IrqError) -> Self {
+    Self::Irq(err)
+  }
+}
+
+/// The APIC timer drives the system tick.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The TSC-deadline mode is used, otherwise the one-shot mode.
+static TSC_DEADLINE: AtomicBool = AtomicBool::new(false);
+/// Frequency of the count in one-shot mode, in Hz.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The tick is stopped, the next event is the next kernel timer.
+static TICK_STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// True if the processor supports the TSC-deadline mode.
+fn is_tsc_deadline_supported() -> bool {
+  CpuId::new()
+    .get_feature_info()
+    .is_some_and(|info| info.ecx().contains(FeatureInfoEcx::TSC_DEADLINE))
+}
+
+/// True if the APIC timer drives the system tick.
+#[inline]
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::Acquire)
+}
+
+/// Measure the frequency of the count in Hz during a busy wait on the PIT.
+fn measure_frequency(local_apic: &LocalApic) -> u64 {
+  without_interrupts(|| unsafe {
+    local_apic.write(Register::TimerDivideConfiguration, DIVIDE_BY_16);
+    local_apic.write(Register::TimerInitialCount, u32::MAX);
+    pit::busy_wait(CALIBRATION_TIME);
+    let elapsed = u32::MAX - local_apic.read(Register::TimerCurrentCount);
+    local_apic.write(Register::TimerInitialCount, 0);
+    u64::from(elapsed) * 1_000_000 / CALIBRATION_TIME
+  })
+}
+
+/// Program the next event at the counter time, in nanoseconds. An event in the past fires at once.
+fn set_next_event(local_apic: &LocalApic, deadline: NanoSecond) {
+  let delta = deadline.saturating_sub(counter());
+  unsafe {
+    if TSC_DEADLINE.load(Ordering::Relaxed) {
+      let cycles = delta * NanoSecond::from(tsc::frequency()) / 1_000_000_000;
+      let target = tsc::read_tsc().saturating_add(cycles as u64).max(1);
+      TscDeadline::write(target);
+    } else {
+      let frequency = NanoSecond::from(FREQUENCY.load(Ordering::Relaxed));
+      let count = (delta * frequency / 1_000_000_000).clamp(1, NanoSecond::from(u32::MAX));
+      local_apic.write(Register::TimerInitialCount, count as u32);
+    }
+  }
+}
+
+/// Disarm the timer.
+fn cancel_next_event(local_apic: &LocalApic) {
+  unsafe {
+    if TSC_DEADLINE.load(Ordering::Relaxed) {
+      TscDeadline::write(0);
+    } else {
+      local_apic.write(Register::TimerInitialCount, 0);
+    }
+  }
+}
+
+/// Program the next tick.
+fn program_tick(local_apic: &LocalApic) {
+  set_next_event(local_apic, time::jiffy_to_counter(time::jiffies() + 1));
+}
+
+/// Calibrate the APIC timer, and take over the system tick from the PIT.
+///
+/// The clock source must not be the jiffies, which stop with the tick.
+pub fn init_apic_timer() -> Result<(), ApicTimerError> {
+  let local_apic = LocalApic::get().ok_or(ApicTimerError::NoLocalApic)?;
+  if ptr::eq(clock_source(), &JIFFIES_CLOCK_SOURCE) {
+    return Err(ApicTimerError::NoClockSource);
+  }
+
+  let tsc_deadline = is_tsc_deadline_supported() && tsc::frequency() != 0;
+  let mode = if tsc_deadline {
+    println!("[INFO ] Initialize APIC timer, TSC-deadline mode.");
+    LVT_TIMER_TSC_DEADLINE
+  } else {
+    // Take the best of a few runs, a run may be stretched by an SMI or a virtual machine exit.
+    let frequency = (0..3).map(|_| measure_frequency(&local_apic)).min().unwrap_or(0);
+    if frequency == 0 {
+      return Err(ApicTimerError::CalibrationFailed);
+    }
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    println!(
+      "[INFO ] Initialize APIC timer, one-shot mode, {}.{:03} MHz.",
+      frequency / 1_000_000,
+      frequency / 1000 % 1000
+    );
+    LVT_TIMER_ONE_SHOT
+  };
+  TSC_DEADLINE.store(tsc_deadline, Ordering::Relaxed);
+
+  pit::stop_pit()?;
+  without_interrupts(|| unsafe {
+    local_apic.write(Register::LvtTimer, mode | u32::from(TIMER_VECTOR));
+    // The write to the LVT must be ordered before the write to the deadline MSR.
+    core::sync::atomic::fence(Ordering::SeqCst);
+    time::update_jiffies();
+    ENABLED.store(true, Ordering::Release);
+    program_tick(&local_apic);
+  });
+  Ok(())
+}
+
+/// Stop the tick before idling, and program the next event at the next kernel timer, if any.
+///
+/// ## Safety
+/// Interrupts must be disabled until the processor halts, otherwise the tick may stay stopped.
+pub unsafe fn stop_tick() {
+  let Some(local_apic) = LocalApic::get() else {
+    return;
+  };
+  TICK_STOPPED.store(true, Ordering::Relaxed);
+  match support::time::next_expiry() {
+    Some(jiffy) => set_next_event(&local_apic, time::jiffy_to_counter(jiffy)),
+    None => cancel_next_event(&local_apic),
+  }
+}
+
+/// Catch up the jiffies and restart the tick after idling.
+///
+/// ## Safety
+/// Interrupts must be disabled.
+pub unsafe fn restart_tick() {
+  let Some(local_apic) = LocalApic::get() else {
+    return;
+  };
+  TICK_STOPPED.store(false, Ordering::Relaxed);
+  time::update_jiffies();
+  support::time::run_timers();
+  program_tick(&local_apic);
+}
+
+/// Account the elapsed ticks, run the expired kernel timers, and program the next event. Called
+/// by the interrupt handler of the APIC timer.
+pub fn timer_interrupt() {
+  time::update_jiffies();
+  support::time::run_timers();
+  // While the tick is stopped, the idle loop programs the next event once woken up.
+  if !TICK_STOPPED.load(Ordering::Relaxed) {
+    if let Some(local_apic) = LocalApic::get() {
+      program_tick(&local_apic);
+    }
+  }
+}
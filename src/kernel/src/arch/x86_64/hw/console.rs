@@ -1,8 +1,9 @@
-use spin::Mutex;
 use uart_16550::SerialPort;
 
-pub static COM1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x03F8) });
-pub static COM2: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x02F8) });
+use crate::support::sync::IrqSpinLock;
+
+pub static COM1: IrqSpinLock<SerialPort> = IrqSpinLock::new(unsafe { SerialPort::new(0x03F8) });
+pub static COM2: IrqSpinLock<SerialPort> = IrqSpinLock::new(unsafe { SerialPort::new(0x02F8) });
 
 pub fn init() {
   COM1.lock().init();
@@ -0,0 +1,146 @@
+//! # HPET - High Precision Event Timer
+//!
+//! The HPET has a main counter running at a constant rate of at least 10 MHz, discovered through
+//! the ACPI HPET table. Only the main counter is used, as a clock source.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::time::ClockSource;
+use crate::arch::x86_64::acpi::find_table;
+use crate::arch::x86_64::acpi::AcpiError;
+use crate::arch::PhysicalAddress;
+use crate::arch::VirtualAddress;
+use crate::mem::mmio::map_mmio;
+use crate::mem::mmio::MmioError;
+use crate::println;
+use crate::support::NanoSecond;
+
+const HPET_SIGNATURE: &[u8; 4] = b"HPET";
+
+/// Offset of the address space ID of the base address, a generic address structure.
+const ADDRESS_SPACE_OFFSET: usize = 40;
+/// Offset of the base address in the generic address structure.
+const ADDRESS_OFFSET: usize = 44;
+/// Address space ID of the system memory.
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// General capabilities and ID register.
+const CAPABILITIES: u64 = 0x000;
+/// General configuration register.
+const CONFIGURATION: u64 = 0x010;
+/// Main counter value register.
+const MAIN_COUNTER: u64 = 0x0F0;
+/// Size of the register block.
+const REGISTERS_SIZE: u64 = 0x400;
+
+/// Capabilities: the main counter is 64-bit.
+const CAPABILITY_64_BIT: u64 = 1 << 13;
+/// Configuration: the main counter runs.
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+/// Femtoseconds per nanosecond.
+const FEMTOSECONDS: u128 = 1_000_000;
+
+/// Error occurred when initializing the HPET.
+#[derive(Debug)]
+pub enum HpetError {
+  Acpi(AcpiError),
+  /// The table ends before the base address.
+  TableTooShort,
+  /// The registers are not in the system memory.
+  UnsupportedAddressSpace(u8),
+  /// The main counter is 32-bit, which wraps around in minutes.
+  Counter32Bit,
+  Mmio(MmioError),
+}
+
+impl From<AcpiError> for HpetError {
+  fn from(err: AcpiError) -> Self {
+    Self::Acpi(err)
+  }
+}
+
+impl From<MmioError> for HpetError {
+  fn from(err: MmioError) -> Self {
+    Self::Mmio(err)
+  }
+}
+
+/// Virtual address of the registers, zero if not initialized.
+static HPET_BASE: AtomicU64 = AtomicU64::new(0);
+/// Period of the main counter in femtoseconds.
+static HPET_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// The HPET clock source.
+pub static HPET_CLOCK_SOURCE: ClockSource = ClockSource {
+  name:   "hpet",
+  rating: 250,
+  read:   read_nanoseconds,
+};
+
+#[inline]
+unsafe fn read_register(base: VirtualAddress, register: u64) -> u64 {
+  core::ptr::read_volatile((base + register).as_ptr::<u64>())
+}
+
+#[inline]
+unsafe fn write_register(base: VirtualAddress, register: u64, value: u64) {
+  core::ptr::write_volatile((base + register).as_mut_ptr::<u64>(), value);
+}
+
+/// True if the HPET is initialized.
+#[inline]
+pub fn is_available() -> bool {
+  HPET_BASE.load(Ordering::Acquire) != 0
+}
+
+/// Return the value of the main counter, or zero if the HPET is not initialized.
+#[inline]
+pub fn read_counter() -> u64 {
+  match HPET_BASE.load(Ordering::Acquire) {
+    0 => 0,
+    base => unsafe { read_register(VirtualAddress::new(base), MAIN_COUNTER) },
+  }
+}
+
+/// Return the main counter in nanoseconds.
+pub fn read_nanoseconds() -> NanoSecond {
+  let period = NanoSecond::from(HPET_PERIOD.load(Ordering::Relaxed));
+  NanoSecond::from(read_counter()) * period / FEMTOSECONDS
+}
+
+/// Find the HPET in the ACPI tables, and start its main counter.
+pub fn init_hpet() -> Result<(), HpetError> {
+  let table = find_table(HPET_SIGNATURE)?;
+  let space = unsafe { table.read::<u8>(ADDRESS_SPACE_OFFSET) }.ok_or(HpetError::TableTooShort)?;
+  if space != ADDRESS_SPACE_MEMORY {
+    return Err(HpetError::UnsupportedAddressSpace(space));
+  }
+  let address = unsafe { table.read::<u64>(ADDRESS_OFFSET) }.ok_or(HpetError::TableTooShort)?;
+  let address = PhysicalAddress::new(address);
+  let base = unsafe { map_mmio(address, REGISTERS_SIZE) }?;
+
+  unsafe {
+    let capabilities = read_register(base, CAPABILITIES);
+    if capabilities & CAPABILITY_64_BIT == 0 {
+      return Err(HpetError::Counter32Bit);
+    }
+    let period = capabilities >> 32;
+
+    // The main counter may only be written while halted.
+    let configuration = read_register(base, CONFIGURATION);
+    write_register(base, CONFIGURATION, configuration & !CONFIGURATION_ENABLE);
+    write_register(base, MAIN_COUNTER, 0);
+    write_register(base, CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+
+    HPET_PERIOD.store(period, Ordering::Relaxed);
+    HPET_BASE.store(base.as_raw(), Ordering::Release);
+    println!(
+      "[INFO ] HPET at {:?}, {} kHz.",
+      address,
+      1_000_000_000_000 / period
+    );
+  }
+  Ok(())
+}
@@ -0,0 +1,27 @@
+//! # PS/2 Keyboard
+//!
+//! The keyboard of the i8042 controller raises ISA IRQ 1 for each scancode.
+
+use crate::arch::interrupt::register_irq;
+use crate::arch::shared::irq::Irq;
+use crate::arch::shared::irq::IrqError;
+use crate::arch::shared::irq::IrqReturn;
+use crate::arch::x86_64::hw::port::Port;
+
+/// IRQ of the keyboard.
+pub const KEYBOARD_IRQ: Irq = 1;
+
+/// Data port of the i8042 controller.
+const DATA_PORT: u16 = 0x60;
+
+/// Attach the keyboard handler to its IRQ.
+pub fn init_keyboard() -> Result<(), IrqError> {
+  register_irq(KEYBOARD_IRQ, keyboard_interrupt, "i8042 keyboard")
+}
+
+fn keyboard_interrupt(_irq: Irq) -> IrqReturn {
+  // The scancode must be read, otherwise the keyboard controller raises no more interrupts.
+  let port: Port<u8> = unsafe { Port::new(DATA_PORT) };
+  let _scancode = unsafe { port.read() };
+  IrqReturn::Handled
+}
@@ -1,4 +1,10 @@
+pub mod apic_timer;
 pub mod console;
+pub mod hpet;
+pub mod keyboard;
+pub mod pit;
 pub mod port;
 pub(crate) mod qemu;
+pub mod rtc;
+pub mod tsc;
 pub mod vga;
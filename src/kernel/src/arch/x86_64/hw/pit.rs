@@ -0,0 +1,108 @@
+//! # PIT - Programmable Interval Timer
+//!
+//! The 8253/8254 PIT divides its 1.193182 MHz input clock. Channel 0 is wired to ISA IRQ 0, and
+//! drives the system tick as a rate generator. Channel 2, whose output can be polled, is used for
+//! busy waits which do not depend on interrupts, e.g. to calibrate other timers.
+
+use crate::arch::interrupt::register_irq;
+use crate::arch::interrupt::unregister_irq;
+use crate::arch::shared::irq::Irq;
+use crate::arch::shared::irq::IrqError;
+use crate::arch::shared::irq::IrqReturn;
+use crate::arch::shared::time;
+use crate::arch::shared::time::HZ;
+use crate::arch::x86_64::hw::port::Port;
+use crate::println;
+use crate::support;
+
+/// Frequency of the input clock in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// IRQ of channel 0.
+pub const TIMER_IRQ: Irq = 0;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// NMI status and control port, which gates channel 2 and reads its output.
+const CONTROL_PORT: u16 = 0x61;
+
+/// Command: channel 0, low byte then high byte of the reload value, mode 2, binary counting.
+const COMMAND_CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+/// Command: channel 0, low byte then high byte of the reload value, mode 0, binary counting.
+const COMMAND_CHANNEL_0_ONE_SHOT: u8 = 0b0011_0000;
+/// Command: channel 2, low byte then high byte of the reload value, mode 0, binary counting.
+const COMMAND_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Control: gate of channel 2.
+const CONTROL_GATE_2: u8 = 1 << 0;
+/// Control: channel 2 output to the speaker.
+const CONTROL_SPEAKER: u8 = 1 << 1;
+/// Control: output of channel 2.
+const CONTROL_OUTPUT_2: u8 = 1 << 5;
+
+/// Program channel 0 to fire every `divisor` input clocks.
+///
+/// ## Safety
+/// Channel 0 must not be used by another timer.
+unsafe fn set_rate_generator(divisor: u16) {
+  let command: Port<u8> = Port::new(COMMAND_PORT);
+  let channel: Port<u8> = Port::new(CHANNEL_0_PORT);
+  command.write(COMMAND_CHANNEL_0_RATE_GENERATOR);
+  channel.write(divisor as u8);
+  channel.write((divisor >> 8) as u8);
+}
+
+/// Start the system tick at `HZ` on channel 0.
+pub fn init_pit() -> Result<(), IrqError> {
+  let divisor = (PIT_FREQUENCY + HZ / 2) / HZ;
+  let period = divisor * 1_000_000_000_000 / PIT_FREQUENCY;
+  println!(
+    "[INFO ] Initialize PIT, tick period {}.{:03} ns.",
+    period / 1000,
+    period % 1000
+  );
+
+  time::set_tick_period(period);
+  unsafe { set_rate_generator(divisor as u16) };
+  register_irq(TIMER_IRQ, timer_interrupt, "pit")
+}
+
+/// Stop the system tick on channel 0, once another timer took it over.
+pub fn stop_pit() -> Result<(), IrqError> {
+  unregister_irq(TIMER_IRQ, "pit")?;
+  // In mode 0, the counter does not run until a count is written.
+  unsafe {
+    let command: Port<u8> = Port::new(COMMAND_PORT);
+    command.write(COMMAND_CHANNEL_0_ONE_SHOT);
+  }
+  Ok(())
+}
+
+/// Busy wait for the microseconds, at most 54925 which is the longest count of the PIT, by polling
+/// the output of channel 2. Interrupts may be disabled.
+pub fn busy_wait(microseconds: u64) {
+  let count = (PIT_FREQUENCY * microseconds / 1_000_000).clamp(1, u64::from(u16::MAX)) as u16;
+  unsafe {
+    let control: Port<u8> = Port::new(CONTROL_PORT);
+    let command: Port<u8> = Port::new(COMMAND_PORT);
+    let channel: Port<u8> = Port::new(CHANNEL_2_PORT);
+
+    // Silence the speaker, and raise the gate so that channel 2 counts.
+    control.write((control.read() & !CONTROL_SPEAKER) | CONTROL_GATE_2);
+    command.write(COMMAND_CHANNEL_2_ONE_SHOT);
+    channel.write(count as u8);
+    channel.write((count >> 8) as u8);
+
+    // In mode 0, the output goes high when the count reaches zero.
+    while control.read() & CONTROL_OUTPUT_2 == 0 {
+      core::hint::spin_loop();
+    }
+  }
+}
+
+fn timer_interrupt(_irq: Irq) -> IrqReturn {
+  time::tick();
+  support::time::run_timers();
+  IrqReturn::Handled
+}
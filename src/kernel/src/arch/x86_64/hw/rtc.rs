@@ -0,0 +1,292 @@
+//! # CMOS RTC - Real-Time Clock
+//!
+//! The RTC keeps the wall-clock date, in BCD or binary, and in 12-hour or 24-hour format depending
+//! on status register B. The century lives in a CMOS register given by the FADT, if any.
+//!
+//! The date is read at boot to set the kernel start time. The update-ended interrupt, raised once
+//! per second right after the RTC ticks, can resynchronize the start time periodically, which
+//! corrects the drift of the monotonic clock.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+use crate::arch::interrupt::register_irq;
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::shared::irq::Irq;
+use crate::arch::shared::irq::IrqError;
+use crate::arch::shared::irq::IrqReturn;
+use crate::arch::shared::time::counter;
+use crate::arch::x86_64::acpi::find_table;
+use crate::arch::x86_64::hw::port::Port;
+use crate::println;
+use crate::support;
+use crate::support::NanoSecond;
+
+/// IRQ of the RTC.
+pub const RTC_IRQ: Irq = 8;
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+const REGISTER_STATUS_C: u8 = 0x0C;
+
+/// Status A: an update is in progress, the date registers must not be read.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: the update-ended interrupt is enabled.
+const STATUS_B_UPDATE_INTERRUPT: u8 = 1 << 4;
+/// Status B: the hours are in 24-hour format.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Status B: the date is binary, otherwise BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Hours in 12-hour format: post meridiem.
+const HOURS_PM: u8 = 1 << 7;
+
+/// FADT signature.
+const FADT_SIGNATURE: &[u8; 4] = b"FACP";
+/// Offset of the CMOS index of the century register in the FADT.
+const FADT_CENTURY_OFFSET: usize = 108;
+
+const NANOSECONDS: NanoSecond = 1_000_000_000;
+
+/// CMOS index of the century register, zero if there is none.
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+/// Resynchronize the start time every such seconds, zero to disable.
+static RESYNC_INTERVAL: AtomicU64 = AtomicU64::new(0);
+/// Count of update-ended interrupts.
+static UPDATES: AtomicU64 = AtomicU64::new(0);
+
+/// A date and time in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+  pub year:   u16,
+  pub month:  u8,
+  pub day:    u8,
+  pub hour:   u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl DateTime {
+  /// Return the seconds since the Unix epoch.
+  pub fn unix_timestamp(&self) -> u64 {
+    let days = days_from_civil(
+      i64::from(self.year),
+      u32::from(self.month),
+      u32::from(self.day),
+    );
+    let seconds =
+      u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+    days as u64 * 86400 + seconds
+  }
+}
+
+impl core::fmt::Display for DateTime {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+/// Count of days since 1970-01-01 of the date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+  // Count the years from March, so that the leap day is the last day of the year.
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let month = i64::from(month);
+  let day_of_year =
+    (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+#[inline]
+unsafe fn read_register(register: u8) -> u8 {
+  let index: Port<u8> = Port::new(INDEX_PORT);
+  let data: Port<u8> = Port::new(DATA_PORT);
+  index.write(register);
+  data.read()
+}
+
+#[inline]
+unsafe fn write_register(register: u8, value: u8) {
+  let index: Port<u8> = Port::new(INDEX_PORT);
+  let data: Port<u8> = Port::new(DATA_PORT);
+  index.write(register);
+  data.write(value);
+}
+
+#[inline]
+fn from_bcd(value: u8) -> u8 {
+  (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// The raw date registers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Registers {
+  second:  u8,
+  minute:  u8,
+  hour:    u8,
+  day:     u8,
+  month:   u8,
+  year:    u8,
+  century: u8,
+}
+
+unsafe fn read_registers() -> Registers {
+  let century_register = CENTURY_REGISTER.load(Ordering::Relaxed);
+  Registers {
+    second:  read_register(REGISTER_SECONDS),
+    minute:  read_register(REGISTER_MINUTES),
+    hour:    read_register(REGISTER_HOURS),
+    day:     read_register(REGISTER_DAY),
+    month:   read_register(REGISTER_MONTH),
+    year:    read_register(REGISTER_YEAR),
+    century: if century_register != 0 {
+      read_register(century_register)
+    } else {
+      0
+    },
+  }
+}
+
+/// Read the date of the RTC.
+pub fn read_rtc() -> DateTime {
+  let registers = without_interrupts(|| unsafe {
+    // The date is read twice, until no update happened in between.
+    loop {
+      while read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+      }
+      let first = read_registers();
+      while read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+      }
+      if read_registers() == first {
+        break first;
+      }
+    }
+  });
+  let status_b = without_interrupts(|| unsafe { read_register(REGISTER_STATUS_B) });
+
+  let decode = |value: u8| {
+    if status_b & STATUS_B_BINARY != 0 {
+      value
+    } else {
+      from_bcd(value)
+    }
+  };
+
+  let mut hour = decode(registers.hour & !HOURS_PM);
+  if status_b & STATUS_B_24_HOUR == 0 {
+    // 12 AM is midnight, 12 PM is noon.
+    hour %= 12;
+    if registers.hour & HOURS_PM != 0 {
+      hour += 12;
+    }
+  }
+
+  let century = if CENTURY_REGISTER.load(Ordering::Relaxed) != 0 {
+    u16::from(decode(registers.century))
+  } else {
+    20
+  };
+
+  DateTime {
+    year: century * 100 + u16::from(decode(registers.year)),
+    month: decode(registers.month),
+    day: decode(registers.day),
+    hour,
+    minute: decode(registers.minute),
+    second: decode(registers.second),
+  }
+}
+
+/// Return the kernel start time, so that the real time at the monotonic time is the RTC date.
+fn start_time(date: &DateTime, monotonic: NanoSecond) -> NanoSecond {
+  let now = NanoSecond::from(date.unix_timestamp()) * NANOSECONDS;
+  now.saturating_sub(monotonic)
+}
+
+/// Find the century register, read the RTC, and set the kernel start time.
+pub fn init_rtc() {
+  if let Ok(fadt) = find_table(FADT_SIGNATURE) {
+    let century = unsafe { fadt.read::<u8>(FADT_CENTURY_OFFSET) }.unwrap_or(0);
+    CENTURY_REGISTER.store(century, Ordering::Relaxed);
+  }
+
+  let date = read_rtc();
+  *support::START.lock() = start_time(&date, support::monotonic());
+  println!("[INFO ] RTC: {} UTC.", date);
+}
+
+/// Resynchronize the kernel start time every `interval` seconds, on the update-ended interrupt of
+/// the RTC. The start time is exact then, since the RTC just ticked.
+pub fn enable_resync(interval: u64) -> Result<(), IrqError> {
+  RESYNC_INTERVAL.store(interval, Ordering::Relaxed);
+  register_irq(RTC_IRQ, rtc_interrupt, "rtc")?;
+  without_interrupts(|| unsafe {
+    let status_b = read_register(REGISTER_STATUS_B);
+    write_register(REGISTER_STATUS_B, status_b | STATUS_B_UPDATE_INTERRUPT);
+    // Acknowledge any pending interrupt, otherwise no more is raised.
+    read_register(REGISTER_STATUS_C);
+  });
+  Ok(())
+}
+
+fn rtc_interrupt(_irq: Irq) -> IrqReturn {
+  // Reading status C acknowledges the interrupt.
+  let _status_c = unsafe { read_register(REGISTER_STATUS_C) };
+
+  let updates = UPDATES.fetch_add(1, Ordering::Relaxed) + 1;
+  let interval = RESYNC_INTERVAL.load(Ordering::Relaxed);
+  if interval != 0 && updates.is_multiple_of(interval) {
+    // The times may be locked by the interrupted code, then wait for the next interval.
+    if let (Some(offset), Some(mut start)) = (support::OFFSET.try_lock(), support::START.try_lock())
+    {
+      *start = start_time(&read_rtc(), *offset + counter());
+    }
+  }
+  IrqReturn::Handled
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_unix_timestamp() {
+    let epoch = DateTime {
+      year:   1970,
+      month:  1,
+      day:    1,
+      hour:   0,
+      minute: 0,
+      second: 0,
+    };
+    assert_eq!(epoch.unix_timestamp(), 0);
+
+    let leap_day = DateTime {
+      year:   2024,
+      month:  2,
+      day:    29,
+      hour:   13,
+      minute: 14,
+      second: 15,
+    };
+    assert_eq!(leap_day.unix_timestamp(), 1_709_212_455);
+  }
+}
@@ -0,0 +1,92 @@
+//! # TSC - Time Stamp Counter
+//!
+//! The TSC counts processor cycles, and is the cheapest clock to read. Its frequency is not
+//! reported reliably, so it's calibrated against the HPET, or the PIT without HPET.
+//!
+//! Only an invariant TSC, which runs at a constant rate in all the power states, is a clock source.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::cpuid::CpuId;
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::shared::time::ClockSource;
+use crate::arch::x86_64::hw::hpet;
+use crate::arch::x86_64::hw::pit;
+use crate::println;
+use crate::support::NanoSecond;
+
+/// Duration of the calibration in microseconds.
+const CALIBRATION_TIME: u64 = 10_000;
+
+/// Calibrated frequency in Hz, zero if not calibrated.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// The TSC clock source.
+pub static TSC_CLOCK_SOURCE: ClockSource = ClockSource {
+  name:   "tsc",
+  rating: 300,
+  read:   read_nanoseconds,
+};
+
+/// Read the time stamp counter.
+#[inline]
+pub fn read_tsc() -> u64 {
+  unsafe { _rdtsc() }
+}
+
+/// Return the calibrated frequency in Hz, or zero if not calibrated.
+#[inline]
+pub fn frequency() -> u64 {
+  TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Return the TSC in nanoseconds.
+pub fn read_nanoseconds() -> NanoSecond {
+  match frequency() {
+    0 => 0,
+    frequency => NanoSecond::from(read_tsc()) * 1_000_000_000 / NanoSecond::from(frequency),
+  }
+}
+
+/// True if the TSC runs at a constant rate in all the power states.
+pub fn is_invariant() -> bool {
+  CpuId::new()
+    .get_advanced_power_mgmt_info()
+    .is_some_and(|info| info.has_invariant_tsc())
+}
+
+/// Measure the TSC frequency in Hz during a busy wait on the PIT. The elapsed time is measured by
+/// the HPET if available, which is more precise than the requested duration.
+fn measure_frequency() -> u64 {
+  without_interrupts(|| {
+    let hpet_start = hpet::read_nanoseconds();
+    let tsc_start = read_tsc();
+    pit::busy_wait(CALIBRATION_TIME);
+    let tsc_end = read_tsc();
+    let hpet_end = hpet::read_nanoseconds();
+
+    let elapsed = if hpet::is_available() {
+      (hpet_end - hpet_start) as u64
+    } else {
+      CALIBRATION_TIME * 1000
+    };
+    (tsc_end - tsc_start) * 1_000_000_000 / elapsed
+  })
+}
+
+/// Calibrate the TSC, and return its frequency in Hz.
+pub fn calibrate_tsc() -> u64 {
+  // Take the best of a few runs, a run may be stretched by an SMI or a virtual machine exit.
+  let frequency = (0..3).map(|_| measure_frequency()).min().unwrap_or(0);
+  TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+  println!(
+    "[INFO ] TSC calibrated against {}: {}.{:03} MHz, invariant: {}.",
+    if hpet::is_available() { "HPET" } else { "PIT" },
+    frequency / 1_000_000,
+    frequency / 1000 % 1000,
+    is_invariant()
+  );
+  frequency
+}
@@ -15,9 +15,10 @@ use core::ops::Deref;
 use core::ops::DerefMut;
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 
+use crate::support::sync::IrqSpinLock;
+
 /// Height of VGA text buffer.
 const BUFFER_HEIGHT: usize = 25;
 /// Width of VGA text buffer.
@@ -25,7 +26,7 @@ const BUFFER_WIDTH: usize = 80;
 
 lazy_static! {
   /// VGA writer instance.
-  pub static ref VGA_WRITER: Mutex<VgaWriter> = Mutex::new(VgaWriter::new());
+  pub static ref VGA_WRITER: IrqSpinLock<VgaWriter> = IrqSpinLock::new(VgaWriter::new());
 }
 
 /// VGA color enumeration.
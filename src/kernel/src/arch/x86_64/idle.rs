@@ -0,0 +1,100 @@
+//! # Idle Loop
+//!
+//! The idle thread of a processor runs the ready threads, and halts the processor until the next
+//! interrupt when there is none. If the APIC timer drives the tick, the tick is stopped while
+//! halted, so that an idle processor is only woken up by the next kernel timer or a device.
+//!
+//! The tick is driven by the bootstrap processor, the application processors only halt. A halted
+//! processor is marked idle, and is woken up with a reschedule IPI when a thread is made ready by
+//! another one, see [`kick_idle_processor`].
+
+use core::sync::atomic::fence;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::x86_64::hw::apic_timer;
+use crate::arch::x86_64::interrupt;
+use crate::arch::x86_64::interrupt::apic::LocalApic;
+use crate::arch::x86_64::interrupt::apic::ICR_DELIVERY_FIXED;
+use crate::arch::x86_64::interrupt::apic::ICR_LEVEL_ASSERT;
+use crate::arch::x86_64::interrupt::apic::RESCHEDULE_VECTOR;
+use crate::arch::x86_64::percpu;
+use crate::arch::x86_64::smp;
+use crate::println;
+use crate::proc::sched;
+
+/// Processors halted in their idle loop, a bit by id.
+static IDLE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+crate::per_cpu! {
+  /// Local APIC ID of the processor, the destination of its reschedule IPIs.
+  static APIC_ID: AtomicU32 = AtomicU32::new(0);
+}
+
+/// Idle forever.
+pub fn idle() -> ! {
+  println!("[TRACE] idle");
+  let bootstrap = smp::is_bootstrap_processor();
+  let local_apic = LocalApic::get();
+  if let Some(local_apic) = local_apic {
+    APIC_ID.get().store(local_apic.id(), Ordering::Relaxed);
+  }
+  let cpu = 1 << percpu::cpu_id();
+  loop {
+    unsafe {
+      interrupt::disable();
+      // Once marked idle, a thread made ready by another processor comes with a reschedule IPI,
+      // which stays pending until the processor halts.
+      if local_apic.is_some() {
+        IDLE_CPUS.fetch_or(cpu, Ordering::SeqCst);
+      }
+      // An interrupt handler may wake a thread until interrupts are disabled.
+      if sched::has_ready_threads() {
+        IDLE_CPUS.fetch_and(!cpu, Ordering::Relaxed);
+        interrupt::enable();
+        sched::yield_now();
+        continue;
+      }
+      let tickless = bootstrap && apic_timer::is_enabled();
+      if tickless {
+        apic_timer::stop_tick();
+      }
+      interrupt::enable_and_halt();
+
+      interrupt::disable();
+      IDLE_CPUS.fetch_and(!cpu, Ordering::Relaxed);
+      if tickless {
+        apic_timer::restart_tick();
+      }
+      interrupt::enable();
+    }
+  }
+}
+
+/// Wake up an idle processor, other than the current one, to run a thread just made ready.
+pub fn kick_idle_processor() {
+  if !percpu::is_installed() {
+    return;
+  }
+  let Some(local_apic) = LocalApic::get() else {
+    return;
+  };
+  // Order the queueing of the thread before the load of the idle processors, as the idle loop
+  // orders the other way around, so that either the processor sees the thread or it's kicked.
+  fence(Ordering::SeqCst);
+  let mut idle = IDLE_CPUS.load(Ordering::Relaxed) & !(1 << percpu::cpu_id());
+  while idle != 0 {
+    let cpu = idle.trailing_zeros() as usize;
+    // Kick each idle processor once, the next ready thread goes to another one.
+    if IDLE_CPUS.fetch_and(!(1 << cpu), Ordering::Relaxed) & (1 << cpu) != 0 {
+      let apic_id = APIC_ID.get_for(cpu).load(Ordering::Relaxed);
+      let command = ICR_DELIVERY_FIXED | ICR_LEVEL_ASSERT | u32::from(RESCHEDULE_VECTOR);
+      // An interrupt handler must not send an IPI between the writes of the command.
+      without_interrupts(|| unsafe { local_apic.send_ipi(apic_id, command) });
+      return;
+    }
+    idle &= !(1 << cpu);
+  }
+}
@@ -0,0 +1,259 @@
+//! # Local APIC
+//!
+//! Each processor has a local APIC, which receives the interrupts from the I/O APICs and other
+//! processors, and owns a timer. It's accessed through MMIO registers in xAPIC mode, or through
+//! MSRs in x2APIC mode, which is used when the processor supports it.
+
+use spin::Once;
+
+use crate::arch::shared::cpuid::CpuId;
+use crate::arch::shared::cpuid::FeatureInfoEcx;
+use crate::arch::shared::cpuid::FeatureInfoEdx;
+use crate::arch::x86_64::acpi::madt::Madt;
+use crate::arch::x86_64::acpi::madt::Polarity;
+use crate::arch::x86_64::acpi::madt::TriggerMode;
+use crate::arch::x86_64::reg::ApicBase;
+use crate::arch::x86_64::reg::ApicBaseFlags;
+use crate::arch::x86_64::reg::Msr;
+use crate::arch::VirtualAddress;
+use crate::mem::mmio::map_mmio;
+
+/// Vector of the spurious interrupts, its low 4 bits must be set on old processors.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// Vector of the APIC errors.
+pub const ERROR_VECTOR: u8 = 0xFE;
+/// Vector of the APIC timer.
+pub const TIMER_VECTOR: u8 = 0xFD;
+/// Vector of the IPIs waking up an idle processor to run the threads made ready.
+pub const RESCHEDULE_VECTOR: u8 = 0xFC;
+
+/// First MSR of the x2APIC registers.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Spurious interrupt vector register: the APIC is software enabled.
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+
+/// LVT: the interrupt is masked.
+pub const LVT_MASKED: u32 = 1 << 16;
+/// LVT: level-triggered.
+const LVT_LEVEL_TRIGGERED: u32 = 1 << 15;
+/// LVT: active low.
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+/// LVT: NMI delivery mode.
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+
+/// ICR: fixed delivery mode, which raises the vector on the destination processor.
+pub const ICR_DELIVERY_FIXED: u32 = 0b000 << 8;
+/// ICR: INIT delivery mode, which resets the destination processor.
+pub const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+/// ICR: start-up delivery mode, the vector gives the page where the destination processor starts.
+pub const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+/// ICR: assert level, required by all the delivery modes but INIT level de-assert.
+pub const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// ICR: the IPI has not been accepted yet, xAPIC only.
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+/// Registers of the local APIC, by their offset in xAPIC mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Register {
+  Id = 0x020,
+  Version = 0x030,
+  TaskPriority = 0x080,
+  EndOfInterrupt = 0x0B0,
+  SpuriousInterruptVector = 0x0F0,
+  ErrorStatus = 0x280,
+  InterruptCommand = 0x300,
+  InterruptCommandHigh = 0x310,
+  LvtTimer = 0x320,
+  LvtThermal = 0x330,
+  LvtPerformance = 0x340,
+  LvtLint0 = 0x350,
+  LvtLint1 = 0x360,
+  LvtError = 0x370,
+  TimerInitialCount = 0x380,
+  TimerCurrentCount = 0x390,
+  TimerDivideConfiguration = 0x3E0,
+}
+
+impl Register {
+  #[inline]
+  fn x2apic_msr(self) -> u32 {
+    X2APIC_MSR_BASE + (self as u32 >> 4)
+  }
+}
+
+/// Access mode of the local APIC.
+#[derive(Clone, Copy, Debug)]
+pub enum LocalApic {
+  /// MMIO registers, mapped at the virtual address.
+  XApic(VirtualAddress),
+  /// MSRs.
+  X2Apic,
+}
+
+/// Local APIC mode, shared by all the processors.
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+/// True if the processor has a local APIC.
+pub fn is_supported() -> bool {
+  CpuId::new()
+    .get_feature_info()
+    .is_some_and(|info| info.edx().contains(FeatureInfoEdx::APIC))
+}
+
+/// True if the processor supports the x2APIC mode.
+fn is_x2apic_supported() -> bool {
+  CpuId::new()
+    .get_feature_info()
+    .is_some_and(|info| info.ecx().contains(FeatureInfoEcx::X2APIC))
+}
+
+impl LocalApic {
+  /// Return the local APIC, if initialized.
+  #[inline]
+  pub fn get() -> Option<LocalApic> {
+    LOCAL_APIC.r#try().copied()
+  }
+
+  /// ## Safety
+  /// Reading some registers has side effects.
+  #[inline]
+  pub unsafe fn read(&self, register: Register) -> u32 {
+    match *self {
+      LocalApic::XApic(base) => {
+        core::ptr::read_volatile((base + u64::from(register as u32)).as_ptr::<u32>())
+      }
+      LocalApic::X2Apic => Msr::new(register.x2apic_msr()).read() as u32,
+    }
+  }
+
+  /// ## Safety
+  /// Writing the registers changes the delivery of interrupts.
+  #[inline]
+  pub unsafe fn write(&self, register: Register, value: u32) {
+    match *self {
+      LocalApic::XApic(base) => {
+        core::ptr::write_volatile(
+          (base + u64::from(register as u32)).as_mut_ptr::<u32>(),
+          value,
+        );
+      }
+      LocalApic::X2Apic => Msr::new(register.x2apic_msr()).write(u64::from(value)),
+    }
+  }
+
+  /// Return the APIC ID of the current processor.
+  pub fn id(&self) -> u32 {
+    let id = unsafe { self.read(Register::Id) };
+    match self {
+      LocalApic::XApic(_) => id >> 24,
+      LocalApic::X2Apic => id,
+    }
+  }
+
+  /// Return the version of the local APIC.
+  pub fn version(&self) -> u8 {
+    unsafe { self.read(Register::Version) as u8 }
+  }
+
+  /// Signal the end of the interrupt being handled.
+  #[inline]
+  pub fn end_of_interrupt(&self) {
+    unsafe { self.write(Register::EndOfInterrupt, 0) };
+  }
+
+  /// Send an inter-processor interrupt to the local APIC `destination`, and wait until it's
+  /// accepted. `command` is the low 32 bits of the interrupt command register.
+  ///
+  /// ## Safety
+  /// IPIs such as INIT reset the destination processor.
+  pub unsafe fn send_ipi(&self, destination: u32, command: u32) {
+    match *self {
+      LocalApic::XApic(_) => {
+        self.write(Register::InterruptCommandHigh, destination << 24);
+        self.write(Register::InterruptCommand, command);
+        while self.read(Register::InterruptCommand) & ICR_DELIVERY_PENDING != 0 {
+          core::hint::spin_loop();
+        }
+      }
+      LocalApic::X2Apic => {
+        let value = (u64::from(destination) << 32) | u64::from(command);
+        Msr::new(Register::InterruptCommand.x2apic_msr()).write(value);
+      }
+    }
+  }
+
+  /// Enable the local APIC of the current processor, with all the local interrupts masked except
+  /// the errors and the NMIs described by the MADT.
+  ///
+  /// ## Safety
+  /// Interrupts must be disabled, and each processor must enable its local APIC once.
+  pub unsafe fn enable(&self, madt: &Madt) {
+    let (address, mut flags) = ApicBase::read();
+    flags |= ApicBaseFlags::ENABLE;
+    ApicBase::write(address, flags);
+    // The x2APIC mode is entered from the xAPIC mode, going from disabled to x2APIC is invalid.
+    if let LocalApic::X2Apic = self {
+      flags |= ApicBaseFlags::X2APIC;
+      ApicBase::write(address, flags);
+    }
+
+    // Accept all the interrupts.
+    self.write(Register::TaskPriority, 0);
+
+    self.write(Register::LvtTimer, LVT_MASKED);
+    self.write(Register::LvtThermal, LVT_MASKED);
+    self.write(Register::LvtPerformance, LVT_MASKED);
+    // LINT0 is the virtual wire of the 8259 PICs, which are disabled.
+    self.write(Register::LvtLint0, LVT_MASKED);
+    self.write(Register::LvtLint1, LVT_MASKED);
+
+    let uid = madt.processor_uid(self.id());
+    for nmi in madt.nmis.iter().filter(|nmi| nmi.uid.is_none() || nmi.uid == uid) {
+      let mut lvt = LVT_DELIVERY_NMI;
+      if nmi.polarity == Polarity::ActiveLow {
+        lvt |= LVT_ACTIVE_LOW;
+      }
+      if nmi.trigger == TriggerMode::Level {
+        lvt |= LVT_LEVEL_TRIGGERED;
+      }
+      match nmi.lint {
+        0 => self.write(Register::LvtLint0, lvt),
+        1 => self.write(Register::LvtLint1, lvt),
+        _ => {}
+      }
+    }
+
+    self.write(Register::LvtError, u32::from(ERROR_VECTOR));
+    // The error status register must be written before it's read.
+    self.write(Register::ErrorStatus, 0);
+    self.write(Register::ErrorStatus, 0);
+
+    self.write(
+      Register::SpuriousInterruptVector,
+      SVR_APIC_ENABLE | u32::from(SPURIOUS_VECTOR),
+    );
+    self.end_of_interrupt();
+  }
+}
+
+/// Select the mode of the local APIC, and map its registers for the xAPIC mode.
+///
+/// ## Safety
+/// The local APIC address must come from the MADT.
+pub unsafe fn init_local_apic(madt: &Madt) -> LocalApic {
+  *LOCAL_APIC.call_once(|| {
+    if is_x2apic_supported() {
+      LocalApic::X2Apic
+    } else {
+      let address = if madt.local_apic_address.as_raw() != 0 {
+        madt.local_apic_address
+      } else {
+        ApicBase::read().0
+      };
+      let base = map_mmio(address, 0x1000).expect("Failed to map the local APIC");
+      LocalApic::XApic(base)
+    }
+  })
+}
@@ -0,0 +1,299 @@
+//! # CPU Exceptions
+//!
+//! Handlers for the exceptions of vectors 0 to 31. Each handler prints the exception name with a
+//! register dump. Breakpoint and debug exceptions resume execution, the others are fatal.
+//!
+//! The entry stubs are written in assembly, so that the general-purpose registers of the
+//! interrupted code are saved before the compiler uses them. Each stub pushes a zero error code if
+//! the processor pushed none, and its vector, then the common entry pushes the registers, and
+//! passes the resulting [`ExceptionFrame`] to [`exception_handler`]. The stubs are 16 bytes apart,
+//! so that the stub of a vector is found from the first one.
+
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::ptr::addr_of;
+
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::VirtAddr;
+
+use crate::arch::paging::PageTableFlags;
+use crate::arch::x86_64::gdt::DOUBLE_FAULT_IST_INDEX;
+use crate::arch::x86_64::reg::CR2;
+use crate::arch::x86_64::reg::CR3;
+use crate::arch::x86_64::translate_address_flags;
+use crate::arch::VirtualAddress;
+use crate::mem::is_user_address;
+use crate::mem::physical_memory_offset;
+use crate::println;
+
+/// Distance between the entry stubs.
+const STUB_SIZE: u64 = 16;
+
+global_asm!(
+  r#"
+  .section .text
+  .macro exception_stub num
+  .balign 16
+  .if (\num == 8) || (\num >= 10 && \num <= 14) || (\num == 17) || (\num == 21)
+  .elseif (\num == 29) || (\num == 30)
+  .else
+  push 0
+  .endif
+  push \num
+  jmp exception_entry
+  .endm
+
+  .balign 16
+exception_stubs:
+  .irp num, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+  exception_stub \num
+  .endr
+  .irp num, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31
+  exception_stub \num
+  .endr
+
+exception_entry:
+  push rax
+  push rbx
+  push rcx
+  push rdx
+  push rsi
+  push rdi
+  push rbp
+  push r8
+  push r9
+  push r10
+  push r11
+  push r12
+  push r13
+  push r14
+  push r15
+  mov rdi, rsp
+  cld
+  call {handler}
+  pop r15
+  pop r14
+  pop r13
+  pop r12
+  pop r11
+  pop r10
+  pop r9
+  pop r8
+  pop rbp
+  pop rdi
+  pop rsi
+  pop rdx
+  pop rcx
+  pop rbx
+  pop rax
+  add rsp, 16
+  iretq
+"#,
+  handler = sym exception_handler,
+);
+
+extern "C" {
+  static exception_stubs: u8;
+}
+
+/// Registers of the interrupted code, saved by the entry stubs and the processor.
+#[derive(Debug)]
+#[repr(C)]
+struct ExceptionFrame {
+  r15:        u64,
+  r14:        u64,
+  r13:        u64,
+  r12:        u64,
+  r11:        u64,
+  r10:        u64,
+  r9:         u64,
+  r8:         u64,
+  rbp:        u64,
+  rdi:        u64,
+  rsi:        u64,
+  rdx:        u64,
+  rcx:        u64,
+  rbx:        u64,
+  rax:        u64,
+  vector:     u64,
+  /// Zero if the exception has no error code.
+  error_code: u64,
+  rip:        u64,
+  cs:         u64,
+  rflags:     u64,
+  rsp:        u64,
+  ss:         u64,
+}
+
+// The stack is aligned to 16 bytes once the processor pushed its frame, and must be again when the
+// handler is called.
+const _: () = assert!(size_of::<ExceptionFrame>().is_multiple_of(16));
+
+/// Names of the exceptions, by vector.
+const EXCEPTION_NAMES: [&str; 32] = [
+  "DIVIDE ERROR",
+  "DEBUG",
+  "NON-MASKABLE INTERRUPT",
+  "BREAKPOINT",
+  "OVERFLOW",
+  "BOUND RANGE EXCEEDED",
+  "INVALID OPCODE",
+  "DEVICE NOT AVAILABLE",
+  "DOUBLE FAULT",
+  "COPROCESSOR SEGMENT OVERRUN",
+  "INVALID TSS",
+  "SEGMENT NOT PRESENT",
+  "STACK SEGMENT FAULT",
+  "GENERAL PROTECTION FAULT",
+  "PAGE FAULT",
+  "RESERVED",
+  "X87 FLOATING POINT",
+  "ALIGNMENT CHECK",
+  "MACHINE CHECK",
+  "SIMD FLOATING POINT",
+  "VIRTUALIZATION",
+  "CONTROL PROTECTION",
+  "RESERVED",
+  "RESERVED",
+  "RESERVED",
+  "RESERVED",
+  "RESERVED",
+  "RESERVED",
+  "HYPERVISOR INJECTION",
+  "VMM COMMUNICATION",
+  "SECURITY",
+  "RESERVED",
+];
+
+/// True if the processor pushes an error code for the exception, as listed in the entry stubs.
+fn has_error_code(vector: u64) -> bool {
+  matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+}
+
+/// Return the address of the entry stub of the vector.
+fn stub(vector: u64) -> VirtAddr {
+  VirtAddr::from_ptr(unsafe { addr_of!(exception_stubs) }) + vector * STUB_SIZE
+}
+
+/// Install the exception handlers into the IDT.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+  unsafe {
+    idt.divide_error.set_handler_addr(stub(0));
+    idt.debug.set_handler_addr(stub(1));
+    idt.non_maskable_interrupt.set_handler_addr(stub(2));
+    idt.breakpoint.set_handler_addr(stub(3));
+    idt.overflow.set_handler_addr(stub(4));
+    idt.bound_range_exceeded.set_handler_addr(stub(5));
+    idt.invalid_opcode.set_handler_addr(stub(6));
+    idt.device_not_available.set_handler_addr(stub(7));
+    // The kernel stack may be the culprit, e.g. a stack overflow, so switch to a known good one.
+    idt
+      .double_fault
+      .set_handler_addr(stub(8))
+      .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+    idt.invalid_tss.set_handler_addr(stub(10));
+    idt.segment_not_present.set_handler_addr(stub(11));
+    idt.stack_segment_fault.set_handler_addr(stub(12));
+    idt.general_protection_fault.set_handler_addr(stub(13));
+    idt.page_fault.set_handler_addr(stub(14));
+    idt.x87_floating_point.set_handler_addr(stub(16));
+    idt.alignment_check.set_handler_addr(stub(17));
+    idt.machine_check.set_handler_addr(stub(18));
+    idt.simd_floating_point.set_handler_addr(stub(19));
+    idt.virtualization.set_handler_addr(stub(20));
+    idt.cp_protection_exception.set_handler_addr(stub(21));
+    idt.hv_injection_exception.set_handler_addr(stub(28));
+    idt.vmm_communication_exception.set_handler_addr(stub(29));
+    idt.security_exception.set_handler_addr(stub(30));
+  }
+}
+
+/// Print the registers saved on exception, and the control registers.
+fn dump(frame: &ExceptionFrame) {
+  let (cr3, _) = CR3::read();
+  println!(
+    "[ERROR] EXCEPTION: {}",
+    EXCEPTION_NAMES[frame.vector as usize]
+  );
+  if has_error_code(frame.vector) {
+    println!("        Error code: {:#x}", frame.error_code);
+  }
+  println!("        RIP:    {:#018x}  CS: {:#06x}", frame.rip, frame.cs);
+  println!("        RSP:    {:#018x}  SS: {:#06x}", frame.rsp, frame.ss);
+  println!("        RFLAGS: {:#018x}", frame.rflags);
+  println!(
+    "        RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}",
+    frame.rax, frame.rbx, frame.rcx
+  );
+  println!(
+    "        RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}",
+    frame.rdx, frame.rsi, frame.rdi
+  );
+  println!(
+    "        RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}",
+    frame.rbp, frame.r8, frame.r9
+  );
+  println!(
+    "        R10: {:#018x}  R11: {:#018x}  R12: {:#018x}",
+    frame.r10, frame.r11, frame.r12
+  );
+  println!(
+    "        R13: {:#018x}  R14: {:#018x}  R15: {:#018x}",
+    frame.r13, frame.r14, frame.r15
+  );
+  println!("        CR2:    {:#018x}", CR2::read().as_raw());
+  println!("        CR3:    {:#018x}", cr3.start_address().as_raw());
+}
+
+/// Handle the exception of the frame, called by the common entry stub. The debug and breakpoint
+/// exceptions return to the interrupted code, the others panic.
+extern "C" fn exception_handler(frame: &ExceptionFrame) {
+  dump(frame);
+  match frame.vector {
+    1 | 3 => return,
+    14 => {
+      let address = CR2::read();
+      let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+      println!("        Accessed address: {:?}", address);
+      println!("        {:?}", error_code);
+      if !error_code.contains(PageFaultErrorCode::USER_MODE) {
+        explain_kernel_page_fault(address, error_code);
+      }
+    }
+    _ => {}
+  }
+  panic!("EXCEPTION: {}", EXCEPTION_NAMES[frame.vector as usize]);
+}
+
+/// Print the likely cause of a page fault in the kernel, from the flags of the faulting page.
+fn explain_kernel_page_fault(address: VirtualAddress, error_code: PageFaultErrorCode) {
+  let offset = physical_memory_offset();
+  // The page tables are not reachable before the memory is initialized.
+  let mapping = if offset.as_raw() != 0 {
+    translate_address_flags(address, offset)
+  } else {
+    None
+  };
+  let fetch = error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH);
+  match mapping {
+    Some((_, flags)) if flags.contains(PageTableFlags::USER_ACCESSIBLE) => {
+      if fetch {
+        println!("        SMEP: the kernel executed a user page.");
+      } else {
+        println!(
+          "        SMAP: the kernel accessed a user page outside of copy_from_user/copy_to_user."
+        );
+      }
+    }
+    Some(_) if fetch => println!("        The kernel executed a non-executable page."),
+    Some(_) if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) => {
+      println!("        The kernel wrote to a read-only page.")
+    }
+    Some(_) => {}
+    None if is_user_address(address) => {
+      println!("        The kernel accessed an unmapped user address.")
+    }
+    None => println!("        The kernel accessed an unmapped address."),
+  }
+}
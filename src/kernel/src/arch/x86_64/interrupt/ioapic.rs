@@ -0,0 +1,203 @@
+//! # I/O APIC
+//!
+//! The I/O APICs route the external interrupts to the local APICs. Each input pin is a global
+//! system interrupt (GSI), programmed by an entry of the redirection table. The ISA IRQs are
+//! mapped to GSIs according to the interrupt source overrides of the MADT.
+
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::arch::x86_64::acpi::madt::InterruptSourceOverride;
+use crate::arch::x86_64::acpi::madt::Madt;
+use crate::arch::x86_64::acpi::madt::Polarity;
+use crate::arch::x86_64::acpi::madt::TriggerMode;
+use crate::arch::VirtualAddress;
+use crate::mem::mmio::map_mmio;
+use crate::mem::mmio::MmioError;
+use crate::println;
+
+/// Register selector.
+const IOREGSEL: u64 = 0x00;
+/// Register window.
+const IOWIN: u64 = 0x10;
+
+const IOAPICID: u32 = 0x00;
+const IOAPICVER: u32 = 0x01;
+/// First register of the redirection table, each entry takes two registers.
+const IOREDTBL: u32 = 0x10;
+
+/// Redirection entry: the interrupt is masked.
+const REDIRECTION_MASKED: u64 = 1 << 16;
+/// Redirection entry: level-triggered.
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+/// Redirection entry: active low.
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+
+/// Highest local APIC ID in the 8-bit destination field of a redirection entry.
+const MAX_DESTINATION: u32 = 0xFF;
+
+/// The I/O APICs of the system.
+static IO_APICS: Mutex<Vec<IoApic>> = Mutex::new(Vec::new());
+
+/// ISA IRQs which are not identity-mapped, from the MADT.
+static OVERRIDES: Mutex<Vec<InterruptSourceOverride>> = Mutex::new(Vec::new());
+
+/// An I/O APIC, whose registers are mapped at `base`.
+pub struct IoApic {
+  id:       u8,
+  base:     VirtualAddress,
+  /// First GSI handled by this I/O APIC.
+  gsi_base: u32,
+  /// Count of redirection entries.
+  entries:  u32,
+}
+
+impl IoApic {
+  /// ## Safety
+  /// The registers of an I/O APIC must be mapped at `base`.
+  unsafe fn new(id: u8, base: VirtualAddress, gsi_base: u32) -> Self {
+    let mut io_apic = Self {
+      id,
+      base,
+      gsi_base,
+      entries: 0,
+    };
+    io_apic.entries = ((io_apic.read(IOAPICVER) >> 16) & 0xFF) + 1;
+    io_apic
+  }
+
+  unsafe fn read(&self, register: u32) -> u32 {
+    core::ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr::<u32>(), register);
+    core::ptr::read_volatile((self.base + IOWIN).as_ptr::<u32>())
+  }
+
+  unsafe fn write(&self, register: u32, value: u32) {
+    core::ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr::<u32>(), register);
+    core::ptr::write_volatile((self.base + IOWIN).as_mut_ptr::<u32>(), value);
+  }
+
+  /// True if the GSI is an input pin of this I/O APIC.
+  #[inline]
+  fn handles(&self, gsi: u32) -> bool {
+    (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+  }
+
+  unsafe fn read_entry(&self, gsi: u32) -> u64 {
+    let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+    u64::from(self.read(register)) | (u64::from(self.read(register + 1)) << 32)
+  }
+
+  unsafe fn write_entry(&self, gsi: u32, entry: u64) {
+    let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+    // Write the destination first, the low half may unmask the entry.
+    self.write(register + 1, (entry >> 32) as u32);
+    self.write(register, entry as u32);
+  }
+}
+
+/// Map the I/O APICs described by the MADT, with all their entries masked. None is kept if one
+/// fails to be mapped.
+///
+/// ## Safety
+/// Interrupts must be disabled.
+pub unsafe fn init_io_apics(madt: &Madt) -> Result<(), MmioError> {
+  let mut io_apics = Vec::with_capacity(madt.io_apics.len());
+  for entry in &madt.io_apics {
+    let base = map_mmio(entry.address, 0x20)?;
+    let io_apic = IoApic::new(entry.id, base, entry.gsi_base);
+    for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+      io_apic.write_entry(gsi, REDIRECTION_MASKED);
+    }
+    println!(
+      "[DEBUG] I/O APIC {}: {:?}, GSI {}..{}.",
+      io_apic.id,
+      entry.address,
+      io_apic.gsi_base,
+      io_apic.gsi_base + io_apic.entries
+    );
+    io_apics.push(io_apic);
+  }
+  *IO_APICS.lock() = io_apics;
+  *OVERRIDES.lock() = madt.overrides.clone();
+  Ok(())
+}
+
+/// Error occurred when routing an IRQ through the I/O APICs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+  /// The x2APIC ID does not fit the destination field, it's only reachable through interrupt
+  /// remapping.
+  DestinationOutOfRange(u32),
+  /// No I/O APIC handles the GSI.
+  NoIoApic(u32),
+}
+
+/// Resolve the ISA IRQ to its GSI, polarity and trigger mode.
+fn isa_irq(irq: u8) -> InterruptSourceOverride {
+  OVERRIDES.lock().iter().find(|entry| entry.irq == irq).copied().unwrap_or(
+    InterruptSourceOverride {
+      irq,
+      gsi: u32::from(irq),
+      polarity: Polarity::ActiveHigh,
+      trigger: TriggerMode::Edge,
+    },
+  )
+}
+
+/// Route the ISA IRQ to the vector on the local APIC `destination`, masked.
+///
+/// ## Safety
+/// The vector must have a handler before the IRQ is unmasked.
+pub unsafe fn route_isa_irq(irq: u8, vector: u8, destination: u32) -> Result<(), RouteError> {
+  if destination > MAX_DESTINATION {
+    return Err(RouteError::DestinationOutOfRange(destination));
+  }
+  let source = isa_irq(irq);
+  let mut entry = u64::from(vector) | REDIRECTION_MASKED | (u64::from(destination) << 56);
+  if source.polarity == Polarity::ActiveLow {
+    entry |= REDIRECTION_ACTIVE_LOW;
+  }
+  if source.trigger == TriggerMode::Level {
+    entry |= REDIRECTION_LEVEL_TRIGGERED;
+  }
+
+  let io_apics = IO_APICS.lock();
+  let io_apic = io_apics
+    .iter()
+    .find(|io_apic| io_apic.handles(source.gsi))
+    .ok_or(RouteError::NoIoApic(source.gsi))?;
+  io_apic.write_entry(source.gsi, entry);
+  Ok(())
+}
+
+/// Set or clear the mask bit of the ISA IRQ.
+unsafe fn set_isa_irq_masked(irq: u8, masked: bool) {
+  let gsi = isa_irq(irq).gsi;
+  let io_apics = IO_APICS.lock();
+  if let Some(io_apic) = io_apics.iter().find(|io_apic| io_apic.handles(gsi)) {
+    let entry = io_apic.read_entry(gsi);
+    let entry = if masked {
+      entry | REDIRECTION_MASKED
+    } else {
+      entry & !REDIRECTION_MASKED
+    };
+    io_apic.write_entry(gsi, entry);
+  }
+}
+
+/// Mask the ISA IRQ.
+///
+/// ## Safety
+/// Masking an IRQ may lose interrupts which some driver waits for.
+pub unsafe fn mask_isa_irq(irq: u8) {
+  set_isa_irq_masked(irq, true);
+}
+
+/// Unmask the ISA IRQ.
+///
+/// ## Safety
+/// The IRQ must have been routed to a vector with a handler.
+pub unsafe fn unmask_isa_irq(irq: u8) {
+  set_isa_irq_masked(irq, false);
+}
@@ -0,0 +1,60 @@
+//! # IRQ Stubs
+//!
+//! Handlers for the vectors of the ISA IRQs 0 to 15. Each stub dispatches its IRQ to the
+//! registered handlers, and then signals the end of interrupt to the interrupt controller. The
+//! spurious IRQs of the PICs are not dispatched.
+
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::arch::shared::irq::handle_irq;
+use crate::arch::shared::irq::Irq;
+use crate::arch::x86_64::interrupt::end_of_interrupt;
+use crate::arch::x86_64::interrupt::is_spurious_interrupt;
+use crate::arch::x86_64::interrupt::IRQ_BASE_VECTOR;
+use crate::arch::x86_64::percpu::InterruptGuard;
+
+/// Define the IRQ stubs, and install them into the IDT.
+macro_rules! irq_handlers {
+  ($($irq:literal => $handler:ident),* $(,)?) => {
+    $(
+      extern "x86-interrupt" fn $handler(frame: InterruptStackFrame) {
+        let _guard = InterruptGuard::enter(&frame);
+        dispatch($irq);
+      }
+    )*
+
+    /// Install the IRQ stubs into the IDT.
+    pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+      $(idt[IRQ_BASE_VECTOR + $irq].set_handler_fn($handler);)*
+    }
+  };
+}
+
+irq_handlers! {
+  0 => irq_0_handler,
+  1 => irq_1_handler,
+  2 => irq_2_handler,
+  3 => irq_3_handler,
+  4 => irq_4_handler,
+  5 => irq_5_handler,
+  6 => irq_6_handler,
+  7 => irq_7_handler,
+  8 => irq_8_handler,
+  9 => irq_9_handler,
+  10 => irq_10_handler,
+  11 => irq_11_handler,
+  12 => irq_12_handler,
+  13 => irq_13_handler,
+  14 => irq_14_handler,
+  15 => irq_15_handler,
+}
+
+#[inline]
+fn dispatch(irq: u8) {
+  let vector = IRQ_BASE_VECTOR + irq;
+  if !is_spurious_interrupt(vector) {
+    handle_irq(Irq::from(irq));
+  }
+  end_of_interrupt(vector);
+}
@@ -1,13 +1,213 @@
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
 use lazy_static::lazy_static;
 use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::structures::idt::InterruptStackFrame;
 
+pub use crate::arch::shared::irq::irqs;
+pub use crate::arch::shared::irq::register_irq;
+pub use crate::arch::shared::irq::unregister_irq;
+use crate::arch::shared::irq::Irq;
+use crate::arch::shared::irq::IrqError;
+use crate::arch::x86_64::acpi::madt::Madt;
+use crate::arch::x86_64::hw::apic_timer;
+use crate::arch::x86_64::interrupt::apic::init_local_apic;
+use crate::arch::x86_64::interrupt::apic::LocalApic;
+use crate::arch::x86_64::interrupt::apic::Register;
+use crate::arch::x86_64::interrupt::ioapic::init_io_apics;
+use crate::arch::x86_64::interrupt::pic::CASCADE_IRQ;
+use crate::arch::x86_64::interrupt::pic::PICS;
+use crate::arch::x86_64::interrupt::pic::PIC_1_OFFSET;
+use crate::arch::x86_64::percpu::InterruptGuard;
+use crate::arch::x86_64::reg::RFlags;
+use crate::arch::x86_64::reg::RFLAGS;
+use crate::mem::mmio::MmioError;
 use crate::println;
 
+pub mod apic;
+mod exception;
+pub mod ioapic;
+mod irq;
+pub mod pic;
+
+/// Count of IRQs, i.e. the ISA IRQs.
+pub const NR_IRQS: usize = 16;
+
+/// Vector of IRQ 0, the ISA IRQs are mapped to the following vectors on both the PICs and APICs.
+pub const IRQ_BASE_VECTOR: u8 = PIC_1_OFFSET;
+
+lazy_static! {
+  /// # IDT - Interrupt Descriptor Table
+  static ref IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    exception::install(&mut idt);
+    irq::install(&mut idt);
+    idt[apic::TIMER_VECTOR].set_handler_fn(apic_timer_interrupt_handler);
+    idt[apic::ERROR_VECTOR].set_handler_fn(apic_error_interrupt_handler);
+    idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+    idt[apic::RESCHEDULE_VECTOR].set_handler_fn(reschedule_interrupt_handler);
+    idt
+  };
+}
+
 pub fn init_idt() {
   println!("[INFO ] Initialize IDT.");
-  // IDT.load();
+  IDT.load();
+}
+
+/// Load the IDT, shared by all the processors, on an application processor.
+pub fn init_ap_idt() {
+  IDT.load();
+}
+
+/// True once the APICs took over from the 8259 PICs.
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set up the interrupt controllers, with all the IRQs masked until a handler is registered.
+///
+/// The local APIC and I/O APICs described by the MADT are used if available, otherwise the 8259
+/// PICs.
+pub fn init_interrupt_controller() {
+  match Madt::parse() {
+    Ok(madt) if apic::is_supported() && !madt.io_apics.is_empty() => {
+      if let Err(err) = unsafe { init_apic(&madt) } {
+        println!(
+          "[ERROR] Failed to map the I/O APICs: {:?}, fall back to the 8259 PIC.",
+          err
+        );
+        init_pic();
+      }
+    }
+    Ok(_) => {
+      println!("[INFO ] No usable APIC, fall back to the 8259 PIC.");
+      init_pic();
+    }
+    Err(err) => {
+      println!(
+        "[ERROR] Failed to parse the MADT: {:?}, fall back to the 8259 PIC.",
+        err
+      );
+      init_pic();
+    }
+  }
+}
+
+/// Disable the 8259 PICs, enable the local APIC of the bootstrap processor, and route the ISA
+/// IRQs to it through the I/O APICs. The PICs are left untouched if the I/O APICs cannot be
+/// mapped.
+///
+/// ## Safety
+/// Interrupts must be disabled.
+unsafe fn init_apic(madt: &Madt) -> Result<(), MmioError> {
+  println!("[INFO ] Initialize APIC.");
+  init_io_apics(madt)?;
+  if madt.pcat_compatible {
+    // Remap the PICs anyway, so that their spurious interrupts do not look like exceptions.
+    let mut pics = PICS.lock();
+    pics.initialize();
+    pics.disable();
+  }
+
+  let local_apic = init_local_apic(madt);
+  local_apic.enable(madt);
+  println!(
+    "[DEBUG] Local APIC {:?}, ID {}, version {:#x}.",
+    local_apic,
+    local_apic.id(),
+    local_apic.version()
+  );
+
+  APIC_ENABLED.store(true, Ordering::Release);
+
+  for irq in (0..NR_IRQS as u8).filter(|&irq| irq != CASCADE_IRQ) {
+    if let Err(err) = ioapic::route_isa_irq(irq, IRQ_BASE_VECTOR + irq, local_apic.id()) {
+      println!("[ERROR] Failed to route IRQ {}: {:?}.", irq, err);
+    }
+  }
+  Ok(())
+}
+
+/// Remap the 8259 PICs above the exceptions.
+pub fn init_pic() {
+  println!("[INFO ] Initialize PIC.");
+  unsafe { PICS.lock().initialize() };
+}
+
+/// Mask the ISA IRQ on the interrupt controller in use.
+///
+/// ## Safety
+/// Masking an IRQ may lose interrupts which some driver waits for.
+pub unsafe fn mask_irq(irq: Irq) -> Result<(), IrqError> {
+  let irq = irq as u8;
+  if APIC_ENABLED.load(Ordering::Acquire) {
+    ioapic::mask_isa_irq(irq);
+  } else {
+    PICS.lock().mask(irq);
+  }
+  Ok(())
+}
+
+/// Unmask the ISA IRQ on the interrupt controller in use.
+///
+/// ## Safety
+/// The vector of the IRQ must have a handler.
+pub unsafe fn unmask_irq(irq: Irq) -> Result<(), IrqError> {
+  let irq = irq as u8;
+  if APIC_ENABLED.load(Ordering::Acquire) {
+    ioapic::unmask_isa_irq(irq);
+  } else {
+    PICS.lock().unmask(irq);
+  }
+  Ok(())
+}
+
+/// Signal the end of the interrupt of the vector to the interrupt controller in use.
+#[inline]
+fn end_of_interrupt(vector: u8) {
+  match LocalApic::get() {
+    Some(local_apic) if APIC_ENABLED.load(Ordering::Acquire) => local_apic.end_of_interrupt(),
+    _ => unsafe { PICS.lock().notify_end_of_interrupt(vector) },
+  }
+}
+
+/// True if the vector is a spurious IRQ of the 8259 PICs in use, which must not be handled.
+#[inline]
+fn is_spurious_interrupt(vector: u8) -> bool {
+  !APIC_ENABLED.load(Ordering::Acquire) && unsafe { PICS.lock().is_spurious(vector) }
+}
+
+extern "x86-interrupt" fn apic_timer_interrupt_handler(frame: InterruptStackFrame) {
+  let _guard = InterruptGuard::enter(&frame);
+  apic_timer::timer_interrupt();
+  if let Some(local_apic) = LocalApic::get() {
+    local_apic.end_of_interrupt();
+  }
+}
+
+extern "x86-interrupt" fn apic_error_interrupt_handler(frame: InterruptStackFrame) {
+  let _guard = InterruptGuard::enter(&frame);
+  if let Some(local_apic) = LocalApic::get() {
+    // The error status register latches the errors when it's written.
+    unsafe {
+      local_apic.write(Register::ErrorStatus, 0);
+      let _status = local_apic.read(Register::ErrorStatus);
+    }
+    local_apic.end_of_interrupt();
+  }
 }
 
+/// The idle loop runs the threads made ready once the handler returns.
+extern "x86-interrupt" fn reschedule_interrupt_handler(frame: InterruptStackFrame) {
+  let _guard = InterruptGuard::enter(&frame);
+  if let Some(local_apic) = LocalApic::get() {
+    local_apic.end_of_interrupt();
+  }
+}
+
+/// Spurious interrupts of the local APIC must not be acknowledged.
+extern "x86-interrupt" fn spurious_interrupt_handler(_frame: InterruptStackFrame) {}
+
 /// Enable interrupts.
 ///
 /// x86_64 assemble instruction `STI` to enable the interrupts.
@@ -42,6 +242,12 @@ pub unsafe fn disable() {
   }
 }
 
+/// True if interrupts are enabled, i.e. the interrupt flag of RFLAGS is set.
+#[inline(always)]
+pub fn are_enabled() -> bool {
+  RFLAGS::read().contains(RFlags::INTERRUPT_FLAG)
+}
+
 /// Halt instruction.
 ///
 /// ## Safety
@@ -50,6 +256,36 @@ pub unsafe fn halt() {
   core::arch::asm!("hlt", options(nomem, nostack));
 }
 
+/// Save RFLAGS and disable interrupts, for [`restore`].
+#[inline(always)]
+pub fn save_and_disable() -> RFlags {
+  let flags = RFLAGS::read();
+  unsafe { disable() };
+  flags
+}
+
+/// Restore the interrupt flag saved by [`save_and_disable`].
+///
+/// ## Safety
+/// Enabling interrupts may run the handlers in the middle of a critical section.
+#[inline(always)]
+pub unsafe fn restore(flags: RFlags) {
+  if flags.contains(RFlags::INTERRUPT_FLAG) {
+    enable();
+  }
+}
+
+/// Enable interrupts and halt until the next interrupt.
+///
+/// The interrupts are enabled after the next instruction, so that no interrupt is handled before
+/// halting, which could otherwise sleep through a wakeup.
+///
+/// ## Safety
+#[inline(always)]
+pub unsafe fn enable_and_halt() {
+  core::arch::asm!("sti; hlt", options(nomem, nostack));
+}
+
 /// Pause instruction.
 #[inline(always)]
 pub fn pause() {
@@ -57,3 +293,12 @@ pub fn pause() {
     core::arch::asm!("pause", options(nomem, nostack));
   }
 }
+
+#[cfg(test)]
+mod tests {
+  #[test_case]
+  fn test_breakpoint_exception() {
+    // Execution must resume after the breakpoint exception.
+    x86_64::instructions::interrupts::int3();
+  }
+}
@@ -0,0 +1,205 @@
+//! # 8259 PIC
+//!
+//! The legacy programmable interrupt controller pair. The master PIC handles IRQ 0 to 7, and the
+//! slave PIC, cascaded on IRQ 2 of the master, handles IRQ 8 to 15.
+//!
+//! By default the PICs deliver IRQs on vectors 0 to 15, which overlap the CPU exceptions, so they
+//! are remapped right above the exception range.
+
+use spin::Mutex;
+
+use crate::arch::x86_64::hw::port::Port;
+
+/// Vector of IRQ 0.
+pub const PIC_1_OFFSET: u8 = 32;
+/// Vector of IRQ 8.
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// IRQ of the slave PIC on the master PIC.
+pub const CASCADE_IRQ: u8 = 2;
+
+/// ICW1: Start initialization, and ICW4 is present.
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086 mode.
+const ICW4_8086: u8 = 0x01;
+/// OCW2: Non-specific end of interrupt.
+const OCW2_END_OF_INTERRUPT: u8 = 0x20;
+/// OCW3: Read the in-service register on the next read of the command port.
+const OCW3_READ_ISR: u8 = 0x0B;
+/// IRQ raised by a PIC, on its lowest priority line, when the IRQ it was delivering went away.
+const SPURIOUS_IRQ: u8 = 7;
+
+/// Global chained PICs.
+pub static PICS: Mutex<ChainedPics> =
+  Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+struct Pic {
+  /// Vector of the first IRQ of this PIC.
+  offset:  u8,
+  command: Port<u8>,
+  data:    Port<u8>,
+}
+
+impl Pic {
+  #[inline]
+  fn handles_interrupt(&self, vector: u8) -> bool {
+    (self.offset..self.offset + 8).contains(&vector)
+  }
+
+  #[inline]
+  unsafe fn end_of_interrupt(&self) {
+    self.command.write(OCW2_END_OF_INTERRUPT);
+  }
+
+  /// Return the in-service register, whose bits are the IRQs being handled.
+  #[inline]
+  unsafe fn read_in_service(&self) -> u8 {
+    self.command.write(OCW3_READ_ISR);
+    self.command.read()
+  }
+
+  /// True if the vector is the spurious IRQ of this PIC: it's not in service.
+  #[inline]
+  unsafe fn is_spurious(&self, vector: u8) -> bool {
+    vector == self.offset + SPURIOUS_IRQ && self.read_in_service() & (1 << SPURIOUS_IRQ) == 0
+  }
+
+  #[inline]
+  unsafe fn read_mask(&self) -> u8 {
+    self.data.read()
+  }
+
+  #[inline]
+  unsafe fn write_mask(&self, mask: u8) {
+    self.data.write(mask)
+  }
+}
+
+/// The master and slave PIC pair.
+pub struct ChainedPics {
+  master: Pic,
+  slave:  Pic,
+}
+
+impl ChainedPics {
+  /// ## Safety
+  /// The offsets must not overlap the CPU exceptions or other interrupt vectors.
+  pub const unsafe fn new(offset1: u8, offset2: u8) -> Self {
+    Self {
+      master: Pic {
+        offset:  offset1,
+        command: Port::new(0x20),
+        data:    Port::new(0x21),
+      },
+      slave:  Pic {
+        offset:  offset2,
+        command: Port::new(0xA0),
+        data:    Port::new(0xA1),
+      },
+    }
+  }
+
+  /// Remap the PICs to their offsets, with all the IRQs masked except the cascade.
+  ///
+  /// ## Safety
+  /// Interrupts must be disabled.
+  pub unsafe fn initialize(&mut self) {
+    // Writing to an unused port gives the PIC time to react on old hardware.
+    let wait_port: Port<u8> = Port::new(0x80);
+    let wait = || wait_port.write(0);
+
+    self.master.command.write(ICW1_INIT);
+    wait();
+    self.slave.command.write(ICW1_INIT);
+    wait();
+
+    // ICW2: vector offsets.
+    self.master.data.write(self.master.offset);
+    wait();
+    self.slave.data.write(self.slave.offset);
+    wait();
+
+    // ICW3: the slave is attached to IRQ 2 of the master, which is the cascade identity of slave.
+    self.master.data.write(1 << CASCADE_IRQ);
+    wait();
+    self.slave.data.write(CASCADE_IRQ);
+    wait();
+
+    self.master.data.write(ICW4_8086);
+    wait();
+    self.slave.data.write(ICW4_8086);
+    wait();
+
+    self.master.write_mask(!(1 << CASCADE_IRQ));
+    self.slave.write_mask(0xFF);
+  }
+
+  /// True if the vector is delivered by the PICs.
+  #[inline]
+  pub fn handles_interrupt(&self, vector: u8) -> bool {
+    self.master.handles_interrupt(vector) || self.slave.handles_interrupt(vector)
+  }
+
+  /// True if the vector is a spurious IRQ 7 or 15, raised when an IRQ went away before it was
+  /// delivered. It must not be handled as the IRQ.
+  ///
+  /// ## Safety
+  /// The vector must be the interrupt being handled.
+  pub unsafe fn is_spurious(&self, vector: u8) -> bool {
+    self.master.is_spurious(vector) || self.slave.is_spurious(vector)
+  }
+
+  /// Signal the end of interrupt of the vector. The slave IRQs must be acknowledged on both PICs.
+  ///
+  /// A spurious IRQ is not in service, so it's not acknowledged, but the master still delivered
+  /// the cascade IRQ of a spurious IRQ of the slave.
+  ///
+  /// ## Safety
+  /// The vector must be the interrupt being handled.
+  pub unsafe fn notify_end_of_interrupt(&mut self, vector: u8) {
+    if !self.handles_interrupt(vector) {
+      return;
+    }
+    if self.slave.handles_interrupt(vector) {
+      if !self.slave.is_spurious(vector) {
+        self.slave.end_of_interrupt();
+      }
+      self.master.end_of_interrupt();
+    } else if !self.master.is_spurious(vector) {
+      self.master.end_of_interrupt();
+    }
+  }
+
+  /// Mask the IRQ, so that it is no longer delivered.
+  ///
+  /// ## Safety
+  /// Masking an IRQ may lose interrupts which some driver waits for.
+  pub unsafe fn mask(&mut self, irq: u8) {
+    match irq {
+      0..=7 => self.master.write_mask(self.master.read_mask() | (1 << irq)),
+      8..=15 => self.slave.write_mask(self.slave.read_mask() | (1 << (irq - 8))),
+      _ => {}
+    }
+  }
+
+  /// Unmask the IRQ, so that it is delivered.
+  ///
+  /// ## Safety
+  /// The vector of the IRQ must have a handler.
+  pub unsafe fn unmask(&mut self, irq: u8) {
+    match irq {
+      0..=7 => self.master.write_mask(self.master.read_mask() & !(1 << irq)),
+      8..=15 => self.slave.write_mask(self.slave.read_mask() & !(1 << (irq - 8))),
+      _ => {}
+    }
+  }
+
+  /// Mask all the IRQs, e.g. when the APIC takes over.
+  ///
+  /// ## Safety
+  /// Interrupts delivered by the PICs are lost then.
+  pub unsafe fn disable(&mut self) {
+    self.master.write_mask(0xFF);
+    self.slave.write_mask(0xFF);
+  }
+}
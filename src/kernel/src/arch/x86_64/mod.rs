@@ -1,17 +1,25 @@
+use crate::arch::paging::PageTableFlags;
 use crate::arch::paging::PageTableIndex;
 use crate::arch::paging::PageTableLevel;
 use crate::arch::x86_64::paging::PageTable;
 use crate::arch::x86_64::reg::CR3;
-use crate::arch::FrameError;
 use crate::arch::PhysicalAddress;
 use crate::arch::VirtualAddress;
 use crate::println;
 
+pub mod acpi;
+pub mod context;
+pub mod cpu;
+pub mod fpu;
 pub mod gdt;
 pub mod hw;
+pub mod idle;
 pub mod interrupt;
 pub mod paging;
+pub mod percpu;
 pub mod reg;
+pub mod smp;
+pub mod time;
 
 /// Activate CPU page table of Level 4.
 /// Offset: Physical memory offset.
@@ -30,32 +38,67 @@ pub unsafe fn active_level_4_table(offset: VirtualAddress) -> &'static mut PageT
   page_table
 }
 
-pub(crate) fn translate_address_inner(
+/// Walk the page tables from the level 4 table down to the entry mapping the address, and return
+/// the physical address with the effective flags of the mapping: the flags of the last entry, with
+/// `WRITABLE` and `USER_ACCESSIBLE` only if set at all the levels, and `NO_EXECUTE` if set at any
+/// level. If not mapped, return `None`.
+pub(crate) fn walk_page_tables(
+  level_4_table: &PageTable,
   address: VirtualAddress,
   physical_memory_offset: VirtualAddress,
-) -> Option<PhysicalAddress> {
-  let (mut frame, _) = CR3::read();
+) -> Option<(PhysicalAddress, PageTableFlags)> {
+  let mut table = level_4_table;
+  let mut level = PageTableLevel::Four;
+  let mut all_levels = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+  let mut any_level = PageTableFlags::empty();
 
-  let table_indexes = [
-    address.p4_index(),
-    address.p3_index(),
-    address.p2_index(),
-    address.p1_index(),
-  ];
-  for &index in &table_indexes {
-    let virtual_address = physical_memory_offset + frame.start_address().as_raw();
-    let table_ptr: *const PageTable = virtual_address.as_ptr();
-    let table = unsafe { &*table_ptr };
+  loop {
+    let entry = &table[address.page_table_index(level)];
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT) {
+      return None;
+    }
+    all_levels &= flags;
+    any_level |= flags & PageTableFlags::NO_EXECUTE;
 
-    let entry = &table[index];
-    frame = match entry.frame() {
-      Ok(frame) => frame,
-      Err(FrameError::FrameNotPresent) => return None,
-      Err(FrameError::HugePage) => panic!(""),
-    };
+    match level.lower() {
+      // `HUGE_PAGE` is reserved in level 4 entries.
+      Some(lower)
+        if level == PageTableLevel::Four || !flags.contains(PageTableFlags::HUGE_PAGE) =>
+      {
+        let table_address = physical_memory_offset + entry.address().as_raw();
+        table = unsafe { &*table_address.as_ptr::<PageTable>() };
+        level = lower;
+      }
+      // Level 1 entry or huge page entry at level 2 or 3.
+      _ => {
+        let size = level.entry_address_space_alignment();
+        let physical_address = entry.address().align_down(size) + (address.as_raw() & (size - 1));
+        let flags = (flags - PageTableFlags::WRITABLE - PageTableFlags::USER_ACCESSIBLE)
+          | all_levels
+          | any_level;
+        return Some((physical_address, flags));
+      }
+    }
   }
+}
 
-  Some(frame.start_address() + u64::from(address.page_offset()))
+/// Translate the address in the active address space, with the effective flags of its mapping.
+pub(crate) fn translate_address_flags(
+  address: VirtualAddress,
+  physical_memory_offset: VirtualAddress,
+) -> Option<(PhysicalAddress, PageTableFlags)> {
+  let (frame, _) = CR3::read();
+  let table_address = physical_memory_offset + frame.start_address().as_raw();
+  let level_4_table = unsafe { &*table_address.as_ptr::<PageTable>() };
+  walk_page_tables(level_4_table, address, physical_memory_offset)
+}
+
+pub(crate) fn translate_address_inner(
+  address: VirtualAddress,
+  physical_memory_offset: VirtualAddress,
+) -> Option<PhysicalAddress> {
+  translate_address_flags(address, physical_memory_offset).map(|(address, _)| address)
 }
 
 impl VirtualAddress {
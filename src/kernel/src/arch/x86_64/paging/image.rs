@@ -0,0 +1,117 @@
+//! # Kernel Image
+//!
+//! The loadable segments of the kernel ELF image are remapped W^X: the code is read-only and
+//! executable, the read-only data is read-only and not executable, and the data and BSS are
+//! writable and not executable. A stray write into the code faults instead of corrupting it. The
+//! pages are global, since the kernel is mapped in every address space.
+//!
+//! The segments are found through the program headers, which the linker places in the first
+//! segment, right after the ELF header at `__ehdr_start`. The bootloader maps the segments with
+//! 4KiB pages.
+
+use crate::arch::paging::PageTableFlags;
+use crate::arch::paging::Size4KiB;
+use crate::arch::x86_64::reg::Efer;
+use crate::arch::x86_64::reg::EferFlags;
+use crate::arch::Page;
+use crate::arch::VirtualAddress;
+use crate::mem::mapper::FlagUpdateError;
+use crate::mem::KERNEL_MAPPER;
+use crate::println;
+
+extern "C" {
+  /// ELF header of the kernel image, defined by the linker.
+  static __ehdr_start: u8;
+}
+
+/// Offset of the program header table in the ELF header.
+const E_PHOFF: usize = 32;
+/// Offset of the size of a program header in the ELF header.
+const E_PHENTSIZE: usize = 54;
+/// Offset of the count of program headers in the ELF header.
+const E_PHNUM: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+/// An ELF64 program header.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct ProgramHeader {
+  p_type:   u32,
+  p_flags:  u32,
+  p_offset: u64,
+  p_vaddr:  u64,
+  p_paddr:  u64,
+  p_filesz: u64,
+  p_memsz:  u64,
+  p_align:  u64,
+}
+
+/// Error occurred when protecting the kernel image.
+#[derive(Debug)]
+pub enum ImageError {
+  /// No loadable segment contains the ELF header, so the load address is unknown.
+  HeaderNotLoaded,
+  FlagUpdate(FlagUpdateError),
+}
+
+impl From<FlagUpdateError> for ImageError {
+  fn from(err: FlagUpdateError) -> Self {
+    Self::FlagUpdate(err)
+  }
+}
+
+/// Return the program headers of the kernel image.
+fn program_headers() -> impl Iterator<Item = ProgramHeader> {
+  unsafe {
+    let header = core::ptr::addr_of!(__ehdr_start);
+    let offset = header.add(E_PHOFF).cast::<u64>().read_unaligned() as usize;
+    let size = usize::from(header.add(E_PHENTSIZE).cast::<u16>().read_unaligned());
+    let count = usize::from(header.add(E_PHNUM).cast::<u16>().read_unaligned());
+    (0..count).map(move |i| header.add(offset + i * size).cast::<ProgramHeader>().read_unaligned())
+  }
+}
+
+/// Remap the segments of the kernel image with the least permissions.
+pub fn protect_kernel_image() -> Result<(), ImageError> {
+  // The headers are in the first segment, which gives where the image is loaded.
+  let header = unsafe { core::ptr::addr_of!(__ehdr_start) } as u64;
+  let first = program_headers()
+    .find(|ph| ph.p_type == PT_LOAD && ph.p_offset == 0)
+    .ok_or(ImageError::HeaderNotLoaded)?;
+  let bias = header.wrapping_sub(first.p_vaddr);
+  let no_execute = Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE);
+
+  let mut mapper = KERNEL_MAPPER.lock();
+  let mapper = mapper.as_mut().expect("protect_kernel_image: kernel mapper not initialized");
+  for segment in program_headers().filter(|ph| ph.p_type == PT_LOAD && ph.p_memsz != 0) {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::GLOBAL;
+    if segment.p_flags & PF_W != 0 {
+      flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.p_flags & PF_X == 0 && no_execute {
+      flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if segment.p_flags & (PF_W | PF_X) == PF_W | PF_X {
+      println!(
+        "[ERROR] Kernel segment at {:#x} is writable and executable.",
+        segment.p_vaddr
+      );
+    }
+
+    let start = VirtualAddress::new(segment.p_vaddr.wrapping_add(bias));
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(start + (segment.p_memsz - 1));
+    let count = (last.start_address() - first.start_address()) / Page::<Size4KiB>::SIZE + 1;
+    for i in 0..count {
+      unsafe { mapper.update_flags(first + i, flags)?.flush() };
+    }
+    println!(
+      "[DEBUG] Kernel segment {:?}, {} pages, {:?}.",
+      first, count, flags
+    );
+  }
+  Ok(())
+}
@@ -6,11 +6,14 @@ use crate::arch::PhysicalFrame;
 use crate::arch::PtrWidth;
 use crate::arch::VirtualAddress;
 
+pub mod image;
+pub mod tlb;
+
 /// Count of entries in the page table.
 const ENTRY_COUNT: usize = 512;
 
 /// Page size for CPU page.
-pub trait PageSize {
+pub trait PageSize: Copy + Eq + Ord {
   const SIZE: PtrWidth;
 
   /// String for debug, such as `4KiB`.
@@ -18,6 +21,7 @@ pub trait PageSize {
   const DEBUG_STR: &'static str;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Size4KiB;
 
 // noinspection RsSortImplTraitMembers
@@ -27,6 +31,28 @@ impl PageSize for Size4KiB {
   const SIZE: PtrWidth = 4096;
 }
 
+/// 2MiB huge page, mapped by a level 2 page table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size2MiB;
+
+// noinspection RsSortImplTraitMembers
+impl PageSize for Size2MiB {
+  #[cfg(debug_assertions)]
+  const DEBUG_STR: &'static str = "2MiB";
+  const SIZE: PtrWidth = Size4KiB::SIZE * ENTRY_COUNT as PtrWidth;
+}
+
+/// 1GiB huge page, mapped by a level 3 page table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size1GiB;
+
+// noinspection RsSortImplTraitMembers
+impl PageSize for Size1GiB {
+  #[cfg(debug_assertions)]
+  const DEBUG_STR: &'static str = "1GiB";
+  const SIZE: PtrWidth = Size2MiB::SIZE * ENTRY_COUNT as PtrWidth;
+}
+
 impl VirtualAddress {
   #[inline]
   pub const fn page_offset(self) -> PageOffset {
@@ -163,6 +189,12 @@ impl PageTableEntry {
     self.entry = address.as_raw() | flags.bits();
   }
 
+  #[inline]
+  pub fn set_frame(&mut self, frame: PhysicalFrame, flags: PageTableFlags) {
+    debug_assert!(!flags.contains(PageTableFlags::HUGE_PAGE));
+    self.set_address(frame.start_address(), flags);
+  }
+
   #[inline]
   pub fn set_flags(&mut self, flags: PageTableFlags) {
     self.entry = self.address().as_raw() | flags.bits()
@@ -240,7 +272,7 @@ impl From<PageTableIndex> for usize {
 bitflags! {
   #[rustfmt::skip]
   #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-  struct PageTableFlags: u64 {
+  pub struct PageTableFlags: u64 {
     const PRESENT               = 1 << 0;
     const WRITABLE              = 1 << 1;
     const USER_ACCESSIBLE       = 1 << 2;
@@ -304,6 +336,15 @@ pub(crate) enum PageTableLevel {
 }
 
 impl PageTableLevel {
+  /// Return the level whose entries map pages of size `S`.
+  pub const fn of_page_size<S: PageSize>() -> Self {
+    match S::SIZE {
+      Size1GiB::SIZE => Self::Three,
+      Size2MiB::SIZE => Self::Two,
+      _ => Self::One,
+    }
+  }
+
   pub const fn lower(self) -> Option<Self> {
     match self {
       Self::Four => Some(Self::Three),
@@ -0,0 +1,28 @@
+//! # Translation Lookaside Buffer
+//!
+//! The CPU caches page table translations in the TLB, which must be flushed manually after any
+//! modification on the page tables.
+
+use core::arch::asm;
+
+use crate::arch::x86_64::reg::CR3;
+use crate::arch::VirtualAddress;
+
+/// Invalidate the TLB entry of the page containing the given address.
+///
+/// ```asm
+/// invlpg [address]
+/// ```
+#[inline]
+pub fn flush(address: VirtualAddress) {
+  unsafe {
+    asm!("invlpg [{}]", in(reg) address.as_raw(), options(nostack, preserves_flags));
+  }
+}
+
+/// Invalidate all the TLB entries except global pages, by reloading `CR3`.
+#[inline]
+pub fn flush_all() {
+  let (frame, flags) = CR3::read();
+  unsafe { CR3::write(frame, flags) }
+}
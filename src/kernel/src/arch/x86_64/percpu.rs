@@ -0,0 +1,287 @@
+//! # Per-CPU Data
+//!
+//! Each processor owns an area, whose address is in its GS base while it runs in the kernel. The
+//! fields of the area are read with a single `gs`-relative instruction, which cannot be torn by an
+//! interrupt nor by a migration to another processor.
+//!
+//! User mode owns the GS base, the kernel one is kept in `IA32_KERNEL_GS_BASE` meanwhile, and
+//! `swapgs` exchanges them on entry from user mode and on return, see [`InterruptGuard`].
+//!
+//! Variables declared with [`per_cpu!`](crate::per_cpu) have an instance for each processor,
+//! indexed by the id in the area.
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::mem::offset_of;
+use core::ptr::addr_of_mut;
+use core::ptr::NonNull;
+
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::PrivilegeLevel;
+
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::x86_64::reg::GsBase;
+use crate::arch::x86_64::reg::KernelGsBase;
+use crate::arch::VirtualAddress;
+use crate::proc::Thread;
+
+/// Maximum count of processors.
+pub const MAX_CPUS: usize = 64;
+
+/// Per-CPU area, at the GS base of its processor.
+#[derive(Debug)]
+#[repr(C)]
+pub struct PerCpuArea {
+  /// Address of the area itself, so that it's found with `gs:[0]`.
+  this:            *mut PerCpuArea,
+  cpu_id:          usize,
+  tss:             *const TaskStateSegment,
+  current_thread:  *mut Thread,
+  /// Count of nested interrupt handlers running.
+  interrupt_depth: usize,
+}
+
+impl PerCpuArea {
+  const fn new(cpu_id: usize, tss: &'static TaskStateSegment) -> Self {
+    Self {
+      this: core::ptr::null_mut(),
+      cpu_id,
+      tss,
+      current_thread: core::ptr::null_mut(),
+      interrupt_depth: 0,
+    }
+  }
+}
+
+/// Read a field of the area of the current processor.
+macro_rules! read_field {
+  ($field:ident) => {{
+    let value: usize;
+    unsafe {
+      asm!(
+        "mov {}, qword ptr gs:[{}]",
+        out(reg) value,
+        const offset_of!(PerCpuArea, $field),
+        options(nostack, preserves_flags, readonly),
+      );
+    }
+    value
+  }};
+}
+
+/// Install the area in the GS base of the current processor.
+///
+/// ## Safety
+/// The area must not be installed on another processor.
+unsafe fn install(area: &'static mut PerCpuArea) {
+  let address = area as *mut PerCpuArea;
+  area.this = address;
+  GsBase::write(VirtualAddress::new(address as u64));
+  KernelGsBase::write(VirtualAddress::new(0));
+}
+
+/// Install the per-CPU area of the bootstrap processor, which is static so that it's available
+/// before the heap.
+///
+/// ## Safety
+/// Must be called once, on the bootstrap processor, before interrupts are enabled.
+pub unsafe fn init_per_cpu(tss: &'static TaskStateSegment) {
+  static mut BSP_AREA: Option<PerCpuArea> = None;
+
+  let area = &mut *addr_of_mut!(BSP_AREA);
+  install(area.insert(PerCpuArea::new(0, tss)));
+}
+
+/// Install the per-CPU area of an application processor.
+///
+/// ## Safety
+/// Must be called once on each application processor, before its IDT is loaded.
+pub unsafe fn init_ap_per_cpu(cpu_id: usize, tss: &'static TaskStateSegment) {
+  install(Box::leak(Box::new(PerCpuArea::new(cpu_id, tss))));
+}
+
+/// True if the per-CPU area of the current processor is installed.
+#[inline]
+pub fn is_installed() -> bool {
+  GsBase::read().as_raw() != 0
+}
+
+/// Return the id of the current processor, 0 for the bootstrap processor.
+#[inline]
+pub fn cpu_id() -> usize {
+  read_field!(cpu_id)
+}
+
+/// Return the TSS of the current processor.
+#[inline]
+pub fn tss() -> &'static TaskStateSegment {
+  unsafe { &*(read_field!(tss) as *const TaskStateSegment) }
+}
+
+/// Return the thread running on the current processor, if any.
+#[inline]
+pub fn current_thread() -> Option<NonNull<Thread>> {
+  NonNull::new(read_field!(current_thread) as *mut Thread)
+}
+
+/// Set the thread running on the current processor.
+///
+/// ## Safety
+/// The thread must be switched to, and live as long as it runs.
+#[inline]
+pub unsafe fn set_current_thread(thread: Option<NonNull<Thread>>) {
+  let thread = thread.map_or(core::ptr::null_mut(), NonNull::as_ptr);
+  asm!(
+    "mov qword ptr gs:[{}], {}",
+    const offset_of!(PerCpuArea, current_thread),
+    in(reg) thread,
+    options(nostack, preserves_flags),
+  );
+}
+
+/// Return the count of nested interrupt handlers running on the current processor.
+#[inline]
+pub fn interrupt_depth() -> usize {
+  read_field!(interrupt_depth)
+}
+
+/// True if the current processor runs an interrupt handler.
+#[inline]
+pub fn in_interrupt() -> bool {
+  interrupt_depth() != 0
+}
+
+/// Guard held by the interrupt handlers. It switches to the kernel GS base if the interrupt came
+/// from user mode, and counts the nesting depth, until dropped.
+///
+/// Exceptions which may hit anywhere, such as the NMI and the double fault, cannot trust the code
+/// segment to know which GS base is loaded, and must not use it.
+pub struct InterruptGuard {
+  from_user: bool,
+}
+
+impl InterruptGuard {
+  #[inline(always)]
+  pub fn enter(frame: &InterruptStackFrame) -> Self {
+    let from_user = frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+    unsafe {
+      if from_user {
+        asm!("swapgs", options(nostack, preserves_flags));
+      }
+      asm!(
+        "inc qword ptr gs:[{}]",
+        const offset_of!(PerCpuArea, interrupt_depth),
+        options(nostack),
+      );
+    }
+    Self { from_user }
+  }
+}
+
+impl Drop for InterruptGuard {
+  #[inline(always)]
+  fn drop(&mut self) {
+    unsafe {
+      asm!(
+        "dec qword ptr gs:[{}]",
+        const offset_of!(PerCpuArea, interrupt_depth),
+        options(nostack),
+      );
+      if self.from_user {
+        asm!("swapgs", options(nostack, preserves_flags));
+      }
+    }
+  }
+}
+
+/// Variable with an instance for each processor, declared with [`per_cpu!`](crate::per_cpu).
+pub struct PerCpu<T> {
+  values: [T; MAX_CPUS],
+}
+
+// Only the owner processor accesses the instances which are not `Sync`, with interrupts disabled.
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+  #[doc(hidden)]
+  pub const fn new(values: [T; MAX_CPUS]) -> Self {
+    Self { values }
+  }
+
+  /// Call the function on the instance of the current processor, with interrupts disabled, so
+  /// that neither an interrupt handler nor another thread of the processor interleaves.
+  #[inline]
+  pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    without_interrupts(|| f(&self.values[cpu_id()]))
+  }
+}
+
+impl<T: Sync> PerCpu<T> {
+  /// Return the instance of the current processor.
+  #[inline]
+  pub fn get(&self) -> &T {
+    &self.values[cpu_id()]
+  }
+
+  /// Return the instance of the processor.
+  #[inline]
+  pub fn get_for(&self, cpu_id: usize) -> &T {
+    &self.values[cpu_id]
+  }
+
+  /// Return the instances of all the processors, e.g. to sum statistics.
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    self.values.iter()
+  }
+}
+
+/// Declare per-CPU variables, whose initializers must be constant.
+///
+/// ```ignore
+/// per_cpu! {
+///   static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+/// }
+///
+/// INTERRUPTS.get().fetch_add(1, Ordering::Relaxed);
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+  ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+    $(
+      $(#[$attr])*
+      $vis static $name: $crate::arch::percpu::PerCpu<$ty> =
+        $crate::arch::percpu::PerCpu::new([const { $init }; $crate::arch::percpu::MAX_CPUS]);
+    )*
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use core::cell::Cell;
+  use core::sync::atomic::AtomicUsize;
+  use core::sync::atomic::Ordering;
+
+  use super::*;
+
+  crate::per_cpu! {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    static LOCAL: Cell<usize> = Cell::new(0);
+  }
+
+  #[test_case]
+  fn test_per_cpu() {
+    assert_eq!(cpu_id(), 0);
+    assert!(!in_interrupt());
+
+    COUNTER.get().fetch_add(2, Ordering::Relaxed);
+    assert_eq!(COUNTER.get_for(0).load(Ordering::Relaxed), 2);
+    assert_eq!(
+      COUNTER.iter().map(|c| c.load(Ordering::Relaxed)).sum::<usize>(),
+      2
+    );
+
+    LOCAL.with(|local| local.set(local.get() + 1));
+    assert_eq!(LOCAL.with(Cell::get), 1);
+  }
+}
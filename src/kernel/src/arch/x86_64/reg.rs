@@ -1,7 +1,178 @@
+//! # Registers
+//!
+//! Control registers, RFLAGS, and model-specific registers. The bits which the kernel does not
+//! know are kept as is when a register is updated.
+
 use core::arch::asm;
 
+use bitflags::bitflags;
+
+use crate::arch::shared::cpuid::XFeatures;
 use crate::arch::PhysicalAddress;
 use crate::arch::PhysicalFrame;
+use crate::arch::VirtualAddress;
+
+bitflags! {
+  /// Flags of CR0.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct Cr0Flags: u64 {
+    /// Protected mode.
+    const PROTECTED_MODE_ENABLE = 1 << 0;
+    /// `wait` raises #NM when `TASK_SWITCHED` is set.
+    const MONITOR_COPROCESSOR   = 1 << 1;
+    /// No x87 FPU, its instructions raise #NM.
+    const EMULATE_COPROCESSOR   = 1 << 2;
+    /// The FPU, SSE and AVX instructions raise #NM, so that their state is switched lazily.
+    const TASK_SWITCHED         = 1 << 3;
+    const EXTENSION_TYPE        = 1 << 4;
+    /// x87 FPU errors raise #MF, otherwise an external interrupt.
+    const NUMERIC_ERROR         = 1 << 5;
+    /// The kernel cannot write read-only pages either.
+    const WRITE_PROTECT         = 1 << 16;
+    /// Misaligned accesses raise #AC in user mode, if `RFlags::ALIGNMENT_CHECK` is set.
+    const ALIGNMENT_MASK        = 1 << 18;
+    const NOT_WRITE_THROUGH     = 1 << 29;
+    const CACHE_DISABLE         = 1 << 30;
+    /// Paging.
+    const PAGING                = 1 << 31;
+  }
+}
+
+bitflags! {
+  /// Flags of CR4.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct Cr4Flags: u64 {
+    const VIRTUAL_8086_MODE_EXTENSIONS = 1 << 0;
+    const PROTECTED_MODE_VIRTUAL_INTERRUPTS = 1 << 1;
+    /// `rdtsc` is privileged.
+    const TIMESTAMP_DISABLE            = 1 << 2;
+    const DEBUGGING_EXTENSIONS         = 1 << 3;
+    const PAGE_SIZE_EXTENSION          = 1 << 4;
+    const PHYSICAL_ADDRESS_EXTENSION   = 1 << 5;
+    const MACHINE_CHECK_EXCEPTION      = 1 << 6;
+    /// Global pages, kept in the TLB when CR3 is written.
+    const PAGE_GLOBAL                  = 1 << 7;
+    const PERFORMANCE_MONITOR_COUNTER  = 1 << 8;
+    /// `fxsave` and `fxrstor` save and restore the SSE state, and SSE is enabled.
+    const OSFXSR                       = 1 << 9;
+    /// Unmasked SSE exceptions raise #XM, otherwise #UD.
+    const OSXMMEXCPT_ENABLE            = 1 << 10;
+    /// `sgdt`, `sidt`, `sldt`, `smsw` and `str` are privileged.
+    const USER_MODE_INSTRUCTION_PREVENTION = 1 << 11;
+    /// 5-level paging.
+    const L5_PAGING                    = 1 << 12;
+    const VIRTUAL_MACHINE_EXTENSIONS   = 1 << 13;
+    const SAFER_MODE_EXTENSIONS        = 1 << 14;
+    /// `rdfsbase`, `wrfsbase`, `rdgsbase` and `wrgsbase` are enabled.
+    const FSGSBASE                     = 1 << 16;
+    const PCID                         = 1 << 17;
+    /// `xsave` and `xrstor` are enabled, and XCR0 is writable.
+    const OSXSAVE                      = 1 << 18;
+    const KEY_LOCKER                   = 1 << 19;
+    /// Supervisor mode execution prevention.
+    const SUPERVISOR_MODE_EXECUTION_PREVENTION = 1 << 20;
+    /// Supervisor mode access prevention.
+    const SUPERVISOR_MODE_ACCESS_PREVENTION = 1 << 21;
+    const PROTECTION_KEY_USER          = 1 << 22;
+    const CONTROL_FLOW_ENFORCEMENT     = 1 << 23;
+    const PROTECTION_KEY_SUPERVISOR    = 1 << 24;
+  }
+}
+
+bitflags! {
+  /// Flags of RFLAGS.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct RFlags: u64 {
+    const CARRY_FLAG          = 1 << 0;
+    const PARITY_FLAG         = 1 << 2;
+    const AUXILIARY_CARRY_FLAG = 1 << 4;
+    const ZERO_FLAG           = 1 << 6;
+    const SIGN_FLAG           = 1 << 7;
+    /// Single-step debugging.
+    const TRAP_FLAG           = 1 << 8;
+    /// Maskable interrupts are enabled.
+    const INTERRUPT_FLAG      = 1 << 9;
+    const DIRECTION_FLAG      = 1 << 10;
+    const OVERFLOW_FLAG       = 1 << 11;
+    const IOPL_LOW            = 1 << 12;
+    const IOPL_HIGH           = 1 << 13;
+    const NESTED_TASK         = 1 << 14;
+    const RESUME_FLAG         = 1 << 16;
+    const VIRTUAL_8086_MODE   = 1 << 17;
+    /// Misaligned accesses raise #AC in user mode, and user pages are accessible with SMAP.
+    const ALIGNMENT_CHECK     = 1 << 18;
+    const VIRTUAL_INTERRUPT   = 1 << 19;
+    const VIRTUAL_INTERRUPT_PENDING = 1 << 20;
+    /// `cpuid` is supported if this flag can be toggled.
+    const ID                  = 1 << 21;
+  }
+}
+
+pub struct CR0;
+
+impl CR0 {
+  pub fn read() -> Cr0Flags {
+    Cr0Flags::from_bits_retain(Self::read_raw())
+  }
+
+  pub fn read_raw() -> u64 {
+    let value: u64;
+
+    unsafe {
+      asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+
+    value
+  }
+
+  /// ## Safety
+  /// Clearing `PROTECTED_MODE_ENABLE` or `PAGING` breaks the kernel.
+  pub unsafe fn write(flags: Cr0Flags) {
+    let value = flags.bits();
+
+    unsafe {
+      asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+  }
+
+  /// Update the flags with the closure.
+  ///
+  /// ## Safety
+  /// See [`CR0::write`].
+  pub unsafe fn update(f: impl FnOnce(&mut Cr0Flags)) {
+    let mut flags = Self::read();
+    f(&mut flags);
+    Self::write(flags);
+  }
+}
+
+pub struct CR2;
+
+impl CR2 {
+  /// Read the address which caused the last page fault.
+  pub fn read() -> VirtualAddress {
+    let value: u64;
+
+    unsafe {
+      asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+
+    VirtualAddress::new(value)
+  }
+
+  /// ## Safety
+  /// A page fault handler which has not read the faulting address yet gets a wrong one.
+  pub unsafe fn write(address: VirtualAddress) {
+    let value = address.as_raw();
+
+    unsafe {
+      asm!("mov cr2, {}", in(reg) value, options(nomem, nostack, preserves_flags));
+    }
+  }
+}
 
 pub struct CR3;
 
@@ -17,4 +188,417 @@ impl CR3 {
     let frame = PhysicalFrame::containing_address(address);
     (frame, (value & 0xFFF) as u16)
   }
+
+  /// Load a new level 4 page table, which also flushes the whole TLB except global pages.
+  ///
+  /// ## Safety
+  /// Switching the page table may break memory safety, the new one must map the kernel.
+  pub unsafe fn write(frame: PhysicalFrame, flags: u16) {
+    let value = frame.start_address().as_raw() | u64::from(flags);
+
+    unsafe {
+      asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+  }
+}
+
+pub struct CR4;
+
+impl CR4 {
+  pub fn read() -> Cr4Flags {
+    Cr4Flags::from_bits_retain(Self::read_raw())
+  }
+
+  pub fn read_raw() -> u64 {
+    let value: u64;
+
+    unsafe {
+      asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+
+    value
+  }
+
+  /// ## Safety
+  /// Setting a flag which the processor does not support raises #GP, and clearing
+  /// `PHYSICAL_ADDRESS_EXTENSION` breaks the kernel.
+  pub unsafe fn write(flags: Cr4Flags) {
+    let value = flags.bits();
+
+    unsafe {
+      asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+  }
+
+  /// Update the flags with the closure.
+  ///
+  /// ## Safety
+  /// See [`CR4::write`].
+  pub unsafe fn update(f: impl FnOnce(&mut Cr4Flags)) {
+    let mut flags = Self::read();
+    f(&mut flags);
+    Self::write(flags);
+  }
+}
+
+pub struct RFLAGS;
+
+impl RFLAGS {
+  pub fn read() -> RFlags {
+    let value: u64;
+
+    unsafe {
+      asm!("pushfq; pop {}", out(reg) value, options(nomem, preserves_flags));
+    }
+
+    RFlags::from_bits_retain(value)
+  }
+
+  /// ## Safety
+  /// Changing `INTERRUPT_FLAG` or `IOPL` may break the assumptions of the interrupted code.
+  pub unsafe fn write(flags: RFlags) {
+    let value = flags.bits();
+
+    unsafe {
+      asm!("push {}; popfq", in(reg) value, options(nomem));
+    }
+  }
+}
+
+/// Extended control register 0, the state components managed by `xsave` and `xrstor`.
+pub struct XCR0;
+
+impl XCR0 {
+  /// ## Safety
+  /// `Cr4Flags::OSXSAVE` must be set, otherwise `xgetbv` raises #UD.
+  pub unsafe fn read() -> XFeatures {
+    let (high, low): (u32, u32);
+
+    unsafe {
+      asm!(
+        "xgetbv",
+        in("ecx") 0,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags),
+      );
+    }
+
+    XFeatures::from_bits_retain((u64::from(high) << 32) | u64::from(low))
+  }
+
+  /// ## Safety
+  /// `Cr4Flags::OSXSAVE` must be set, and the components must be supported and consistent, e.g.
+  /// `AVX` requires `SSE`, otherwise `xsetbv` raises #GP.
+  pub unsafe fn write(features: XFeatures) {
+    let value = features.bits();
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+
+    unsafe {
+      asm!(
+        "xsetbv",
+        in("ecx") 0,
+        in("eax") low,
+        in("edx") high,
+        options(nostack, preserves_flags),
+      );
+    }
+  }
+}
+
+/// A model-specific register, by its index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Msr(u32);
+
+impl Msr {
+  pub const fn new(index: u32) -> Self {
+    Self(index)
+  }
+
+  pub const fn index(self) -> u32 {
+    self.0
+  }
+
+  /// ## Safety
+  /// Reading an MSR which the processor does not have raises #GP.
+  #[inline]
+  pub unsafe fn read(self) -> u64 {
+    let (high, low): (u32, u32);
+
+    unsafe {
+      asm!(
+        "rdmsr",
+        in("ecx") self.0,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags),
+      );
+    }
+
+    (u64::from(high) << 32) | u64::from(low)
+  }
+
+  /// ## Safety
+  /// Writing an MSR may change the behavior of the processor in any way, or raise #GP.
+  #[inline]
+  pub unsafe fn write(self, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+
+    unsafe {
+      asm!(
+        "wrmsr",
+        in("ecx") self.0,
+        in("eax") low,
+        in("edx") high,
+        options(nostack, preserves_flags),
+      );
+    }
+  }
+}
+
+pub const IA32_APIC_BASE: Msr = Msr::new(0x1B);
+pub const IA32_PAT: Msr = Msr::new(0x277);
+pub const IA32_TSC_DEADLINE: Msr = Msr::new(0x6E0);
+pub const IA32_EFER: Msr = Msr::new(0xC000_0080);
+pub const IA32_STAR: Msr = Msr::new(0xC000_0081);
+pub const IA32_LSTAR: Msr = Msr::new(0xC000_0082);
+pub const IA32_FMASK: Msr = Msr::new(0xC000_0084);
+pub const IA32_FS_BASE: Msr = Msr::new(0xC000_0100);
+pub const IA32_GS_BASE: Msr = Msr::new(0xC000_0101);
+pub const IA32_KERNEL_GS_BASE: Msr = Msr::new(0xC000_0102);
+
+bitflags! {
+  /// Flags of IA32_EFER.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct EferFlags: u64 {
+    /// `syscall` and `sysret` are enabled.
+    const SYSTEM_CALL_EXTENSIONS = 1 << 0;
+    const LONG_MODE_ENABLE       = 1 << 8;
+    /// Read-only, long mode is active.
+    const LONG_MODE_ACTIVE       = 1 << 10;
+    /// The `NO_EXECUTE` flag of the page table entries is enabled.
+    const NO_EXECUTE_ENABLE      = 1 << 11;
+    const SECURE_VIRTUAL_MACHINE_ENABLE = 1 << 12;
+    const LONG_MODE_SEGMENT_LIMIT_ENABLE = 1 << 13;
+    const FAST_FXSAVE_FXRSTOR    = 1 << 14;
+    const TRANSLATION_CACHE_EXTENSION = 1 << 15;
+  }
+}
+
+bitflags! {
+  /// Flags of IA32_APIC_BASE, the other bits are the base address.
+  #[rustfmt::skip]
+  #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+  pub struct ApicBaseFlags: u64 {
+    /// Read-only, the current processor is the bootstrap processor.
+    const BSP    = 1 << 8;
+    /// x2APIC mode.
+    const X2APIC = 1 << 10;
+    /// The APIC is globally enabled.
+    const ENABLE = 1 << 11;
+  }
+}
+
+pub struct Efer;
+
+impl Efer {
+  pub fn read() -> EferFlags {
+    EferFlags::from_bits_retain(unsafe { IA32_EFER.read() })
+  }
+
+  /// ## Safety
+  /// Clearing `LONG_MODE_ENABLE` or `NO_EXECUTE_ENABLE` breaks the kernel.
+  pub unsafe fn write(flags: EferFlags) {
+    IA32_EFER.write(flags.bits());
+  }
+
+  /// Update the flags with the closure.
+  ///
+  /// ## Safety
+  /// See [`Efer::write`].
+  pub unsafe fn update(f: impl FnOnce(&mut EferFlags)) {
+    let mut flags = Self::read();
+    f(&mut flags);
+    Self::write(flags);
+  }
+}
+
+/// Segment selectors loaded by `syscall` and `sysret`.
+pub struct Star;
+
+impl Star {
+  /// Return the selector bases of `sysret` and `syscall`. `sysret` loads CS from the base plus 16
+  /// and SS from the base plus 8, `syscall` loads CS from the base and SS from the base plus 8.
+  pub fn read() -> (u16, u16) {
+    let value = unsafe { IA32_STAR.read() };
+    ((value >> 48) as u16, (value >> 32) as u16)
+  }
+
+  /// ## Safety
+  /// The selectors must match the GDT layout expected by `syscall` and `sysret`.
+  pub unsafe fn write(sysret_base: u16, syscall_base: u16) {
+    IA32_STAR.write(u64::from(sysret_base) << 48 | u64::from(syscall_base) << 32);
+  }
+}
+
+/// Entry point of `syscall` in 64-bit mode.
+pub struct LStar;
+
+impl LStar {
+  pub fn read() -> VirtualAddress {
+    VirtualAddress::new(unsafe { IA32_LSTAR.read() })
+  }
+
+  /// ## Safety
+  /// The entry point must handle system calls.
+  pub unsafe fn write(entry: VirtualAddress) {
+    IA32_LSTAR.write(entry.as_raw());
+  }
+}
+
+/// RFLAGS bits cleared by `syscall`.
+pub struct SfMask;
+
+impl SfMask {
+  pub fn read() -> RFlags {
+    RFlags::from_bits_retain(unsafe { IA32_FMASK.read() })
+  }
+
+  /// ## Safety
+  /// The `syscall` entry point must be able to run with the flags left.
+  pub unsafe fn write(mask: RFlags) {
+    IA32_FMASK.write(mask.bits());
+  }
+}
+
+/// Base of the FS segment.
+pub struct FsBase;
+
+impl FsBase {
+  pub fn read() -> VirtualAddress {
+    VirtualAddress::new(unsafe { IA32_FS_BASE.read() })
+  }
+
+  /// ## Safety
+  /// The code addressing through FS must expect the new base.
+  pub unsafe fn write(base: VirtualAddress) {
+    IA32_FS_BASE.write(base.as_raw());
+  }
+}
+
+/// Base of the GS segment.
+pub struct GsBase;
+
+impl GsBase {
+  pub fn read() -> VirtualAddress {
+    VirtualAddress::new(unsafe { IA32_GS_BASE.read() })
+  }
+
+  /// ## Safety
+  /// The code addressing through GS must expect the new base.
+  pub unsafe fn write(base: VirtualAddress) {
+    IA32_GS_BASE.write(base.as_raw());
+  }
+}
+
+/// Base of the GS segment swapped in by `swapgs`.
+pub struct KernelGsBase;
+
+impl KernelGsBase {
+  pub fn read() -> VirtualAddress {
+    VirtualAddress::new(unsafe { IA32_KERNEL_GS_BASE.read() })
+  }
+
+  /// ## Safety
+  /// The code addressing through GS after `swapgs` must expect the new base.
+  pub unsafe fn write(base: VirtualAddress) {
+    IA32_KERNEL_GS_BASE.write(base.as_raw());
+  }
+}
+
+/// Base address and flags of the local APIC.
+pub struct ApicBase;
+
+impl ApicBase {
+  pub fn read() -> (PhysicalAddress, ApicBaseFlags) {
+    let value = unsafe { IA32_APIC_BASE.read() };
+    (
+      PhysicalAddress::new(value & 0x000F_FFFF_FFFF_F000),
+      ApicBaseFlags::from_bits_truncate(value),
+    )
+  }
+
+  /// ## Safety
+  /// Moving or disabling the local APIC breaks the delivery of interrupts.
+  pub unsafe fn write(address: PhysicalAddress, flags: ApicBaseFlags) {
+    IA32_APIC_BASE.write(address.as_raw() | flags.bits());
+  }
+}
+
+/// Memory types of the page attribute table, selected by the `PAT`, `DISABLE_CACHE` and
+/// `WRITE_THROUGH_CACHING` flags of the page table entries.
+pub struct Pat;
+
+/// Memory type of a page attribute table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PatMemoryType {
+  Uncacheable = 0,
+  WriteCombining = 1,
+  WriteThrough = 4,
+  WriteProtected = 5,
+  WriteBack = 6,
+  Uncached = 7,
+}
+
+impl PatMemoryType {
+  fn from_bits(bits: u8) -> Option<Self> {
+    match bits {
+      0 => Some(Self::Uncacheable),
+      1 => Some(Self::WriteCombining),
+      4 => Some(Self::WriteThrough),
+      5 => Some(Self::WriteProtected),
+      6 => Some(Self::WriteBack),
+      7 => Some(Self::Uncached),
+      _ => None,
+    }
+  }
+}
+
+impl Pat {
+  /// Return the 8 entries, `None` for a reserved memory type.
+  pub fn read() -> [Option<PatMemoryType>; 8] {
+    let value = unsafe { IA32_PAT.read() };
+    core::array::from_fn(|i| PatMemoryType::from_bits((value >> (i * 8)) as u8 & 0x7))
+  }
+
+  /// ## Safety
+  /// The memory types of the pages already mapped change, and the caches must be flushed.
+  pub unsafe fn write(entries: [PatMemoryType; 8]) {
+    let value = entries
+      .iter()
+      .enumerate()
+      .fold(0, |value, (i, &entry)| value | (entry as u64) << (i * 8));
+    IA32_PAT.write(value);
+  }
+}
+
+/// Deadline of the local APIC timer in TSC-deadline mode.
+pub struct TscDeadline;
+
+impl TscDeadline {
+  pub fn read() -> u64 {
+    unsafe { IA32_TSC_DEADLINE.read() }
+  }
+
+  /// Arm the timer at the TSC value, or disarm it with zero.
+  ///
+  /// ## Safety
+  /// The local APIC timer must be in TSC-deadline mode.
+  pub unsafe fn write(deadline: u64) {
+    IA32_TSC_DEADLINE.write(deadline);
+  }
 }
@@ -0,0 +1,268 @@
+//! # Symmetric Multiprocessing
+//!
+//! The firmware starts the bootstrap processor only, the application processors listed in the MADT
+//! wait for an INIT IPI followed by start-up IPIs. They are started one at a time on the same
+//! trampoline, each on its own stack, then set up their own descriptor tables, features and local
+//! APIC, and enter the idle loop.
+//!
+//! Processors are numbered from 0, the bootstrap processor, in the order of the MADT.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use spin::Once;
+
+use crate::allocator::allocate_frames;
+use crate::allocator::BuddyAllocator;
+use crate::arch::paging::PageSize;
+use crate::arch::paging::PageTableFlags;
+use crate::arch::paging::Size4KiB;
+use crate::arch::x86_64::acpi::madt::Madt;
+use crate::arch::x86_64::acpi::AcpiError;
+use crate::arch::x86_64::cpu::init_paging_features;
+use crate::arch::x86_64::cpu::init_protection_features;
+use crate::arch::x86_64::fpu::init_fpu;
+use crate::arch::x86_64::gdt::init_ap_gdt;
+use crate::arch::x86_64::gdt::DOUBLE_FAULT_STACK_SIZE;
+use crate::arch::x86_64::hw::pit;
+use crate::arch::x86_64::idle::idle;
+use crate::arch::x86_64::interrupt;
+use crate::arch::x86_64::interrupt::apic::LocalApic;
+use crate::arch::x86_64::interrupt::apic::ICR_DELIVERY_INIT;
+use crate::arch::x86_64::interrupt::apic::ICR_DELIVERY_STARTUP;
+use crate::arch::x86_64::interrupt::apic::ICR_LEVEL_ASSERT;
+use crate::arch::x86_64::interrupt::init_ap_idt;
+use crate::arch::x86_64::paging::tlb;
+use crate::arch::x86_64::percpu::init_ap_per_cpu;
+use crate::arch::x86_64::percpu::MAX_CPUS;
+use crate::arch::x86_64::reg::ApicBase;
+use crate::arch::x86_64::reg::ApicBaseFlags;
+use crate::arch::x86_64::reg::Efer;
+use crate::arch::x86_64::reg::EferFlags;
+use crate::arch::x86_64::reg::CR3;
+use crate::arch::Page;
+use crate::arch::PhysicalFrame;
+use crate::arch::VirtualAddress;
+use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::mapper::MapToError;
+use crate::mem::physical_to_virtual;
+use crate::mem::KERNEL_MAPPER;
+use crate::println;
+use crate::proc::sched::init_scheduler;
+
+pub mod trampoline;
+
+/// Order of the kernel stack of an application processor, in the buddy allocator.
+const STACK_ORDER: usize = 4;
+/// Order of the double fault stack of an application processor.
+const DOUBLE_FAULT_STACK_ORDER: usize =
+  BuddyAllocator::order_of(DOUBLE_FAULT_STACK_SIZE / Size4KiB::SIZE as usize);
+
+/// Delay between the INIT IPI and the first start-up IPI, in microseconds.
+const INIT_DELAY: u64 = 10_000;
+/// Delay between the two start-up IPIs, in microseconds.
+const STARTUP_DELAY: u64 = 200;
+/// Time given to a processor to come online, in milliseconds.
+const STARTUP_TIMEOUT: u64 = 100;
+
+/// Error occurred when starting the application processors.
+#[derive(Debug)]
+pub enum SmpError {
+  /// The APICs are not enabled, so that no IPI can be sent.
+  NoLocalApic,
+  Acpi(AcpiError),
+  /// No usable frame in the low memory for the trampoline.
+  NoTrampolineFrame,
+  /// The level 4 page table is above 4GiB, out of reach of the trampoline.
+  PageTableTooHigh,
+  /// Out of memory for a stack.
+  OutOfMemory,
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<AcpiError> for SmpError {
+  fn from(err: AcpiError) -> Self {
+    Self::Acpi(err)
+  }
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    Self::Map(err)
+  }
+}
+
+/// MADT, read by the application processors to enable their local APIC.
+static MADT: Once<Madt> = Once::new();
+/// Count of processors online.
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+/// The processor being started is online, and no longer uses the trampoline.
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Count of processors online.
+#[inline]
+pub fn online_cpus() -> usize {
+  ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// True if the current processor is the bootstrap processor.
+#[inline]
+pub fn is_bootstrap_processor() -> bool {
+  unsafe { ApicBase::read().1.contains(ApicBaseFlags::BSP) }
+}
+
+/// Allocate a stack from the buddy allocator, and return its top.
+fn allocate_stack(order: usize) -> Option<VirtualAddress> {
+  let frame = allocate_frames(order)?;
+  let size = Size4KiB::SIZE << order;
+  Some(physical_to_virtual(frame.start_address()) + size)
+}
+
+/// Start the application processors listed in the MADT, and wait until each is online.
+pub fn start_application_processors() -> Result<(), SmpError> {
+  let local_apic = LocalApic::get().ok_or(SmpError::NoLocalApic)?;
+  let madt = Madt::parse()?;
+  let madt = MADT.call_once(|| madt);
+
+  let (level_4_frame, _) = CR3::read();
+  let cr3 = level_4_frame.start_address().as_raw();
+  if cr3 > u64::from(u32::MAX) {
+    return Err(SmpError::PageTableTooHigh);
+  }
+  let frame = FRAME_ALLOCATOR
+    .lock()
+    .as_ref()
+    .and_then(|allocator| allocator.low_memory_frame())
+    .ok_or(SmpError::NoTrampolineFrame)?;
+
+  // The trampoline enables paging while running at its physical address.
+  let page =
+    Page::<Size4KiB>::containing_address(VirtualAddress::new(frame.start_address().as_raw()));
+  let identity = PhysicalFrame::<Size4KiB>::containing_address(frame.start_address());
+  let mapped = map_trampoline(page, identity)?;
+  unsafe { trampoline::install(frame) };
+
+  let bsp_id = local_apic.id();
+  let processors = madt.processors.iter().filter(|processor| processor.enabled);
+  let mut result = Ok(());
+  for (cpu, processor) in processors.filter(|processor| processor.apic_id != bsp_id).enumerate() {
+    let cpu = cpu + 1;
+    if cpu >= MAX_CPUS {
+      println!("[ERROR] Only {} CPUs are supported.", MAX_CPUS);
+      break;
+    }
+    let Some(stack) = allocate_stack(STACK_ORDER) else {
+      result = Err(SmpError::OutOfMemory);
+      break;
+    };
+    let data = trampoline::TrampolineData {
+      cr3,
+      // LMA is set by the processor when paging is enabled, it must not be written before.
+      efer: (Efer::read() - EferFlags::LONG_MODE_ACTIVE).bits(),
+      stack: stack.as_raw(),
+      entry: ap_entry as *const () as u64,
+      argument: cpu as u64,
+    };
+    unsafe {
+      trampoline::write_data(frame, data);
+      if !start_processor(&local_apic, processor.apic_id, frame) {
+        println!(
+          "[ERROR] CPU {} (APIC ID {}) failed to start.",
+          cpu, processor.apic_id
+        );
+        // It may still run the trampoline, which must not be reused.
+        break;
+      }
+    }
+  }
+
+  if mapped {
+    unmap_trampoline(page);
+  }
+  println!("[INFO ] SMP: {} CPUs online.", online_cpus());
+  result
+}
+
+/// Identity map the trampoline, read-only and executable. Return false if it was already mapped.
+fn map_trampoline(page: Page, frame: PhysicalFrame) -> Result<bool, SmpError> {
+  let mut mapper = KERNEL_MAPPER.lock();
+  let mapper = mapper.as_mut().expect("map_trampoline: kernel mapper not initialized");
+  let mut allocator = FRAME_ALLOCATOR.lock();
+  let allocator = allocator.as_mut().expect("map_trampoline: frame allocator not initialized");
+  match unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT, allocator) } {
+    Ok(flush) => {
+      flush.flush();
+      Ok(true)
+    }
+    Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => Ok(false),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Remove the identity mapping of the trampoline. The application processors drop its
+/// translations from their TLB once in the kernel.
+fn unmap_trampoline(page: Page) {
+  let mut mapper = KERNEL_MAPPER.lock();
+  let mapper = mapper.as_mut().expect("unmap_trampoline: kernel mapper not initialized");
+  if let Ok((_, flush)) = unsafe { mapper.unmap(page) } {
+    flush.flush();
+  }
+}
+
+/// Send INIT and start-up IPIs to the processor, and wait until it's online.
+///
+/// ## Safety
+/// The trampoline must be installed in the frame, with the data of the processor.
+unsafe fn start_processor(local_apic: &LocalApic, apic_id: u32, frame: PhysicalFrame) -> bool {
+  let vector = (frame.start_address().as_raw() / Size4KiB::SIZE) as u32;
+  AP_STARTED.store(false, Ordering::Release);
+
+  local_apic.send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+  pit::busy_wait(INIT_DELAY);
+  // A second start-up IPI is sent in case the first one is lost, and is ignored otherwise.
+  for _ in 0..2 {
+    local_apic.send_ipi(apic_id, ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | vector);
+    pit::busy_wait(STARTUP_DELAY);
+    if AP_STARTED.load(Ordering::Acquire) {
+      return true;
+    }
+  }
+
+  for _ in 0..STARTUP_TIMEOUT {
+    if AP_STARTED.load(Ordering::Acquire) {
+      return true;
+    }
+    pit::busy_wait(1000);
+  }
+  AP_STARTED.load(Ordering::Acquire)
+}
+
+/// Entry point of the application processors, called by the trampoline on their own stack with
+/// interrupts disabled.
+extern "C" fn ap_entry(cpu: usize) -> ! {
+  let double_fault_stack =
+    allocate_stack(DOUBLE_FAULT_STACK_ORDER).expect("Failed to allocate the double fault stack");
+  let tss = init_ap_gdt(double_fault_stack);
+  unsafe { init_ap_per_cpu(cpu, tss) };
+  init_ap_idt();
+  // The identity mapping of the trampoline is going to be removed.
+  tlb::flush_all();
+
+  init_paging_features();
+  init_protection_features();
+  init_fpu();
+
+  let local_apic = LocalApic::get().expect("Application processor started without local APIC");
+  if let Some(madt) = MADT.r#try() {
+    unsafe { local_apic.enable(madt) };
+  }
+
+  println!("[INFO ] CPU {} online, APIC ID {}.", cpu, local_apic.id());
+  init_scheduler();
+  ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+  AP_STARTED.store(true, Ordering::Release);
+
+  unsafe { interrupt::enable() };
+  idle()
+}
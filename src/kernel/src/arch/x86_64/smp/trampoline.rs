@@ -0,0 +1,170 @@
+//! # Start-up Trampoline
+//!
+//! An application processor starts in real mode, at the start of the page given by the start-up
+//! IPI, which must be below 1MiB. The trampoline is copied there, switches to protected mode with
+//! a temporary GDT, enables paging with the kernel page tables and switches to long mode, then
+//! jumps to the kernel on the stack given in its data.
+//!
+//! The trampoline runs at its physical address, which must be identity mapped while it switches to
+//! long mode. Addresses in real and protected mode are relative to its base, kept in `ebx`, and
+//! RIP-relative in long mode.
+//!
+//! No stack is usable until long mode: `esp` is 0 after INIT, and the identity mapping is
+//! read-only. The far jumps between the modes are thus patched with the physical addresses of
+//! their targets while in real mode, instead of pushing them for a far return.
+
+use core::arch::global_asm;
+use core::ptr::addr_of;
+
+use crate::arch::PhysicalFrame;
+use crate::mem::physical_to_virtual;
+
+global_asm!(
+  r#"
+  .section .rodata.ap_trampoline, "a"
+  .balign 16
+  .global ap_trampoline_start
+  .global ap_trampoline_data
+  .global ap_trampoline_end
+
+  .code16
+ap_trampoline_start:
+  cli
+  cld
+  mov %cs, %ax
+  mov %ax, %ds
+  movzx %ax, %ebx
+  shl $4, %ebx
+
+  // The base of the GDT pointer is the physical address of the GDT.
+  lea (ap_trampoline_gdt - ap_trampoline_start)(%ebx), %eax
+  movl %eax, (ap_trampoline_gdt_pointer - ap_trampoline_start + 2)
+  lgdtl (ap_trampoline_gdt_pointer - ap_trampoline_start)
+
+  // Patch the offsets of the far jumps.
+  lea (ap_trampoline_32 - ap_trampoline_start)(%ebx), %eax
+  movl %eax, (ap_trampoline_jump_32 - ap_trampoline_start + 2)
+  lea (ap_trampoline_64 - ap_trampoline_start)(%ebx), %eax
+  movl %eax, (ap_trampoline_jump_64 - ap_trampoline_start + 1)
+
+  mov %cr0, %eax
+  or $1, %eax
+  mov %eax, %cr0
+
+  // Far jump to the 32-bit code segment, with a 32-bit offset: `ljmpl $0x08, $offset`.
+ap_trampoline_jump_32:
+  .byte 0x66, 0xEA
+  .long 0
+  .word 0x08
+
+  .code32
+ap_trampoline_32:
+  mov $0x10, %ax
+  mov %ax, %ds
+  mov %ax, %es
+  mov %ax, %ss
+
+  // PAE and global pages.
+  mov %cr4, %eax
+  or $((1 << 5) | (1 << 7)), %eax
+  mov %eax, %cr4
+
+  mov (ap_trampoline_data - ap_trampoline_start)(%ebx), %eax
+  mov %eax, %cr3
+
+  // EFER with long mode and no-execute enabled, as on the bootstrap processor.
+  mov $0xC0000080, %ecx
+  mov (ap_trampoline_data - ap_trampoline_start + 8)(%ebx), %eax
+  xor %edx, %edx
+  wrmsr
+
+  // Paging and write protection.
+  mov %cr0, %eax
+  or $((1 << 31) | (1 << 16)), %eax
+  mov %eax, %cr0
+
+  // Far jump to the 64-bit code segment: `ljmp $0x18, $offset`.
+ap_trampoline_jump_64:
+  .byte 0xEA
+  .long 0
+  .word 0x18
+
+  .code64
+ap_trampoline_64:
+  xor %eax, %eax
+  mov %ax, %ds
+  mov %ax, %es
+  mov %ax, %ss
+  mov %ax, %fs
+  mov %ax, %gs
+
+  mov (ap_trampoline_data + 16)(%rip), %rsp
+  mov (ap_trampoline_data + 32)(%rip), %rdi
+  mov (ap_trampoline_data + 24)(%rip), %rax
+  xor %ebp, %ebp
+  call *%rax
+  ud2
+
+  .balign 8
+ap_trampoline_gdt:
+  .quad 0
+  // 32-bit code.
+  .quad 0x00CF9A000000FFFF
+  // Data.
+  .quad 0x00CF92000000FFFF
+  // 64-bit code.
+  .quad 0x00AF9A000000FFFF
+ap_trampoline_gdt_pointer:
+  .word 4 * 8 - 1
+  .long 0
+
+  .balign 8
+ap_trampoline_data:
+  .skip 40
+ap_trampoline_end:
+"#,
+  options(att_syntax)
+);
+
+extern "C" {
+  static ap_trampoline_start: u8;
+  static ap_trampoline_data: u8;
+  static ap_trampoline_end: u8;
+}
+
+/// Data read by the trampoline, at `ap_trampoline_data`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TrampolineData {
+  /// Physical address of the level 4 page table, below 4GiB.
+  pub cr3:      u64,
+  /// Value of the EFER, including `LONG_MODE_ENABLE` but not `LONG_MODE_ACTIVE`.
+  pub efer:     u64,
+  /// Top of the stack of the processor.
+  pub stack:    u64,
+  /// Entry point in the kernel, `extern "C" fn(argument: usize) -> !`.
+  pub entry:    u64,
+  /// Argument of the entry point.
+  pub argument: u64,
+}
+
+/// Copy the trampoline into the frame.
+///
+/// ## Safety
+/// The frame must be below 1MiB, and not in use.
+pub unsafe fn install(frame: PhysicalFrame) {
+  let start = addr_of!(ap_trampoline_start);
+  let size = addr_of!(ap_trampoline_end) as usize - start as usize;
+  let target = physical_to_virtual(frame.start_address()).as_mut_ptr::<u8>();
+  core::ptr::copy_nonoverlapping(start, target, size);
+}
+
+/// Write the data read by the trampoline installed in the frame.
+///
+/// ## Safety
+/// The trampoline must be installed in the frame, and no processor may be starting on it.
+pub unsafe fn write_data(frame: PhysicalFrame, data: TrampolineData) {
+  let offset = addr_of!(ap_trampoline_data) as usize - addr_of!(ap_trampoline_start) as usize;
+  let target = physical_to_virtual(frame.start_address()) + offset as u64;
+  core::ptr::write_volatile(target.as_mut_ptr::<TrampolineData>(), data);
+}
@@ -0,0 +1,28 @@
+//! # Clock Source Selection
+//!
+//! The HPET and the TSC are probed at boot. The invariant TSC is preferred, then the HPET, and
+//! the PIT tick is the last resort.
+
+use crate::arch::shared::time::clock_source;
+use crate::arch::shared::time::register_clock_source;
+use crate::arch::x86_64::hw::hpet;
+use crate::arch::x86_64::hw::hpet::HPET_CLOCK_SOURCE;
+use crate::arch::x86_64::hw::tsc;
+use crate::arch::x86_64::hw::tsc::TSC_CLOCK_SOURCE;
+use crate::println;
+
+/// Probe the clock sources, and select the best one for the monotonic counter.
+pub fn init_clock_sources() {
+  match hpet::init_hpet() {
+    Ok(()) => {
+      register_clock_source(&HPET_CLOCK_SOURCE);
+    }
+    Err(err) => println!("[INFO ] HPET unavailable: {:?}.", err),
+  }
+
+  if tsc::calibrate_tsc() != 0 && tsc::is_invariant() {
+    register_clock_source(&TSC_CLOCK_SOURCE);
+  }
+
+  println!("[INFO ] Clock source: {}.", clock_source().name);
+}
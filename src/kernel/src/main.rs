@@ -9,14 +9,31 @@
 
 extern crate alloc;
 
+use bootloader::entry_point;
+use bootloader::BootInfo;
+
 use crate::arch::VirtualAddress;
+use crate::proc::sched::init_scheduler;
 #[rustfmt::skip]
 #[cfg(target_arch = "x86_64")]
 use crate::arch::x86_64::{
   gdt::init_gdt,
+  percpu::init_per_cpu,
+  acpi::init_acpi,
+  cpu::init_paging_features,
+  cpu::init_protection_features,
+  fpu::init_fpu,
+  hw::apic_timer::init_apic_timer,
+  hw::keyboard::init_keyboard,
+  hw::pit::init_pit,
+  hw::rtc,
+  idle::idle,
   interrupt::init_idt,
+  paging::image::protect_kernel_image,
+  smp::start_application_processors,
+  interrupt::init_interrupt_controller,
+  time::init_clock_sources,
 };
-use crate::arch::x86_64::active_level_4_table;
 
 pub mod allocator;
 pub mod arch;
@@ -32,48 +49,99 @@ mod panic;
 #[cfg(test)]
 mod test;
 
-/// This function is the entry point, since the linker looks for a function
-/// named `_start` by default
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-  log::init();
+entry_point!(kernel_main);
 
-  println!("[INFO ] Kernel is booting.");
+/// Resynchronize the wall-clock time with the RTC every such seconds.
+const RTC_RESYNC_INTERVAL: u64 = 60;
 
-  /// Prepare and set up kernel memory page tables.
-  println!("[DEBUG] Initialize page table.");
-  let page_table = unsafe { active_level_4_table(VirtualAddress::new(0x0000)) };
+/// This function is the entry point. `entry_point!` exports it as `_start`, which the linker looks
+/// for by default, and checks that it takes the boot info passed by the bootloader.
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+  log::init();
 
-  // Prepare and set up the heap allocator.
-  // Initialize the heap through the heap allocator.
-  let heap = unsafe {};
+  println!("[INFO ] Kernel is booting.");
 
   // Initialize the GDT and IDT.
   // Replace the GDT and IDT as soon as possible, instead of ones provided by UEFI.
   // Install the exception handlers, which allows the kernel to catch and report exception
   // gracefully.
-  init_gdt();
+  let tss = init_gdt();
+  // Install the per-CPU data of this processor, reachable through the GS base.
+  unsafe { init_per_cpu(tss) };
   init_idt();
 
-  // Install a TSS for this processor. This allows us to set up per-CPU data structures.
-  let tss = ();
-  // let tss: Box<()> = Box::new(());
-  //PerCpuImpl::install(tss);
+  /// Prepare and set up kernel memory page tables and the physical frame allocator.
+  println!("[DEBUG] Initialize page table.");
+  let physical_memory_offset = VirtualAddress::new(boot_info.physical_memory_offset);
+  unsafe { mem::init_memory(physical_memory_offset, &boot_info.memory_map) };
+
+  // Enable NX, write protection and global pages, then remap the kernel image W^X.
+  init_paging_features();
+  if let Err(err) = protect_kernel_image() {
+    println!("[ERROR] Failed to protect the kernel image: {:?}.", err);
+  }
+  init_protection_features();
+  init_fpu();
+
+  // Prepare and set up the heap allocator.
+  // Initialize the heap through the heap allocator.
+  println!("[DEBUG] Initialize kernel heap.");
+  allocator::init_heap().expect("Failed to initialize kernel heap");
+
+  // Reserve physically contiguous memory for DMA buffers, huge pages and kernel stacks.
+  println!("[DEBUG] Initialize buddy allocator.");
+  allocator::init_buddy();
+  print!(
+    "[DEBUG] Buddy allocator: {}",
+    allocator::BUDDY_ALLOCATOR.lock().statistics()
+  );
 
   // Parse the static ACPI tables.
+  if let Err(err) = init_acpi() {
+    println!("[ERROR] Failed to find the ACPI tables: {:?}.", err);
+  }
 
   // Initialize PCI.
 
-  // Start scheduler.
+  // Enable hardware interrupts, delivered by the APICs, or the PICs on legacy machines.
+  init_interrupt_controller();
+  if let Err(err) = init_pit() {
+    println!("[ERROR] Failed to start the system tick: {:?}.", err);
+  }
+  init_clock_sources();
+  // Stop the periodic tick when idle, with the APIC timer if available.
+  if let Err(err) = init_apic_timer() {
+    println!(
+      "[INFO ] APIC timer unavailable, keep the PIT tick: {:?}.",
+      err
+    );
+  }
+  rtc::init_rtc();
+  if let Err(err) = rtc::enable_resync(RTC_RESYNC_INTERVAL) {
+    println!(
+      "[ERROR] Failed to enable the RTC resynchronization: {:?}.",
+      err
+    );
+  }
+  if let Err(err) = init_keyboard() {
+    println!("[ERROR] Failed to initialize the keyboard: {:?}.", err);
+  }
+
+  // Start the application processors, which idle until there is something to run.
+  if let Err(err) = start_application_processors() {
+    println!(
+      "[ERROR] Failed to start the application processors: {:?}.",
+      err
+    );
+  }
+  unsafe { arch::interrupt::enable() };
+
+  // Start the scheduler, this context becomes the idle thread of the bootstrap processor.
+  init_scheduler();
 
   // Run test.
   #[cfg(test)]
   test_main();
 
-  loop {
-    println!("[TRACE] hlt");
-    unsafe {
-      core::arch::asm!("hlt");
-    }
-  }
+  idle()
 }
@@ -0,0 +1,239 @@
+//! # Physical Frame Allocator
+//!
+//! Hands out 4KiB physical frames from the regions marked as usable in the memory map provided by
+//! the bootloader. Frames are taken from the memory map in ascending order, and reclaimed frames
+//! are kept in a free list which is threaded through the frames themselves.
+
+use bootloader::bootinfo::MemoryMap;
+use bootloader::bootinfo::MemoryRegionType;
+
+use crate::arch::paging::PageSize;
+use crate::arch::paging::Size4KiB;
+use crate::arch::PhysicalAddress;
+use crate::arch::PhysicalFrame;
+use crate::arch::PtrWidth;
+use crate::arch::VirtualAddress;
+use crate::println;
+use crate::support::sync::IrqSpinLock;
+
+/// Physical memory below 1MiB is never handed out, it is left to the BIOS data area and the
+/// real-mode code.
+pub const LOW_MEMORY_LIMIT: PtrWidth = 0x0010_0000;
+
+/// Global physical frame allocator.
+pub static FRAME_ALLOCATOR: IrqSpinLock<Option<BootInfoFrameAllocator>> = IrqSpinLock::new(None);
+
+/// Allocator of physical frames.
+///
+/// ## Safety
+/// The implementer must guarantee that the allocator never hands out a frame which is in use.
+pub unsafe trait FrameAllocator<S: PageSize> {
+  /// Allocate a physical frame, return `None` if out of memory.
+  fn allocate_frame(&mut self) -> Option<PhysicalFrame<S>>;
+}
+
+/// Deallocator of physical frames.
+pub trait FrameDeallocator<S: PageSize> {
+  /// Give back a physical frame to the allocator.
+  ///
+  /// ## Safety
+  /// The caller must guarantee that the frame is no longer in use.
+  unsafe fn deallocate_frame(&mut self, frame: PhysicalFrame<S>);
+}
+
+/// Header written at the start of a reclaimed frame, linking it to the next free frame.
+struct FreeFrame {
+  next: Option<PhysicalFrame>,
+}
+
+/// Frame allocator built from the memory map of the bootloader.
+pub struct BootInfoFrameAllocator {
+  memory_map:             &'static MemoryMap,
+  physical_memory_offset: VirtualAddress,
+  /// Index of the memory region to allocate fresh frames from.
+  region:                 usize,
+  /// Next fresh frame address in the current region.
+  next:                   PhysicalAddress,
+  /// Head of the reclaimed frame list.
+  free_list:              Option<PhysicalFrame>,
+  /// Count of frames in use.
+  allocated:              usize,
+}
+
+impl BootInfoFrameAllocator {
+  /// Create a frame allocator from the memory map.
+  ///
+  /// ## Safety
+  /// The caller must guarantee that the usable regions of the memory map are really unused, and
+  /// that the whole physical memory is mapped at `physical_memory_offset`.
+  pub unsafe fn new(
+    memory_map: &'static MemoryMap,
+    physical_memory_offset: VirtualAddress,
+  ) -> Self {
+    Self {
+      memory_map,
+      physical_memory_offset,
+      region: 0,
+      next: PhysicalAddress::new(LOW_MEMORY_LIMIT),
+      free_list: None,
+      allocated: 0,
+    }
+  }
+
+  /// Count of frames in usable regions above the low memory.
+  pub fn usable_frames(&self) -> usize {
+    self
+      .memory_map
+      .iter()
+      .filter(|region| region.region_type == MemoryRegionType::Usable)
+      .map(|region| {
+        let start = region.range.start_addr().max(LOW_MEMORY_LIMIT);
+        let end = region.range.end_addr().max(start);
+        ((end - start) / Size4KiB::SIZE) as usize
+      })
+      .sum()
+  }
+
+  /// Return the highest usable frame in the low memory, which is never handed out, for the
+  /// real-mode code. The first frame is left to the interrupt vector table and the BIOS data.
+  pub fn low_memory_frame(&self) -> Option<PhysicalFrame> {
+    self
+      .memory_map
+      .iter()
+      .filter(|region| region.region_type == MemoryRegionType::Usable)
+      .filter_map(|region| {
+        let start = region.range.start_addr().max(Size4KiB::SIZE);
+        let end = region.range.end_addr().min(LOW_MEMORY_LIMIT) & !(Size4KiB::SIZE - 1);
+        (start + Size4KiB::SIZE <= end).then(|| end - Size4KiB::SIZE)
+      })
+      .max()
+      .map(|address| PhysicalFrame::containing_address(PhysicalAddress::new(address)))
+  }
+
+  /// Count of frames in use.
+  #[inline]
+  pub fn allocated_frames(&self) -> usize {
+    self.allocated
+  }
+
+  /// Take a frame which has never been handed out before.
+  fn allocate_fresh_frame(&mut self) -> Option<PhysicalFrame> {
+    while let Some(region) = self.memory_map.get(self.region) {
+      if region.region_type == MemoryRegionType::Usable {
+        let start = region.range.start_addr().max(LOW_MEMORY_LIMIT);
+        if self.next.as_raw() < start {
+          self.next = PhysicalAddress::new(start);
+        }
+        if self.next.as_raw() + Size4KiB::SIZE <= region.range.end_addr() {
+          let frame = PhysicalFrame::containing_address(self.next);
+          self.next += Size4KiB::SIZE;
+          return Some(frame);
+        }
+      }
+      self.region += 1;
+    }
+    None
+  }
+
+  /// Take `count` contiguous frames which have never been handed out before, and return the first
+  /// one. The frames skipped before them are put into the free list.
+  pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysicalFrame> {
+    let size = count as PtrWidth * Size4KiB::SIZE;
+    let (index, start) = self.memory_map[self.region..]
+      .iter()
+      .enumerate()
+      .filter(|(_, region)| region.region_type == MemoryRegionType::Usable)
+      .map(|(i, region)| {
+        let start = region.range.start_addr().max(LOW_MEMORY_LIMIT);
+        let start = if i == 0 {
+          start.max(self.next.as_raw())
+        } else {
+          start
+        };
+        (self.region + i, start, region.range.end_addr())
+      })
+      .find(|&(_, start, end)| start + size <= end)
+      .map(|(index, start, _)| (index, start))?;
+
+    // Give the rest of the regions skipped to the free list.
+    while self.region < index {
+      let region = &self.memory_map[self.region];
+      if region.region_type == MemoryRegionType::Usable {
+        let mut address = region.range.start_addr().max(LOW_MEMORY_LIMIT).max(self.next.as_raw());
+        while address + Size4KiB::SIZE <= region.range.end_addr() {
+          self.push_free_frame(PhysicalFrame::containing_address(PhysicalAddress::new(
+            address,
+          )));
+          address += Size4KiB::SIZE;
+        }
+      }
+      self.region += 1;
+    }
+
+    self.next = PhysicalAddress::new(start + size);
+    self.allocated += count;
+    Some(PhysicalFrame::containing_address(PhysicalAddress::new(
+      start,
+    )))
+  }
+
+  fn push_free_frame(&mut self, frame: PhysicalFrame) {
+    unsafe {
+      self.free_frame_header(frame).write(FreeFrame {
+        next: self.free_list,
+      });
+    }
+    self.free_list = Some(frame);
+  }
+
+  #[inline]
+  fn free_frame_header(&self, frame: PhysicalFrame) -> *mut FreeFrame {
+    (self.physical_memory_offset + frame.start_address().as_raw()).as_mut_ptr()
+  }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+  fn allocate_frame(&mut self) -> Option<PhysicalFrame<Size4KiB>> {
+    let frame = match self.free_list {
+      Some(frame) => {
+        self.free_list = unsafe { (*self.free_frame_header(frame)).next };
+        frame
+      }
+      None => self.allocate_fresh_frame()?,
+    };
+    self.allocated += 1;
+    Some(frame)
+  }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+  unsafe fn deallocate_frame(&mut self, frame: PhysicalFrame<Size4KiB>) {
+    // A double or bogus free would underflow the count, and put a frame twice in the free list.
+    let Some(allocated) = self.allocated.checked_sub(1) else {
+      println!(
+        "[ERROR] Frame {:?} freed while no frame is allocated.",
+        frame
+      );
+      return;
+    };
+    self.push_free_frame(frame);
+    self.allocated = allocated;
+  }
+}
+
+/// Allocate a physical frame from the global frame allocator.
+pub fn allocate_frame() -> Option<PhysicalFrame> {
+  FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()
+}
+
+/// Give back a physical frame to the global frame allocator.
+///
+/// ## Safety
+/// The caller must guarantee that the frame is no longer in use.
+pub unsafe fn deallocate_frame(frame: PhysicalFrame) {
+  if let Some(ref mut allocator) = *FRAME_ALLOCATOR.lock() {
+    allocator.deallocate_frame(frame)
+  } else {
+    panic!("deallocate_frame: frame allocator not initialized");
+  }
+}
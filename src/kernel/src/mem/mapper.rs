@@ -1,28 +1,340 @@
-// use core::marker::PhantomData;
-
-// use crate::arch::PhysicalAddress;
-// use crate::mem::MemoryKind;
-
-// pub struct PageMapper {
-//   kind:     MemoryKind,
-//   address:  PhysicalAddress,
-//   _phantom: PhantomData<()>,
-// }
-
-// impl PageMapper {
-//   pub fn new(kind: MemoryKind, address: PhysicalAddress) -> Self {
-//     Self {
-//       kind,
-//       address,
-//       _phantom: PhantomData,
-//     }
-//   }
-
-//   pub fn create(kind: MemoryKind) -> Option<Self> {
-//     None
-//   }
-
-//   pub fn current(kind: MemoryKind) -> Option<Self> {
-//     None
-//   }
-// }
+//! # Page Mapper
+//!
+//! Maps virtual pages to physical frames by walking the 4-level page tables. The page tables are
+//! accessed through the mapping of the whole physical memory at `physical_memory_offset`.
+//!
+//! Besides 4KiB pages, 2MiB and 1GiB huge pages are mapped by level 2 and level 3 entries with the
+//! `HUGE_PAGE` flag.
+
+use crate::arch::paging::tlb;
+use crate::arch::paging::PageSize;
+use crate::arch::paging::PageTable;
+use crate::arch::paging::PageTableEntry;
+use crate::arch::paging::PageTableFlags;
+use crate::arch::paging::PageTableLevel;
+use crate::arch::paging::Size4KiB;
+use crate::arch::walk_page_tables;
+use crate::arch::FrameError;
+use crate::arch::Page;
+use crate::arch::PhysicalAddress;
+use crate::arch::PhysicalFrame;
+use crate::arch::VirtualAddress;
+use crate::mem::frame::FrameAllocator;
+
+/// Error occurred when mapping a page.
+#[derive(Debug)]
+pub enum MapToError<S: PageSize> {
+  /// No frame is available for creating a new page table.
+  FrameAllocationFailed,
+  /// An entry on the path is a huge page, so that there is no lower page table.
+  ParentEntryHugePage,
+  /// The page is already mapped to the given frame.
+  PageAlreadyMapped(PhysicalFrame<S>),
+}
+
+/// Error occurred when unmapping a page.
+#[derive(Debug)]
+pub enum UnmapError {
+  /// An entry on the path is a huge page, so that there is no lower page table.
+  ParentEntryHugePage,
+  /// The page is not mapped, or mapped with another page size.
+  PageNotMapped,
+}
+
+/// Error occurred when updating the flags of a page.
+#[derive(Debug)]
+pub enum FlagUpdateError {
+  /// An entry on the path is a huge page, so that there is no lower page table.
+  ParentEntryHugePage,
+  /// The page is not mapped, or mapped with another page size.
+  PageNotMapped,
+}
+
+/// Error occurred when translating a page.
+#[derive(Debug)]
+pub enum TranslateError {
+  /// An entry on the path is a huge page, so that there is no lower page table.
+  ParentEntryHugePage,
+  /// The page is not mapped, or mapped with another page size.
+  PageNotMapped,
+}
+
+impl From<FrameError> for UnmapError {
+  fn from(err: FrameError) -> Self {
+    match err {
+      FrameError::FrameNotPresent => Self::PageNotMapped,
+      FrameError::HugePage => Self::ParentEntryHugePage,
+    }
+  }
+}
+
+impl From<FrameError> for FlagUpdateError {
+  fn from(err: FrameError) -> Self {
+    match err {
+      FrameError::FrameNotPresent => Self::PageNotMapped,
+      FrameError::HugePage => Self::ParentEntryHugePage,
+    }
+  }
+}
+
+impl From<FrameError> for TranslateError {
+  fn from(err: FrameError) -> Self {
+    match err {
+      FrameError::FrameNotPresent => Self::PageNotMapped,
+      FrameError::HugePage => Self::ParentEntryHugePage,
+    }
+  }
+}
+
+/// A page whose TLB entry must be flushed after its mapping changed.
+#[derive(Debug)]
+#[must_use = "Page table changes must be flushed or ignored."]
+pub struct MapperFlush(VirtualAddress);
+
+impl MapperFlush {
+  /// Flush the TLB entry of the page.
+  #[inline]
+  pub fn flush(self) {
+    tlb::flush(self.0);
+  }
+
+  /// Do not flush the TLB entry, e.g. the whole TLB is going to be flushed.
+  #[inline]
+  pub fn ignore(self) {}
+}
+
+/// Mapper over the page tables of an address space.
+pub struct PageMapper<'a> {
+  level_4_table:          &'a mut PageTable,
+  physical_memory_offset: VirtualAddress,
+}
+
+impl<'a> PageMapper<'a> {
+  /// Create a page mapper over the given level 4 page table.
+  ///
+  /// ## Safety
+  /// The caller must guarantee that the whole physical memory is mapped at
+  /// `physical_memory_offset`, and that the level 4 page table is not aliased.
+  pub unsafe fn new(
+    level_4_table: &'a mut PageTable,
+    physical_memory_offset: VirtualAddress,
+  ) -> Self {
+    Self {
+      level_4_table,
+      physical_memory_offset,
+    }
+  }
+
+  /// Return the level 4 page table.
+  #[inline]
+  pub fn level_4_table(&mut self) -> &mut PageTable {
+    self.level_4_table
+  }
+
+  /// Return the offset where the physical memory is mapped.
+  #[inline]
+  pub fn physical_memory_offset(&self) -> VirtualAddress {
+    self.physical_memory_offset
+  }
+
+  /// Map the page to the frame. Pages of 2MiB and 1GiB are mapped as huge pages.
+  ///
+  /// Missing intermediate page tables are allocated from the frame allocator.
+  ///
+  /// ## Safety
+  /// Mapping a page may break memory safety, e.g. by aliasing a frame in use.
+  pub unsafe fn map_to<S, A>(
+    &mut self,
+    page: Page<S>,
+    frame: PhysicalFrame<S>,
+    flags: PageTableFlags,
+    allocator: &mut A,
+  ) -> Result<MapperFlush, MapToError<S>>
+  where
+    S: PageSize,
+    A: FrameAllocator<Size4KiB> + ?Sized,
+  {
+    let level = PageTableLevel::of_page_size::<S>();
+    let address = page.start_address();
+
+    // Intermediate tables must be at least as permissive as the page itself.
+    let parent_flags = PageTableFlags::PRESENT
+      | PageTableFlags::WRITABLE
+      | (flags & PageTableFlags::USER_ACCESSIBLE);
+
+    let offset = self.physical_memory_offset;
+    let mut table = &mut *self.level_4_table;
+    let mut current = PageTableLevel::Four;
+    while current != level {
+      let entry = &mut table[address.page_table_index(current)];
+      table = create_next_table(offset, entry, parent_flags, allocator)?;
+      current = current.lower().unwrap();
+    }
+
+    let entry = &mut table[address.page_table_index(level)];
+    if !entry.is_unused() {
+      return Err(MapToError::PageAlreadyMapped(
+        PhysicalFrame::containing_address(entry.address()),
+      ));
+    }
+    if level == PageTableLevel::One {
+      entry.set_address(frame.start_address(), flags);
+    } else {
+      entry.set_address(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
+    }
+
+    Ok(MapperFlush(address))
+  }
+
+  /// Unmap the page, and return the frame it was mapped to.
+  ///
+  /// The frame and the empty intermediate page tables are not deallocated.
+  ///
+  /// ## Safety
+  /// The caller must guarantee that the page is no longer in use.
+  pub unsafe fn unmap<S: PageSize>(
+    &mut self,
+    page: Page<S>,
+  ) -> Result<(PhysicalFrame<S>, MapperFlush), UnmapError> {
+    let entry = self.entry_mut(page)?;
+    let frame = PhysicalFrame::containing_address(entry.address());
+    entry.set_unused();
+
+    Ok((frame, MapperFlush(page.start_address())))
+  }
+
+  /// Update the flags of the page. `HUGE_PAGE` is kept for huge pages.
+  ///
+  /// ## Safety
+  /// Changing the flags of a page may break memory safety, e.g. by making it non-present.
+  pub unsafe fn update_flags<S: PageSize>(
+    &mut self,
+    page: Page<S>,
+    flags: PageTableFlags,
+  ) -> Result<MapperFlush, FlagUpdateError> {
+    let entry = self.entry_mut(page)?;
+    entry.set_flags(flags | (entry.flags() & PageTableFlags::HUGE_PAGE));
+
+    Ok(MapperFlush(page.start_address()))
+  }
+
+  /// Translate the virtual address into the physical address. If not mapped, return `None`.
+  pub fn translate(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+    walk_page_tables(self.level_4_table, address, self.physical_memory_offset)
+      .map(|(address, _)| address)
+  }
+
+  /// Return the frame which the page is mapped to.
+  pub fn translate_page<S: PageSize>(
+    &self,
+    page: Page<S>,
+  ) -> Result<PhysicalFrame<S>, TranslateError> {
+    let level = PageTableLevel::of_page_size::<S>();
+    let address = page.start_address();
+
+    let offset = self.physical_memory_offset;
+    let mut table = &*self.level_4_table;
+    let mut current = PageTableLevel::Four;
+    while current != level {
+      table = unsafe { next_table(offset, &table[address.page_table_index(current)]) }?;
+      current = current.lower().unwrap();
+    }
+
+    let entry = &table[address.page_table_index(level)];
+    if !is_mapping_of(entry, level) {
+      return Err(TranslateError::PageNotMapped);
+    }
+    Ok(PhysicalFrame::containing_address(entry.address()))
+  }
+
+  /// Walk down to the entry mapping the page, without creating any page table.
+  fn entry_mut<S: PageSize>(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, FrameError> {
+    let level = PageTableLevel::of_page_size::<S>();
+    let address = page.start_address();
+
+    let offset = self.physical_memory_offset;
+    let mut table = &mut *self.level_4_table;
+    let mut current = PageTableLevel::Four;
+    while current != level {
+      table = unsafe { next_table_mut(offset, &mut table[address.page_table_index(current)]) }?;
+      current = current.lower().unwrap();
+    }
+
+    let entry = &mut table[address.page_table_index(level)];
+    if !is_mapping_of(entry, level) {
+      return Err(FrameError::FrameNotPresent);
+    }
+    Ok(entry)
+  }
+}
+
+/// True if the entry maps a page of the level, i.e. present, and huge unless at level 1.
+#[inline]
+fn is_mapping_of(entry: &PageTableEntry, level: PageTableLevel) -> bool {
+  let flags = entry.flags();
+  flags.contains(PageTableFlags::PRESENT)
+    && flags.contains(PageTableFlags::HUGE_PAGE) == (level != PageTableLevel::One)
+}
+
+/// Return the page table that the entry points to.
+///
+/// ## Safety
+/// The entry must belong to a page table of level 2 or higher.
+unsafe fn next_table(
+  offset: VirtualAddress,
+  entry: &PageTableEntry,
+) -> Result<&PageTable, FrameError> {
+  let frame = entry.frame()?;
+  Ok(&*table_ptr(offset, frame.start_address()))
+}
+
+/// Return the page table that the entry points to.
+///
+/// ## Safety
+/// The entry must belong to a page table of level 2 or higher.
+unsafe fn next_table_mut(
+  offset: VirtualAddress,
+  entry: &mut PageTableEntry,
+) -> Result<&mut PageTable, FrameError> {
+  let frame = entry.frame()?;
+  Ok(&mut *table_ptr(offset, frame.start_address()))
+}
+
+/// Return the page table that the entry points to, allocate a new one if it's not present.
+///
+/// ## Safety
+/// The entry must belong to a page table of level 2 or higher.
+unsafe fn create_next_table<'b, S, A>(
+  offset: VirtualAddress,
+  entry: &'b mut PageTableEntry,
+  flags: PageTableFlags,
+  allocator: &mut A,
+) -> Result<&'b mut PageTable, MapToError<S>>
+where
+  S: PageSize,
+  A: FrameAllocator<Size4KiB> + ?Sized,
+{
+  let created = if !entry.flags().contains(PageTableFlags::PRESENT) {
+    let frame = allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+    entry.set_frame(frame, flags);
+    true
+  } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+    return Err(MapToError::ParentEntryHugePage);
+  } else {
+    if !entry.flags().contains(flags) {
+      entry.set_flags(entry.flags() | flags);
+    }
+    false
+  };
+
+  let table = &mut *table_ptr(offset, entry.address());
+  if created {
+    table.reset();
+  }
+  Ok(table)
+}
+
+#[inline]
+fn table_ptr(offset: VirtualAddress, address: PhysicalAddress) -> *mut PageTable {
+  (offset + address.as_raw()).as_mut_ptr()
+}
@@ -0,0 +1,79 @@
+//! # Memory-Mapped I/O
+//!
+//! Device registers must not be cached, while the mapping of the whole physical memory is
+//! write-back. Device register ranges are mapped again, uncached, in a dedicated region of the
+//! kernel address space. The region only grows, device mappings are never released.
+
+use spin::Mutex;
+
+use crate::arch::paging::PageSize;
+use crate::arch::paging::PageTableFlags;
+use crate::arch::paging::Size4KiB;
+use crate::arch::Page;
+use crate::arch::PhysicalAddress;
+use crate::arch::PhysicalFrame;
+use crate::arch::PtrWidth;
+use crate::arch::VirtualAddress;
+use crate::mem::frame::FRAME_ALLOCATOR;
+use crate::mem::mapper::MapToError;
+use crate::mem::KERNEL_MAPPER;
+
+/// Start address of the device mapping region.
+pub const MMIO_START: PtrWidth = 0x_5555_5555_0000;
+/// Maximum size of the device mapping region.
+pub const MMIO_MAX_SIZE: PtrWidth = 0x4000_0000;
+
+/// Error occurred when mapping device registers.
+#[derive(Debug)]
+pub enum MmioError {
+  /// The device mapping region is full.
+  RegionExhausted,
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for MmioError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    Self::Map(err)
+  }
+}
+
+/// Next free address in the device mapping region.
+static MMIO_NEXT: Mutex<PtrWidth> = Mutex::new(MMIO_START);
+
+/// Map `size` bytes of device registers from the physical address, and return the virtual address
+/// of the physical address.
+///
+/// ## Safety
+/// The physical range must belong to a device, mapping normal memory uncached breaks nothing but
+/// performance, while aliasing it with a different cache type is undefined.
+pub unsafe fn map_mmio(
+  address: PhysicalAddress,
+  size: PtrWidth,
+) -> Result<VirtualAddress, MmioError> {
+  let first = PhysicalFrame::<Size4KiB>::containing_address(address);
+  let offset = address - first.start_address();
+  let count = (offset + size).div_ceil(Size4KiB::SIZE);
+
+  let mut next = MMIO_NEXT.lock();
+  if *next + count * Size4KiB::SIZE > MMIO_START + MMIO_MAX_SIZE {
+    return Err(MmioError::RegionExhausted);
+  }
+  let start = Page::<Size4KiB>::containing_address(VirtualAddress::new(*next));
+
+  let flags = PageTableFlags::PRESENT
+    | PageTableFlags::WRITABLE
+    | PageTableFlags::WRITE_THROUGH_CACHING
+    | PageTableFlags::DISABLE_CACHE
+    | PageTableFlags::NO_EXECUTE;
+
+  let mut mapper = KERNEL_MAPPER.lock();
+  let mapper = mapper.as_mut().expect("map_mmio: kernel mapper not initialized");
+  let mut allocator = FRAME_ALLOCATOR.lock();
+  let allocator = allocator.as_mut().expect("map_mmio: frame allocator not initialized");
+  for i in 0..count {
+    mapper.map_to(start + i, first + i, flags, allocator)?.flush();
+  }
+
+  *next += count * Size4KiB::SIZE;
+  Ok(start.start_address() + offset)
+}
@@ -1,12 +1,62 @@
 //! # Memory Management
 
-use x86_64::structures::paging::OffsetPageTable;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
 
+use bootloader::bootinfo::MemoryMap;
+use spin::Mutex;
+
+use crate::allocator::KERNEL_HEAP_MAX_SIZE;
+use crate::allocator::KERNEL_HEAP_START;
 use crate::arch::active_level_4_table;
 use crate::arch::PhysicalAddress;
+use crate::arch::PtrWidth;
 use crate::arch::VirtualAddress;
+use crate::mem::mapper::PageMapper;
+use crate::mem::mmio::MMIO_MAX_SIZE;
+use crate::mem::mmio::MMIO_START;
+use crate::println;
 
+pub mod frame;
 pub(crate) mod mapper;
+pub mod mmio;
+
+/// Start of the user region of the address space.
+///
+/// The kernel lives in the lower half too: its image, the physical memory mapping and the boot
+/// structures are placed by the bootloader in the first level 4 entries, the heap and the MMIO
+/// window at fixed addresses below. The user region is the last quarter of the lower half, which
+/// no kernel mapping uses.
+pub const USER_SPACE_START: PtrWidth = 0x0000_6000_0000_0000;
+/// End of the user region of the address space, exclusive.
+pub const USER_SPACE_END: PtrWidth = 0x0000_8000_0000_0000;
+
+const _: () = assert!(KERNEL_HEAP_START + KERNEL_HEAP_MAX_SIZE as PtrWidth <= USER_SPACE_START);
+const _: () = assert!(MMIO_START + MMIO_MAX_SIZE <= USER_SPACE_START);
+
+/// True if the address lies in the user region.
+#[inline]
+pub const fn is_user_address(address: VirtualAddress) -> bool {
+  USER_SPACE_START <= address.as_raw() && address.as_raw() < USER_SPACE_END
+}
+
+/// Page mapper of the kernel address space.
+pub(crate) static KERNEL_MAPPER: Mutex<Option<PageMapper<'static>>> = Mutex::new(None);
+
+/// Offset where the whole physical memory is mapped in the kernel address space.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Return the address where the whole physical memory is mapped in the kernel address space.
+#[inline]
+pub fn physical_memory_offset() -> VirtualAddress {
+  VirtualAddress::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Return the virtual address where the physical address is mapped in the kernel address space.
+#[inline]
+pub fn physical_to_virtual(address: PhysicalAddress) -> VirtualAddress {
+  VirtualAddress::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed)) + address.as_raw()
+}
 
 pub enum MemoryKind {
   /// Kernel memory
@@ -23,10 +73,25 @@ pub struct MemoryDescriptor {
 }
 
 /// ## Safety
-pub unsafe fn init_memory(physical_address_offset: VirtualAddress)
-// -> ()<'static>
-{
+/// The caller must guarantee that the whole physical memory is mapped at
+/// `physical_address_offset`, and the memory map comes from the bootloader.
+pub unsafe fn init_memory(physical_address_offset: VirtualAddress, memory_map: &'static MemoryMap) {
+  PHYSICAL_MEMORY_OFFSET.store(physical_address_offset.as_raw(), Ordering::Relaxed);
   let level_4_table = unsafe { active_level_4_table(physical_address_offset) };
+  // The bootloader places its mappings in free entries, which must stay below the user region.
+  let first = usize::from(VirtualAddress::new(USER_SPACE_START).p4_index());
+  let last = usize::from(VirtualAddress::new(USER_SPACE_END - 1).p4_index());
+  assert!(
+    level_4_table.iter().take(last + 1).skip(first).all(|entry| entry.is_unused()),
+    "The bootloader mapped kernel memory in the user region"
+  );
+
+  let frame_allocator = frame::BootInfoFrameAllocator::new(memory_map, physical_address_offset);
+  println!(
+    "[DEBUG] Usable physical memory: {} KiB.",
+    frame_allocator.usable_frames() * 4
+  );
+  *frame::FRAME_ALLOCATOR.lock() = Some(frame_allocator);
 
-  // OffsetPageTable::new(level_4_table, physical_address_offset)
+  *KERNEL_MAPPER.lock() = Some(PageMapper::new(level_4_table, physical_address_offset));
 }
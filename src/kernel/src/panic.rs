@@ -1,11 +1,21 @@
 use core::panic::PanicInfo;
 
+use crate::arch::hw::vga::VGA_WRITER;
 use crate::println;
 
+/// Release the VGA writer if the panicking processor holds it, e.g. it panicked while printing,
+/// so that the message is printed.
+fn release_writer() {
+  if VGA_WRITER.is_owned_by_current_cpu() {
+    unsafe { VGA_WRITER.force_unlock() };
+  }
+}
+
 /// Panic handler must be implemented manually if using `no_std`.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+  release_writer();
   println!("[FATAL] {}", info);
   loop {
     unsafe {
@@ -20,6 +30,7 @@ fn panic(info: &PanicInfo) -> ! {
 fn panic(info: &PanicInfo) -> ! {
   use crate::arch::x86_64::hw::qemu;
 
+  release_writer();
   println!("[FATAL] {}", info);
   qemu::exit_qemu(qemu::QemuExitCode::Failed);
   loop {
@@ -1,5 +1,11 @@
 //! # Process Management
 
+pub mod sched;
+pub mod thread;
+pub mod wait_queue;
+
+pub use self::thread::Thread;
+
 pub struct Process {}
 
 pub struct Breakpoint {}
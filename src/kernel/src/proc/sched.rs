@@ -0,0 +1,231 @@
+//! # Scheduler
+//!
+//! Threads ready to run wait in a global FIFO run queue, from which any processor picks them. The
+//! scheduling is cooperative: a thread keeps its processor until it yields, blocks on a wait queue
+//! or exits. The boot context of each processor becomes its idle thread, which runs when no thread
+//! is ready, and picks the threads made ready meanwhile when the processor is next interrupted,
+//! e.g. by the reschedule IPI sent to an idle processor when a thread is made ready.
+//!
+//! A thread may be woken and picked by a processor while it's still being switched out of another
+//! one, so the processor waits until its `on_cpu` flag is cleared by [`finish_switch`].
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::ptr::NonNull;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+use crate::arch::x86_64::context::switch_context;
+use crate::arch::x86_64::fpu::switch_fpu_state;
+use crate::arch::x86_64::idle::kick_idle_processor;
+use crate::arch::x86_64::interrupt;
+use crate::arch::x86_64::percpu;
+use crate::proc::thread::SpawnError;
+use crate::proc::thread::ThreadState;
+use crate::proc::Thread;
+use crate::support::sync::IrqSpinLock;
+
+type ThreadEntry = Box<dyn FnOnce() + Send>;
+
+static RUN_QUEUE: IrqSpinLock<VecDeque<Arc<Thread>>> = IrqSpinLock::new(VecDeque::new());
+
+crate::per_cpu! {
+  /// Idle thread of the processor, whose reference is never released.
+  static IDLE_THREAD: AtomicPtr<Thread> = AtomicPtr::new(core::ptr::null_mut());
+  /// Thread switched out, whose reference is released by the next one once switched to.
+  static PREVIOUS_THREAD: AtomicPtr<Thread> = AtomicPtr::new(core::ptr::null_mut());
+}
+
+/// Turn the boot context of the current processor into its idle thread, and start scheduling.
+/// Called once on each processor.
+pub fn init_scheduler() {
+  let idle = Arc::into_raw(Arc::new(Thread::new_boot("idle"))).cast_mut();
+  IDLE_THREAD.get().store(idle, Ordering::Relaxed);
+  // The processor holds another reference on its current thread.
+  unsafe {
+    Arc::increment_strong_count(idle);
+    percpu::set_current_thread(NonNull::new(idle));
+  }
+}
+
+/// True if the scheduler is started on the current processor.
+fn is_started() -> bool {
+  percpu::is_installed() && percpu::current_thread().is_some()
+}
+
+/// Return the thread running on the current processor, if the scheduler is started on it.
+pub fn current() -> Option<Arc<Thread>> {
+  if !percpu::is_installed() {
+    return None;
+  }
+  percpu::current_thread().map(|thread| unsafe { clone_raw(thread.as_ptr()) })
+}
+
+/// True if the current thread may block: the scheduler is started on the processor, which runs
+/// neither an interrupt handler nor its idle thread.
+pub fn can_block() -> bool {
+  is_started()
+    && !percpu::in_interrupt()
+    && percpu::current_thread()
+      .is_some_and(|thread| thread.as_ptr() != IDLE_THREAD.get().load(Ordering::Relaxed))
+}
+
+/// True if threads are waiting for a processor.
+pub fn has_ready_threads() -> bool {
+  !RUN_QUEUE.lock().is_empty()
+}
+
+/// Create a thread running the function, ready to run.
+pub fn spawn<F>(name: &'static str, f: F) -> Result<Arc<Thread>, SpawnError>
+where
+  F: FnOnce() + Send + 'static,
+{
+  let entry: Box<ThreadEntry> = Box::new(Box::new(f));
+  let argument = Box::into_raw(entry);
+  let thread = match Thread::new(name, thread_start, argument as usize) {
+    Ok(thread) => Arc::new(thread),
+    Err(err) => {
+      drop(unsafe { Box::from_raw(argument) });
+      return Err(err);
+    }
+  };
+  make_ready(thread.clone());
+  Ok(thread)
+}
+
+/// Entry point of the spawned threads, switched to by [`schedule`] with interrupts disabled.
+extern "C" fn thread_start(argument: usize) -> ! {
+  finish_switch();
+  unsafe { interrupt::enable() };
+  let entry = unsafe { Box::from_raw(argument as *mut ThreadEntry) };
+  entry();
+  exit()
+}
+
+/// Make the blocked thread ready to run. Nothing is done if it's not blocked.
+pub fn wake(thread: Arc<Thread>) {
+  if thread.transition(ThreadState::Blocked, ThreadState::Ready) {
+    make_ready(thread);
+  }
+}
+
+/// Queue the thread, and wake up an idle processor to run it.
+fn make_ready(thread: Arc<Thread>) {
+  RUN_QUEUE.lock().push_back(thread);
+  kick_idle_processor();
+}
+
+/// Give the processor to the next ready thread, if any. Nothing is done in interrupt context, or
+/// before the scheduler is started.
+pub fn yield_now() {
+  if is_started() && !percpu::in_interrupt() {
+    schedule(true);
+  }
+}
+
+/// Yield until the thread blocks, which it may do on another processor. Panic if it does not
+/// within a second.
+#[cfg(test)]
+pub fn yield_until_blocked(thread: &Thread) {
+  use crate::support::monotonic;
+
+  let deadline = monotonic() + core::time::Duration::from_secs(1).as_nanos();
+  while thread.state() != ThreadState::Blocked {
+    assert!(monotonic() < deadline, "Thread did not block");
+    yield_now();
+    core::hint::spin_loop();
+  }
+}
+
+/// Terminate the current thread, and wake the threads joining it.
+pub fn exit() -> ! {
+  let current = current().expect("Exit without a current thread");
+  assert!(
+    can_block(),
+    "Exit from the idle thread or an interrupt handler"
+  );
+  current.set_state(ThreadState::Exited);
+  current.joiners.wake_all();
+  drop(current);
+  schedule(false);
+  unreachable!("Exited thread resumed");
+}
+
+/// Switch to the next ready thread. The current thread is queued again if `requeue` is set, i.e.
+/// it yields, and the processor falls back to its idle thread if the current one blocked or
+/// exited.
+///
+/// The state of the current thread does not tell whether it's still runnable: once it's blocked
+/// and its wait queue unlocked, it may be woken, queued and picked by other processors before it
+/// gets here.
+pub(super) fn schedule(requeue: bool) {
+  let flags = interrupt::save_and_disable();
+  let current = percpu::current_thread().expect("Scheduler not started").as_ptr();
+  let idle = IDLE_THREAD.get().load(Ordering::Relaxed);
+  let current_thread = unsafe { &*current };
+
+  let next = {
+    let mut queue = RUN_QUEUE.lock();
+    if requeue && current != idle {
+      current_thread.set_state(ThreadState::Ready);
+      queue.push_back(unsafe { clone_raw(current) });
+    }
+    match queue.pop_front() {
+      Some(next) => Arc::into_raw(next).cast_mut(),
+      // Only the idle thread is still runnable when none is ready.
+      None if current == idle => {
+        drop(queue);
+        unsafe { interrupt::restore(flags) };
+        return;
+      }
+      None => unsafe { Arc::into_raw(clone_raw(idle)).cast_mut() },
+    }
+  };
+
+  let next_thread = unsafe { &*next };
+  next_thread.set_state(ThreadState::Running);
+  if next == current {
+    // The current thread was ready, only the reference of the queue is released.
+    drop(unsafe { Arc::from_raw(next) });
+    unsafe { interrupt::restore(flags) };
+    return;
+  }
+
+  while next_thread.on_cpu.load(Ordering::Acquire) {
+    core::hint::spin_loop();
+  }
+  next_thread.on_cpu.store(true, Ordering::Relaxed);
+  // The reference of the processor on the current thread is released by the next one.
+  PREVIOUS_THREAD.get().store(current, Ordering::Relaxed);
+  unsafe {
+    percpu::set_current_thread(NonNull::new(next));
+    switch_fpu_state(&mut *current_thread.fpu.get(), &*next_thread.fpu.get());
+    switch_context(
+      &mut *current_thread.context.get(),
+      &*next_thread.context.get(),
+    );
+  }
+
+  // Resumed by another thread.
+  finish_switch();
+  unsafe { interrupt::restore(flags) };
+}
+
+/// Complete the switch on the next thread: the previous one may now run on another processor.
+fn finish_switch() {
+  let previous = PREVIOUS_THREAD.get().swap(core::ptr::null_mut(), Ordering::Relaxed);
+  if !previous.is_null() {
+    let previous = unsafe { Arc::from_raw(previous) };
+    previous.on_cpu.store(false, Ordering::Release);
+  }
+}
+
+/// Return a new reference on the thread.
+///
+/// ## Safety
+/// The thread must be referenced by an `Arc`.
+unsafe fn clone_raw(thread: *const Thread) -> Arc<Thread> {
+  Arc::increment_strong_count(thread);
+  Arc::from_raw(thread)
+}
@@ -0,0 +1,175 @@
+//! # Kernel Threads
+//!
+//! A thread runs on its own kernel stack, allocated from the buddy allocator. It's shared through
+//! `Arc`: by the scheduler while it's ready or running, by a wait queue while it's blocked, and by
+//! its handles. The stack is freed with the last reference, once the thread exited.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+use crate::allocator::allocate_frames;
+use crate::allocator::deallocate_frames;
+use crate::arch::paging::PageSize;
+use crate::arch::paging::Size4KiB;
+use crate::arch::x86_64::context::Context;
+use crate::arch::x86_64::fpu::FpuState;
+use crate::arch::PhysicalFrame;
+use crate::mem::physical_to_virtual;
+use crate::proc::wait_queue::WaitQueue;
+
+/// Order of the kernel stack of a thread, in the buddy allocator.
+const STACK_ORDER: usize = 2;
+
+pub type ThreadId = u64;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// State of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+  /// Running on a processor.
+  Running,
+  /// In the run queue.
+  Ready,
+  /// In a wait queue.
+  Blocked,
+  Exited,
+}
+
+impl ThreadState {
+  fn from_raw(state: u8) -> Self {
+    match state {
+      0 => Self::Running,
+      1 => Self::Ready,
+      2 => Self::Blocked,
+      _ => Self::Exited,
+    }
+  }
+}
+
+/// Error occurred when creating a thread.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpawnError {
+  /// No memory for the kernel stack.
+  OutOfMemory,
+}
+
+pub struct Thread {
+  id:                 ThreadId,
+  name:               &'static str,
+  state:              AtomicU8,
+  /// The thread runs on a processor, or is being switched out of it.
+  pub(super) on_cpu:  AtomicBool,
+  /// Saved while the thread is switched out.
+  pub(super) context: UnsafeCell<Context>,
+  /// Saved while the thread is switched out.
+  pub(super) fpu:     UnsafeCell<FpuState>,
+  /// Kernel stack, none for the boot context of a processor, which keeps its own.
+  stack:              Option<PhysicalFrame>,
+  /// Threads waiting for this one to exit.
+  pub(super) joiners: WaitQueue,
+}
+
+// The context and the extended state are only accessed by the processor switching the thread.
+unsafe impl Send for Thread {}
+unsafe impl Sync for Thread {}
+
+impl Thread {
+  /// Create a thread ready to call `entry(argument)` on a new stack.
+  pub(super) fn new(
+    name: &'static str,
+    entry: extern "C" fn(argument: usize) -> !,
+    argument: usize,
+  ) -> Result<Self, SpawnError> {
+    let stack = allocate_frames(STACK_ORDER).ok_or(SpawnError::OutOfMemory)?;
+    let stack_end = physical_to_virtual(stack.start_address()) + (Size4KiB::SIZE << STACK_ORDER);
+    let context = unsafe { Context::new(stack_end, entry, argument) };
+    Ok(Self::with_context(
+      name,
+      ThreadState::Ready,
+      context,
+      Some(stack),
+    ))
+  }
+
+  /// Create the thread of the context running on the current processor.
+  pub(super) fn new_boot(name: &'static str) -> Self {
+    Self::with_context(name, ThreadState::Running, Context::default(), None)
+  }
+
+  fn with_context(
+    name: &'static str,
+    state: ThreadState,
+    context: Context,
+    stack: Option<PhysicalFrame>,
+  ) -> Self {
+    Self {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      name,
+      state: AtomicU8::new(state as u8),
+      on_cpu: AtomicBool::new(state == ThreadState::Running),
+      context: UnsafeCell::new(context),
+      fpu: UnsafeCell::new(FpuState::new()),
+      stack,
+      joiners: WaitQueue::new(),
+    }
+  }
+
+  pub fn id(&self) -> ThreadId {
+    self.id
+  }
+
+  pub fn name(&self) -> &'static str {
+    self.name
+  }
+
+  /// Return the state, which may change right after.
+  pub fn state(&self) -> ThreadState {
+    ThreadState::from_raw(self.state.load(Ordering::Acquire))
+  }
+
+  pub(super) fn set_state(&self, state: ThreadState) {
+    self.state.store(state as u8, Ordering::Release);
+  }
+
+  /// Change the state if it's `current`, and return true if it was.
+  pub(super) fn transition(&self, current: ThreadState, new: ThreadState) -> bool {
+    self
+      .state
+      .compare_exchange(
+        current as u8,
+        new as u8,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+      )
+      .is_ok()
+  }
+
+  /// Wait until the thread exits.
+  pub fn join(&self) {
+    self.joiners.wait_while(|| self.state() != ThreadState::Exited);
+  }
+}
+
+impl Drop for Thread {
+  fn drop(&mut self) {
+    if let Some(stack) = self.stack {
+      unsafe { deallocate_frames(stack, STACK_ORDER) };
+    }
+  }
+}
+
+impl fmt::Debug for Thread {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Thread")
+      .field("id", &self.id)
+      .field("name", &self.name)
+      .field("state", &self.state())
+      .finish()
+  }
+}
@@ -0,0 +1,79 @@
+//! # Wait Queues
+//!
+//! A thread waiting for a condition blocks on a wait queue, until a thread or an interrupt handler
+//! changing the condition wakes the queue. The condition is checked with the queue locked before
+//! blocking, so that a wakeup following the change cannot be missed.
+//!
+//! The contexts which cannot block, an interrupt handler, the idle thread, or a processor whose
+//! scheduler is not started, wait by yielding to the ready threads, or spinning.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use crate::proc::sched;
+use crate::proc::thread::ThreadState;
+use crate::proc::Thread;
+use crate::support::sync::IrqSpinLock;
+
+/// Queue of the threads blocked on a condition, woken in FIFO order.
+pub struct WaitQueue {
+  waiters: IrqSpinLock<VecDeque<Arc<Thread>>>,
+}
+
+impl WaitQueue {
+  pub const fn new() -> Self {
+    Self {
+      waiters: IrqSpinLock::new(VecDeque::new()),
+    }
+  }
+
+  /// Block the current thread while the condition holds, which is checked again on each wakeup.
+  /// The condition is called with the queue locked, so it must not wake it.
+  pub fn wait_while(&self, mut condition: impl FnMut() -> bool) {
+    if !sched::can_block() {
+      while condition() {
+        sched::yield_now();
+        core::hint::spin_loop();
+      }
+      return;
+    }
+
+    let current = sched::current().expect("Blocking without a current thread");
+    loop {
+      let mut waiters = self.waiters.lock();
+      if !condition() {
+        return;
+      }
+      current.set_state(ThreadState::Blocked);
+      waiters.push_back(current.clone());
+      drop(waiters);
+      sched::schedule(false);
+    }
+  }
+
+  /// Wake the first thread waiting, and return false if none is.
+  pub fn wake_one(&self) -> bool {
+    let thread = self.waiters.lock().pop_front();
+    match thread {
+      Some(thread) => {
+        sched::wake(thread);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Wake all the threads waiting, and return their count.
+  pub fn wake_all(&self) -> usize {
+    let waiters = core::mem::take(&mut *self.waiters.lock());
+    let count = waiters.len();
+    waiters.into_iter().for_each(sched::wake);
+    count
+  }
+}
+
+impl Default for WaitQueue {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -1,13 +1,14 @@
-use spin::Mutex;
+use crate::support::sync::IrqSpinLock;
 
+pub mod sync;
 pub mod time;
 
 pub type NanoSecond = u128;
 
 /// Kernel start time.
-pub static START: Mutex<NanoSecond> = Mutex::new(0);
+pub static START: IrqSpinLock<NanoSecond> = IrqSpinLock::new(0);
 /// Kernel offset time.
-pub static OFFSET: Mutex<NanoSecond> = Mutex::new(0);
+pub static OFFSET: IrqSpinLock<NanoSecond> = IrqSpinLock::new(0);
 
 pub fn monotonic() -> NanoSecond {
   *OFFSET.lock() + crate::arch::shared::time::counter()
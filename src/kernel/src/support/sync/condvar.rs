@@ -0,0 +1,95 @@
+//! # Condition Variable
+//!
+//! [`Condvar`] blocks threads until another thread notifies it, releasing a [`Mutex`] meanwhile.
+//! Each notification increments a sequence number, and a thread waits until the number changes
+//! from the one it read with the mutex held, so that a notification between the release of the
+//! mutex and the wait is not missed. A thread may also be woken by a notification meant for
+//! another one, so the condition is always checked again, see [`Condvar::wait_while`].
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::proc::wait_queue::WaitQueue;
+use crate::support::sync::MutexGuard;
+
+/// Condition variable, whose waiters sleep.
+pub struct Condvar {
+  sequence: AtomicUsize,
+  waiters:  WaitQueue,
+}
+
+impl Condvar {
+  pub const fn new() -> Self {
+    Self {
+      sequence: AtomicUsize::new(0),
+      waiters:  WaitQueue::new(),
+    }
+  }
+
+  /// Release the mutex of the guard, block until notified, then take the mutex again.
+  pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    let sequence = self.sequence.load(Ordering::Acquire);
+    let mutex = guard.mutex();
+    drop(guard);
+    self.waiters.wait_while(|| self.sequence.load(Ordering::Acquire) == sequence);
+    mutex.lock()
+  }
+
+  /// Wait while the condition holds on the value of the mutex.
+  pub fn wait_while<'a, T: ?Sized>(
+    &self,
+    mut guard: MutexGuard<'a, T>,
+    mut condition: impl FnMut(&mut T) -> bool,
+  ) -> MutexGuard<'a, T> {
+    while condition(&mut guard) {
+      guard = self.wait(guard);
+    }
+    guard
+  }
+
+  /// Wake a thread waiting.
+  pub fn notify_one(&self) {
+    self.sequence.fetch_add(1, Ordering::Release);
+    self.waiters.wake_one();
+  }
+
+  /// Wake all the threads waiting.
+  pub fn notify_all(&self) {
+    self.sequence.fetch_add(1, Ordering::Release);
+    self.waiters.wake_all();
+  }
+}
+
+impl Default for Condvar {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use alloc::sync::Arc;
+
+  use super::*;
+  use crate::proc::sched;
+  use crate::support::sync::Mutex;
+
+  #[test_case]
+  fn test_condvar() {
+    let pair = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread = {
+      let pair = pair.clone();
+      sched::spawn("test_condvar", move || {
+        let (ready, condvar) = &*pair;
+        let _ready = condvar.wait_while(ready.lock(), |ready| !*ready);
+      })
+      .unwrap()
+    };
+    sched::yield_until_blocked(&thread);
+    assert!(!pair.0.is_locked());
+
+    *pair.0.lock() = true;
+    pair.1.notify_one();
+    thread.join();
+  }
+}
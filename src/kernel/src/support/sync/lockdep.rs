@@ -0,0 +1,263 @@
+//! # Lock Debugging
+//!
+//! Enabled by the `lock-debug` feature. Each lock records the processor holding it and where it
+//! was taken, and each processor keeps the stack of the locks it holds. Then:
+//! - taking a lock which the processor already holds, recursively or from an exception handler,
+//!   would spin forever, so it panics with both acquisition sites;
+//! - taking a lock B while holding a lock A records the order A before B. If B was taken before A
+//!   elsewhere, two processors taking them in both orders may deadlock, so the inversion is
+//!   reported with the sites;
+//! - spinning long on a lock is reported with its owner.
+//!
+//! Locks are identified by their address. The locks taken before the per-CPU area of the processor
+//! is installed are not tracked.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::arch::percpu::cpu_id;
+use crate::arch::x86_64::reg::GsBase;
+use crate::println;
+
+/// Count of locks a processor may hold at once, the deeper ones are not tracked.
+const MAX_HELD: usize = 16;
+/// Count of lock orders recorded, the next ones are not checked.
+const MAX_ORDERS: usize = 512;
+/// Spins on a lock before reporting it as contended.
+const CONTENDED_SPINS: usize = 1 << 26;
+
+type Site = &'static Location<'static>;
+
+/// Site which may not be recorded.
+struct OptionalSite(Option<Site>);
+
+impl fmt::Display for OptionalSite {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0 {
+      Some(site) => write!(f, "{}", site),
+      None => f.write_str("<unknown>"),
+    }
+  }
+}
+
+/// Locks held by a processor, in acquisition order.
+struct HeldLocks {
+  locks:     [(usize, Option<Site>); MAX_HELD],
+  count:     usize,
+  /// A report is being printed, which takes locks itself.
+  reporting: bool,
+}
+
+impl HeldLocks {
+  const fn new() -> Self {
+    Self {
+      locks:     [(0, None); MAX_HELD],
+      count:     0,
+      reporting: false,
+    }
+  }
+
+  fn iter(&self) -> impl Iterator<Item = &(usize, Option<Site>)> {
+    self.locks[..self.count.min(MAX_HELD)].iter()
+  }
+}
+
+crate::per_cpu! {
+  static HELD: RefCell<HeldLocks> = RefCell::new(HeldLocks::new());
+}
+
+/// Lock `before` was held while taking lock `after` at the site.
+struct LockOrder {
+  before:   AtomicUsize,
+  after:    AtomicUsize,
+  site:     AtomicPtr<Location<'static>>,
+  /// The inversion of this order was reported.
+  reported: AtomicBool,
+}
+
+impl LockOrder {
+  const fn new() -> Self {
+    Self {
+      before:   AtomicUsize::new(0),
+      after:    AtomicUsize::new(0),
+      site:     AtomicPtr::new(core::ptr::null_mut()),
+      reported: AtomicBool::new(false),
+    }
+  }
+}
+
+static ORDERS: [LockOrder; MAX_ORDERS] = [const { LockOrder::new() }; MAX_ORDERS];
+static ORDER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn orders() -> impl Iterator<Item = &'static LockOrder> {
+  // The entry is published by its `after` field, written last.
+  ORDERS[..ORDER_COUNT.load(Ordering::Acquire).min(MAX_ORDERS)]
+    .iter()
+    .filter(|order| order.after.load(Ordering::Acquire) != 0)
+}
+
+fn find_order(before: usize, after: usize) -> Option<&'static LockOrder> {
+  orders().find(|order| {
+    order.before.load(Ordering::Relaxed) == before && order.after.load(Ordering::Relaxed) == after
+  })
+}
+
+fn add_order(before: usize, after: usize, site: Site) {
+  let index = ORDER_COUNT.fetch_add(1, Ordering::AcqRel);
+  if let Some(order) = ORDERS.get(index) {
+    order.before.store(before, Ordering::Relaxed);
+    order.site.store(site as *const _ as *mut _, Ordering::Relaxed);
+    order.after.store(after, Ordering::Release);
+  }
+}
+
+/// Return the current processor, if its per-CPU area is installed.
+fn current_cpu() -> Option<usize> {
+  (GsBase::read().as_raw() != 0).then(cpu_id)
+}
+
+/// Run the report with the lock checks of the processor disabled, unless it's already reporting.
+fn report(f: impl FnOnce()) {
+  let reporting = HELD.with(|held| core::mem::replace(&mut held.borrow_mut().reporting, true));
+  if !reporting {
+    f();
+    HELD.with(|held| held.borrow_mut().reporting = false);
+  }
+}
+
+/// Debugging state of a lock.
+pub(super) struct LockDebug {
+  /// Processor holding the lock plus one, 0 if free or untracked.
+  owner: AtomicUsize,
+  /// Where the lock was taken.
+  site:  AtomicPtr<Location<'static>>,
+}
+
+impl LockDebug {
+  pub(super) const fn new() -> Self {
+    Self {
+      owner: AtomicUsize::new(0),
+      site:  AtomicPtr::new(core::ptr::null_mut()),
+    }
+  }
+
+  fn owner_site(&self) -> OptionalSite {
+    OptionalSite(unsafe { self.site.load(Ordering::Relaxed).as_ref() })
+  }
+
+  pub(super) fn is_owned_by_current_cpu(&self) -> bool {
+    current_cpu().is_some_and(|cpu| self.owner.load(Ordering::Relaxed) == cpu + 1)
+  }
+
+  /// Check the acquisition of the lock before spinning on it. Interrupts are disabled.
+  pub(super) fn before_acquire(&self, id: usize, site: Site) {
+    let Some(cpu) = current_cpu() else {
+      return;
+    };
+    if self.owner.load(Ordering::Relaxed) == cpu + 1 && !HELD.with(|held| held.borrow().reporting) {
+      panic!(
+        "Self-deadlock: lock {:#x} taken at {} is already held by CPU {} since {}.",
+        id,
+        site,
+        cpu,
+        self.owner_site()
+      );
+    }
+
+    // Record the orders with the locks held, and find an inversion.
+    let inversion = HELD.with(|held| {
+      let held = held.borrow();
+      if held.reporting {
+        return None;
+      }
+      let mut inversion = None;
+      for &(before, before_site) in held.iter().filter(|&&(before, _)| before != id) {
+        if find_order(before, id).is_none() {
+          add_order(before, id, site);
+        }
+        match find_order(id, before) {
+          Some(order) if inversion.is_none() && !order.reported.swap(true, Ordering::Relaxed) => {
+            inversion = Some((before, before_site, order));
+          }
+          _ => {}
+        }
+      }
+      inversion
+    });
+
+    if let Some((before, before_site, order)) = inversion {
+      report(|| {
+        println!(
+          "[ERROR] Lock order inversion on CPU {}: lock {:#x} taken at {} while holding lock {:#x} \
+           taken at {}, but it was held while taking that lock at {}.",
+          cpu,
+          id,
+          site,
+          before,
+          OptionalSite(before_site),
+          OptionalSite(unsafe { order.site.load(Ordering::Relaxed).as_ref() })
+        );
+      });
+    }
+  }
+
+  /// Report the lock once it has been spun on for long.
+  pub(super) fn spinning(&self, id: usize, site: Site, spins: usize) {
+    if spins != CONTENDED_SPINS {
+      return;
+    }
+    if let Some(cpu) = current_cpu() {
+      report(|| {
+        println!(
+          "[ERROR] Lock {:#x} taken at {} on CPU {} is contended, held by CPU {} since {}.",
+          id,
+          site,
+          cpu,
+          self.owner.load(Ordering::Relaxed).wrapping_sub(1) as isize,
+          self.owner_site()
+        );
+      });
+    }
+  }
+
+  /// Record the owner of the lock just taken.
+  pub(super) fn acquired(&self, id: usize, site: Site) {
+    let Some(cpu) = current_cpu() else {
+      return;
+    };
+    self.owner.store(cpu + 1, Ordering::Relaxed);
+    self.site.store(site as *const _ as *mut _, Ordering::Relaxed);
+    HELD.with(|held| {
+      let mut held = held.borrow_mut();
+      let count = held.count;
+      if let Some(entry) = held.locks.get_mut(count) {
+        *entry = (id, Some(site));
+      }
+      held.count += 1;
+    });
+  }
+
+  /// Forget the owner of the lock about to be released.
+  pub(super) fn released(&self, id: usize) {
+    if self.owner.swap(0, Ordering::Relaxed) == 0 {
+      return;
+    }
+    if current_cpu().is_none() {
+      return;
+    }
+    HELD.with(|held| {
+      let mut held = held.borrow_mut();
+      let count = held.count.min(MAX_HELD);
+      // Locks are usually released in reverse order.
+      if let Some(index) = held.locks[..count].iter().rposition(|&(lock, _)| lock == id) {
+        held.locks.copy_within(index + 1..count, index);
+      }
+      held.count = held.count.saturating_sub(1);
+    });
+  }
+}
@@ -0,0 +1,26 @@
+//! # Synchronization
+//!
+//! [`IrqSpinLock`] protects the data shared with interrupt handlers, and is held briefly. The other
+//! primitives block the waiting threads on wait queues of the scheduler, and must not be used in
+//! interrupt context, nor while holding a spin lock.
+
+mod condvar;
+#[cfg(feature = "lock-debug")]
+mod lockdep;
+mod mutex;
+mod once;
+mod rwlock;
+mod semaphore;
+pub mod spinlock;
+
+pub use self::condvar::Condvar;
+pub use self::mutex::Mutex;
+pub use self::mutex::MutexGuard;
+pub use self::once::Lazy;
+pub use self::once::Once;
+pub use self::rwlock::RwLock;
+pub use self::rwlock::RwLockReadGuard;
+pub use self::rwlock::RwLockWriteGuard;
+pub use self::semaphore::Semaphore;
+pub use self::spinlock::IrqSpinLock;
+pub use self::spinlock::IrqSpinLockGuard;
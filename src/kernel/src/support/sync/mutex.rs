@@ -0,0 +1,149 @@
+//! # Sleeping Mutex
+//!
+//! [`Mutex`] blocks the threads which find it locked on a wait queue instead of spinning, so it
+//! may be held for long, across allocations or I/O. It must not be taken in interrupt context,
+//! where [`IrqSpinLock`](super::IrqSpinLock) is used instead.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use crate::proc::wait_queue::WaitQueue;
+
+/// Mutual exclusion lock, whose waiters sleep.
+pub struct Mutex<T: ?Sized> {
+  locked:  AtomicBool,
+  waiters: WaitQueue,
+  value:   UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// Guard of a [`Mutex`], which releases it when dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+  mutex:   &'a Mutex<T>,
+  /// The guard is shared only if the value is.
+  _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> Mutex<T> {
+  pub const fn new(value: T) -> Self {
+    Self {
+      locked:  AtomicBool::new(false),
+      waiters: WaitQueue::new(),
+      value:   UnsafeCell::new(value),
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: ?Sized> Mutex<T> {
+  /// Take the lock, blocking until it's released.
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    loop {
+      if let Some(guard) = self.try_lock() {
+        return guard;
+      }
+      self.waiters.wait_while(|| self.locked.load(Ordering::Relaxed));
+    }
+  }
+
+  /// Take the lock if it's free, without blocking.
+  pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+    self
+      .locked
+      .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .ok()
+      .map(|_| MutexGuard {
+        mutex:   self,
+        _marker: PhantomData,
+      })
+  }
+
+  /// True if the lock is held, which may change right after.
+  #[inline]
+  pub fn is_locked(&self) -> bool {
+    self.locked.load(Ordering::Relaxed)
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.value.get_mut()
+  }
+}
+
+impl<T: Default> Default for Mutex<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.try_lock() {
+      Some(guard) => f.debug_struct("Mutex").field("value", &&*guard).finish(),
+      None => f.write_str("Mutex { <locked> }"),
+    }
+  }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+  /// Return the mutex of the guard.
+  pub(super) fn mutex(&self) -> &'a Mutex<T> {
+    self.mutex
+  }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.mutex.value.get() }
+  }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.mutex.value.get() }
+  }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+  fn drop(&mut self) {
+    self.mutex.locked.store(false, Ordering::Release);
+    self.mutex.waiters.wake_one();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use alloc::sync::Arc;
+
+  use super::*;
+  use crate::proc::sched;
+
+  #[test_case]
+  fn test_mutex() {
+    let mutex = Arc::new(Mutex::new(0));
+    let guard = mutex.lock();
+    let thread = {
+      let mutex = mutex.clone();
+      sched::spawn("test_mutex", move || *mutex.lock() += 1).unwrap()
+    };
+
+    // The thread blocks on the mutex, and gives the processor back.
+    sched::yield_until_blocked(&thread);
+    assert_eq!(*guard, 0);
+
+    drop(guard);
+    thread.join();
+    assert_eq!(*mutex.lock(), 1);
+  }
+}
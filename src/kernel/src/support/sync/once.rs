@@ -0,0 +1,159 @@
+//! # One-Time Initialization
+//!
+//! [`Once`] holds a value initialized by the first caller of [`Once::call_once`], while the
+//! concurrent callers block until it's done. [`Lazy`] initializes its value on first access with
+//! the function given at construction, so that statics need no `lazy_static!`:
+//!
+//! ```ignore
+//! static TABLE: Lazy<Vec<u8>> = Lazy::new(|| vec![0; 16]);
+//! ```
+
+use core::cell::Cell;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+use crate::proc::wait_queue::WaitQueue;
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// Value initialized once, whose concurrent initializers sleep.
+pub struct Once<T> {
+  state:   AtomicU8,
+  waiters: WaitQueue,
+  value:   UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+  pub const fn new() -> Self {
+    Self {
+      state:   AtomicU8::new(INCOMPLETE),
+      waiters: WaitQueue::new(),
+      value:   UnsafeCell::new(MaybeUninit::uninit()),
+    }
+  }
+
+  /// Initialize the value with the function if it's not, or wait until it is, and return it.
+  pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+    if let Some(value) = self.get() {
+      return value;
+    }
+
+    match self
+      .state
+      .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+    {
+      Ok(_) => {
+        unsafe { (*self.value.get()).write(f()) };
+        self.state.store(COMPLETE, Ordering::Release);
+        self.waiters.wake_all();
+      }
+      Err(_) => self.waiters.wait_while(|| self.state.load(Ordering::Acquire) != COMPLETE),
+    }
+    unsafe { (*self.value.get()).assume_init_ref() }
+  }
+
+  /// Return the value if it's initialized.
+  pub fn get(&self) -> Option<&T> {
+    self.is_completed().then(|| unsafe { (*self.value.get()).assume_init_ref() })
+  }
+
+  pub fn is_completed(&self) -> bool {
+    self.state.load(Ordering::Acquire) == COMPLETE
+  }
+}
+
+impl<T> Default for Once<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.get() {
+      Some(value) => f.debug_struct("Once").field("value", value).finish(),
+      None => f.write_str("Once { <uninitialized> }"),
+    }
+  }
+}
+
+impl<T> Drop for Once<T> {
+  fn drop(&mut self) {
+    if *self.state.get_mut() == COMPLETE {
+      unsafe { self.value.get_mut().assume_init_drop() };
+    }
+  }
+}
+
+/// Value initialized on first access.
+pub struct Lazy<T, F = fn() -> T> {
+  once: Once<T>,
+  /// Taken by the initializer.
+  init: Cell<Option<F>>,
+}
+
+// The function is only taken by the single initializer of the value.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+  pub const fn new(init: F) -> Self {
+    Self {
+      once: Once::new(),
+      init: Cell::new(Some(init)),
+    }
+  }
+
+  /// Initialize the value if it's not, and return it.
+  pub fn force(this: &Self) -> &T {
+    this.once.call_once(|| {
+      let init = this.init.take().expect("Lazy value initialized twice");
+      init()
+    })
+  }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    Self::force(self)
+  }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&self.once, f)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use core::sync::atomic::AtomicUsize;
+
+  use super::*;
+
+  static CALLS: AtomicUsize = AtomicUsize::new(0);
+  static VALUE: Lazy<usize> = Lazy::new(|| CALLS.fetch_add(1, Ordering::Relaxed) + 42);
+
+  #[test_case]
+  fn test_once_and_lazy() {
+    let once = Once::new();
+    assert!(once.get().is_none());
+    assert_eq!(*once.call_once(|| 1), 1);
+    assert_eq!(*once.call_once(|| 2), 1);
+    assert!(once.is_completed());
+
+    assert_eq!(*VALUE, 42);
+    assert_eq!(*VALUE, 42);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+  }
+}
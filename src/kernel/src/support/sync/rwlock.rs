@@ -0,0 +1,231 @@
+//! # Reader-Writer Lock
+//!
+//! [`RwLock`] is held by any count of readers or a single writer, and blocks the threads which
+//! cannot take it. Writers are preferred: once a writer waits, new readers wait too, so that a
+//! steady flow of readers cannot starve it. Readers are woken when no writer is left waiting.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
+
+use crate::proc::wait_queue::WaitQueue;
+use crate::support::sync::IrqSpinLock;
+
+struct RwState {
+  /// Count of readers holding the lock.
+  readers:         usize,
+  /// A writer holds the lock.
+  writer:          bool,
+  /// Count of writers waiting for the lock.
+  waiting_writers: usize,
+}
+
+impl RwState {
+  fn can_read(&self) -> bool {
+    !self.writer && self.waiting_writers == 0
+  }
+
+  fn can_write(&self) -> bool {
+    !self.writer && self.readers == 0
+  }
+}
+
+/// Reader-writer lock with writer preference, whose waiters sleep.
+pub struct RwLock<T: ?Sized> {
+  state:   IrqSpinLock<RwState>,
+  readers: WaitQueue,
+  writers: WaitQueue,
+  value:   UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// Guard of a reader of a [`RwLock`], which releases it when dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+  lock:    &'a RwLock<T>,
+  _marker: PhantomData<&'a T>,
+}
+
+/// Guard of the writer of a [`RwLock`], which releases it when dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+  lock:    &'a RwLock<T>,
+  _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> RwLock<T> {
+  pub const fn new(value: T) -> Self {
+    Self {
+      state:   IrqSpinLock::new(RwState {
+        readers:         0,
+        writer:          false,
+        waiting_writers: 0,
+      }),
+      readers: WaitQueue::new(),
+      writers: WaitQueue::new(),
+      value:   UnsafeCell::new(value),
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: ?Sized> RwLock<T> {
+  /// Take the lock for reading, blocking while a writer holds it or waits for it.
+  pub fn read(&self) -> RwLockReadGuard<'_, T> {
+    loop {
+      if let Some(guard) = self.try_read() {
+        return guard;
+      }
+      self.readers.wait_while(|| !self.state.lock().can_read());
+    }
+  }
+
+  /// Take the lock for reading if no writer holds it nor waits for it, without blocking.
+  pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+    let mut state = self.state.lock();
+    if !state.can_read() {
+      return None;
+    }
+    state.readers += 1;
+    Some(RwLockReadGuard {
+      lock:    self,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Take the lock for writing, blocking while it's held.
+  pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+    if let Some(guard) = self.try_write() {
+      return guard;
+    }
+
+    self.state.lock().waiting_writers += 1;
+    loop {
+      self.writers.wait_while(|| !self.state.lock().can_write());
+      let mut state = self.state.lock();
+      if state.can_write() {
+        state.writer = true;
+        state.waiting_writers -= 1;
+        return RwLockWriteGuard {
+          lock:    self,
+          _marker: PhantomData,
+        };
+      }
+    }
+  }
+
+  /// Take the lock for writing if it's free, without blocking.
+  pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+    let mut state = self.state.lock();
+    if !state.can_write() {
+      return None;
+    }
+    state.writer = true;
+    Some(RwLockWriteGuard {
+      lock:    self,
+      _marker: PhantomData,
+    })
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.value.get_mut()
+  }
+}
+
+impl<T: Default> Default for RwLock<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.try_read() {
+      Some(guard) => f.debug_struct("RwLock").field("value", &&*guard).finish(),
+      None => f.write_str("RwLock { <locked> }"),
+    }
+  }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.lock.value.get() }
+  }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+  fn drop(&mut self) {
+    let wake_writer = {
+      let mut state = self.lock.state.lock();
+      state.readers -= 1;
+      state.readers == 0 && state.waiting_writers != 0
+    };
+    if wake_writer {
+      self.lock.writers.wake_one();
+    }
+  }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.lock.value.get() }
+  }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.lock.value.get() }
+  }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+  fn drop(&mut self) {
+    let wake_writer = {
+      let mut state = self.lock.state.lock();
+      state.writer = false;
+      state.waiting_writers != 0
+    };
+    if wake_writer {
+      self.lock.writers.wake_one();
+    } else {
+      self.lock.readers.wake_all();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use alloc::sync::Arc;
+
+  use super::*;
+  use crate::proc::sched;
+
+  #[test_case]
+  fn test_rw_lock_writer_preference() {
+    let lock = Arc::new(RwLock::new(0));
+    let reader = lock.read();
+    assert!(lock.try_read().is_some());
+    assert!(lock.try_write().is_none());
+
+    let writer = {
+      let lock = lock.clone();
+      sched::spawn("test_rw_lock", move || *lock.write() += 1).unwrap()
+    };
+    sched::yield_until_blocked(&writer);
+    // New readers wait behind the writer.
+    assert!(lock.try_read().is_none());
+
+    drop(reader);
+    writer.join();
+    assert_eq!(*lock.read(), 1);
+  }
+}
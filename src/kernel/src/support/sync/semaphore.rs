@@ -0,0 +1,84 @@
+//! # Counting Semaphore
+//!
+//! [`Semaphore`] hands out a count of permits, and blocks the threads acquiring one while none is
+//! left. A permit may be released by another thread than the one which acquired it, or by an
+//! interrupt handler, e.g. to signal a completion.
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::proc::wait_queue::WaitQueue;
+
+/// Semaphore whose waiters sleep.
+pub struct Semaphore {
+  permits: AtomicUsize,
+  waiters: WaitQueue,
+}
+
+impl Semaphore {
+  pub const fn new(permits: usize) -> Self {
+    Self {
+      permits: AtomicUsize::new(permits),
+      waiters: WaitQueue::new(),
+    }
+  }
+
+  /// Acquire a permit, blocking until one is released.
+  pub fn acquire(&self) {
+    while !self.try_acquire() {
+      self.waiters.wait_while(|| self.permits.load(Ordering::Relaxed) == 0);
+    }
+  }
+
+  /// Acquire a permit if one is left, without blocking.
+  pub fn try_acquire(&self) -> bool {
+    self
+      .permits
+      .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+        permits.checked_sub(1)
+      })
+      .is_ok()
+  }
+
+  /// Release a permit, and wake a thread waiting for it.
+  pub fn release(&self) {
+    self.permits.fetch_add(1, Ordering::Release);
+    self.waiters.wake_one();
+  }
+
+  /// Return the count of permits left, which may change right after.
+  pub fn available_permits(&self) -> usize {
+    self.permits.load(Ordering::Relaxed)
+  }
+}
+
+impl Default for Semaphore {
+  fn default() -> Self {
+    Self::new(0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use alloc::sync::Arc;
+
+  use super::*;
+  use crate::proc::sched;
+
+  #[test_case]
+  fn test_semaphore() {
+    let semaphore = Arc::new(Semaphore::new(1));
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+
+    let thread = {
+      let semaphore = semaphore.clone();
+      sched::spawn("test_semaphore", move || semaphore.acquire()).unwrap()
+    };
+    sched::yield_until_blocked(&thread);
+
+    semaphore.release();
+    thread.join();
+    assert_eq!(semaphore.available_permits(), 0);
+  }
+}
@@ -0,0 +1,212 @@
+//! # Interrupt-Safe Spin Lock
+//!
+//! [`IrqSpinLock`] disables interrupts on the current processor while it's held, and restores the
+//! saved interrupt flag on release. An interrupt handler thus never spins on a lock held by the
+//! code it interrupted, while the other processors spin until the lock is released.
+//!
+//! With the `lock-debug` feature, the acquisitions are checked, see [`lockdep`](super::lockdep).
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
+#[cfg(feature = "lock-debug")]
+use core::panic::Location;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use crate::arch::interrupt;
+use crate::arch::x86_64::reg::RFlags;
+#[cfg(feature = "lock-debug")]
+use crate::support::sync::lockdep::LockDebug;
+
+/// Spin lock held with interrupts disabled.
+pub struct IrqSpinLock<T: ?Sized> {
+  locked: AtomicBool,
+  #[cfg(feature = "lock-debug")]
+  debug:  LockDebug,
+  value:  UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for IrqSpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for IrqSpinLock<T> {}
+
+/// Guard of an [`IrqSpinLock`], which releases it and restores the interrupt flag when dropped.
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+  lock:      &'a IrqSpinLock<T>,
+  /// RFLAGS before the lock was taken.
+  flags:     RFlags,
+  /// The guard must be dropped on the processor whose interrupts it disabled.
+  _not_send: PhantomData<*mut ()>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for IrqSpinLockGuard<'_, T> {}
+
+impl<T> IrqSpinLock<T> {
+  pub const fn new(value: T) -> Self {
+    Self {
+      locked: AtomicBool::new(false),
+      #[cfg(feature = "lock-debug")]
+      debug: LockDebug::new(),
+      value: UnsafeCell::new(value),
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+  /// Identity of the lock for the debugging, its address.
+  #[cfg(feature = "lock-debug")]
+  #[inline]
+  fn id(&self) -> usize {
+    self as *const Self as *const () as usize
+  }
+
+  /// Disable interrupts, and spin until the lock is taken.
+  #[cfg_attr(feature = "lock-debug", track_caller)]
+  pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+    let flags = interrupt::save_and_disable();
+    #[cfg(feature = "lock-debug")]
+    let site = Location::caller();
+    #[cfg(feature = "lock-debug")]
+    self.debug.before_acquire(self.id(), site);
+
+    #[cfg(feature = "lock-debug")]
+    let mut spins = 0usize;
+    while self
+      .locked
+      .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_err()
+    {
+      while self.locked.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+        #[cfg(feature = "lock-debug")]
+        {
+          spins += 1;
+          self.debug.spinning(self.id(), site, spins);
+        }
+      }
+    }
+
+    #[cfg(feature = "lock-debug")]
+    self.debug.acquired(self.id(), site);
+    IrqSpinLockGuard {
+      lock: self,
+      flags,
+      _not_send: PhantomData,
+    }
+  }
+
+  /// Take the lock with interrupts disabled if it's free, without spinning.
+  #[cfg_attr(feature = "lock-debug", track_caller)]
+  pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+    let flags = interrupt::save_and_disable();
+    if self
+      .locked
+      .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_err()
+    {
+      unsafe { interrupt::restore(flags) };
+      return None;
+    }
+
+    #[cfg(feature = "lock-debug")]
+    self.debug.acquired(self.id(), Location::caller());
+    Some(IrqSpinLockGuard {
+      lock: self,
+      flags,
+      _not_send: PhantomData,
+    })
+  }
+
+  /// True if the lock is held, which may change right after.
+  #[inline]
+  pub fn is_locked(&self) -> bool {
+    self.locked.load(Ordering::Relaxed)
+  }
+
+  /// True if the lock is held by the current processor. Always false without the `lock-debug`
+  /// feature, the owner is not recorded.
+  pub fn is_owned_by_current_cpu(&self) -> bool {
+    #[cfg(feature = "lock-debug")]
+    return self.debug.is_owned_by_current_cpu();
+    #[cfg(not(feature = "lock-debug"))]
+    false
+  }
+
+  /// Release the lock, without restoring the interrupt flag saved by its holder.
+  ///
+  /// ## Safety
+  /// The holder must no longer use the value, e.g. it panicked while printing.
+  pub unsafe fn force_unlock(&self) {
+    #[cfg(feature = "lock-debug")]
+    self.debug.released(self.id());
+    self.locked.store(false, Ordering::Release);
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.value.get_mut()
+  }
+}
+
+impl<T: Default> Default for IrqSpinLock<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinLock<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.try_lock() {
+      Some(guard) => f.debug_struct("IrqSpinLock").field("value", &&*guard).finish(),
+      None => f.write_str("IrqSpinLock { <locked> }"),
+    }
+  }
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.lock.value.get() }
+  }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.lock.value.get() }
+  }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+  fn drop(&mut self) {
+    #[cfg(feature = "lock-debug")]
+    self.lock.debug.released(self.lock.id());
+    self.lock.locked.store(false, Ordering::Release);
+    unsafe { interrupt::restore(self.flags) };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_irq_spin_lock() {
+    let lock = IrqSpinLock::new(0);
+    let enabled = interrupt::are_enabled();
+    {
+      let mut guard = lock.lock();
+      assert!(!interrupt::are_enabled());
+      assert!(lock.try_lock().is_none());
+      *guard += 1;
+    }
+    assert_eq!(interrupt::are_enabled(), enabled);
+    assert_eq!(*lock.try_lock().unwrap(), 1);
+    assert!(!lock.is_locked());
+  }
+}
@@ -0,0 +1,387 @@
+//! # Kernel Timers
+//!
+//! One-shot and periodic timers, whose callbacks run in the interrupt context of the system tick.
+//!
+//! Timers are kept in a hierarchical timer wheel indexed by jiffies. The first level has a slot
+//! per jiffy for the next 256 jiffies, and each of the next levels covers 64 times the range of
+//! the previous one with slots 64 times coarser. When the first level wraps around, the next slot
+//! of the second level is cascaded down, and so on. Adding, cancelling and expiring a timer take
+//! constant time.
+//!
+//! The timers are linked through indices into a table of entries, so that the tick never
+//! allocates: only adding a timer may grow the table.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::arch::interrupt;
+use crate::arch::shared::time::jiffies;
+use crate::arch::shared::time::tick_period;
+use crate::proc::sched;
+use crate::proc::wait_queue::WaitQueue;
+use crate::support::monotonic;
+use crate::support::sync::IrqSpinLock;
+use crate::support::NanoSecond;
+use crate::support::OFFSET;
+
+/// Bits of the slot index in the first level.
+const ROOT_BITS: u32 = 8;
+const ROOT_SIZE: usize = 1 << ROOT_BITS;
+/// Bits of the slot index in the other levels.
+const LEVEL_BITS: u32 = 6;
+const LEVEL_SIZE: usize = 1 << LEVEL_BITS;
+/// Count of levels after the first one.
+const LEVELS: usize = 4;
+/// Count of jiffies covered by the wheel, later timers are clamped.
+const WHEEL_RANGE: u64 = 1 << (ROOT_BITS + LEVEL_BITS * LEVELS as u32);
+
+/// Callback of a timer, called with the data given when the timer was added.
+///
+/// It runs in interrupt context, so it must be short and must not allocate.
+pub type TimerCallback = fn(data: usize);
+
+/// Handle of a timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerId {
+  index:      u32,
+  generation: u32,
+}
+
+struct TimerEntry {
+  /// Jiffy at which the timer expires.
+  expires:    u64,
+  /// Period in jiffies of a periodic timer, zero for a one-shot timer.
+  period:     u64,
+  callback:   TimerCallback,
+  data:       usize,
+  /// Incremented when the entry is freed, so that stale handles are ignored.
+  generation: u32,
+  /// The entry is linked in a slot.
+  armed:      bool,
+  prev:       Option<usize>,
+  next:       Option<usize>,
+  /// Index of the slot, in all the levels, which the entry is linked in.
+  slot:       usize,
+}
+
+struct TimerWheel {
+  /// The next jiffy to process.
+  current: u64,
+  /// Heads of the slot lists: the first level, then the other levels.
+  slots:   [Option<usize>; ROOT_SIZE + LEVEL_SIZE * LEVELS],
+  entries: Vec<TimerEntry>,
+  /// Entries which are not armed, linked through `next`.
+  free:    Option<usize>,
+}
+
+static TIMER_WHEEL: IrqSpinLock<TimerWheel> = IrqSpinLock::new(TimerWheel::new());
+
+impl TimerWheel {
+  const fn new() -> Self {
+    Self {
+      current: 0,
+      slots:   [None; ROOT_SIZE + LEVEL_SIZE * LEVELS],
+      entries: Vec::new(),
+      free:    None,
+    }
+  }
+
+  /// Return the slot for the expiry jiffy, relative to the current jiffy.
+  fn slot_of(&self, expires: u64) -> usize {
+    let delta = expires - self.current;
+    if delta < ROOT_SIZE as u64 {
+      return (expires as usize) & (ROOT_SIZE - 1);
+    }
+    for level in 0..LEVELS {
+      let shift = ROOT_BITS + LEVEL_BITS * level as u32;
+      if delta < 1 << (shift + LEVEL_BITS) || level == LEVELS - 1 {
+        return ROOT_SIZE + level * LEVEL_SIZE + ((expires >> shift) as usize & (LEVEL_SIZE - 1));
+      }
+    }
+    unreachable!()
+  }
+
+  fn link(&mut self, index: usize) {
+    let current = self.current;
+    let entry = &mut self.entries[index];
+    entry.expires = entry.expires.clamp(current, current + WHEEL_RANGE - 1);
+    let slot = self.slot_of(self.entries[index].expires);
+
+    let head = self.slots[slot];
+    let entry = &mut self.entries[index];
+    entry.slot = slot;
+    entry.prev = None;
+    entry.next = head;
+    entry.armed = true;
+    if let Some(head) = head {
+      self.entries[head].prev = Some(index);
+    }
+    self.slots[slot] = Some(index);
+  }
+
+  fn unlink(&mut self, index: usize) {
+    let TimerEntry {
+      prev, next, slot, ..
+    } = self.entries[index];
+    match prev {
+      Some(prev) => self.entries[prev].next = next,
+      None => self.slots[slot] = next,
+    }
+    if let Some(next) = next {
+      self.entries[next].prev = prev;
+    }
+    self.entries[index].armed = false;
+  }
+
+  fn release(&mut self, index: usize) {
+    let entry = &mut self.entries[index];
+    entry.generation = entry.generation.wrapping_add(1);
+    entry.next = self.free;
+    self.free = Some(index);
+  }
+
+  fn add(&mut self, expires: u64, period: u64, callback: TimerCallback, data: usize) -> TimerId {
+    let index = match self.free {
+      Some(index) => {
+        self.free = self.entries[index].next;
+        index
+      }
+      None => {
+        self.entries.push(TimerEntry {
+          expires:    0,
+          period:     0,
+          callback:   noop,
+          data:       0,
+          generation: 0,
+          armed:      false,
+          prev:       None,
+          next:       None,
+          slot:       0,
+        });
+        self.entries.len() - 1
+      }
+    };
+
+    let entry = &mut self.entries[index];
+    entry.expires = expires;
+    entry.period = period;
+    entry.callback = callback;
+    entry.data = data;
+    let generation = entry.generation;
+    self.link(index);
+
+    TimerId {
+      index: index as u32,
+      generation,
+    }
+  }
+
+  fn cancel(&mut self, id: TimerId) -> bool {
+    let index = id.index as usize;
+    match self.entries.get(index) {
+      Some(entry) if entry.generation == id.generation && entry.armed => {
+        self.unlink(index);
+        self.release(index);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Move the timers of the slot of the level, which the current jiffy reached, to lower levels.
+  fn cascade(&mut self, level: usize) {
+    let shift = ROOT_BITS + LEVEL_BITS * level as u32;
+    let slot =
+      ROOT_SIZE + level * LEVEL_SIZE + ((self.current >> shift) as usize & (LEVEL_SIZE - 1));
+
+    let mut next = self.slots[slot].take();
+    while let Some(index) = next {
+      next = self.entries[index].next;
+      self.link(index);
+    }
+  }
+
+  /// Take the next timer expired at the jiffy `now`, and return its callback. A periodic timer is
+  /// armed again.
+  fn pop_expired(&mut self, now: u64) -> Option<(TimerCallback, usize)> {
+    while self.current <= now {
+      let slot = (self.current as usize) & (ROOT_SIZE - 1);
+      if let Some(index) = self.slots[slot] {
+        self.unlink(index);
+        let entry = &mut self.entries[index];
+        let expired = (entry.callback, entry.data);
+        if entry.period != 0 {
+          entry.expires = entry.expires.saturating_add(entry.period).max(self.current + 1);
+          self.link(index);
+        } else {
+          self.release(index);
+        }
+        return Some(expired);
+      }
+
+      self.current += 1;
+      // Cascade the upper levels whose lower level wrapped around.
+      let mut shift = ROOT_BITS;
+      for level in 0..LEVELS {
+        if self.current & ((1 << shift) - 1) != 0 {
+          break;
+        }
+        self.cascade(level);
+        shift += LEVEL_BITS;
+      }
+    }
+    None
+  }
+
+  /// Return the earliest expiry jiffy of the armed timers.
+  fn next_expiry(&self) -> Option<u64> {
+    self.entries.iter().filter(|entry| entry.armed).map(|entry| entry.expires).min()
+  }
+}
+
+fn noop(_data: usize) {}
+
+/// Convert the count of nanoseconds into jiffies, rounded up.
+fn to_jiffies(nanoseconds: NanoSecond) -> u64 {
+  let period = NanoSecond::from(tick_period());
+  (nanoseconds * 1000).div_ceil(period) as u64
+}
+
+/// Convert the monotonic deadline into the jiffy of the tick after it. The jiffy is computed from
+/// the counter, since the jiffies lag behind while the tick is stopped.
+fn deadline_to_jiffies(deadline: NanoSecond) -> u64 {
+  to_jiffies(deadline.saturating_sub(*OFFSET.lock()))
+}
+
+/// Call `callback(data)` once at the monotonic deadline, in nanoseconds.
+pub fn add_timer(deadline: NanoSecond, callback: TimerCallback, data: usize) -> TimerId {
+  let expires = deadline_to_jiffies(deadline);
+  TIMER_WHEEL.lock().add(expires, 0, callback, data)
+}
+
+/// Call `callback(data)` once after the delay.
+pub fn add_timer_after(delay: Duration, callback: TimerCallback, data: usize) -> TimerId {
+  add_timer(monotonic() + delay.as_nanos(), callback, data)
+}
+
+/// Call `callback(data)` every period, the first time after one period.
+pub fn add_periodic_timer(period: Duration, callback: TimerCallback, data: usize) -> TimerId {
+  let period = to_jiffies(period.as_nanos()).max(1);
+  let expires = deadline_to_jiffies(monotonic()) + period;
+  TIMER_WHEEL.lock().add(expires, period, callback, data)
+}
+
+/// Cancel the timer, and return false if it has already expired or been cancelled.
+pub fn cancel_timer(id: TimerId) -> bool {
+  TIMER_WHEEL.lock().cancel(id)
+}
+
+/// Return the jiffy at which the next timer expires.
+pub fn next_expiry() -> Option<u64> {
+  TIMER_WHEEL.lock().next_expiry()
+}
+
+/// Run the callbacks of the expired timers, called on each tick with interrupts disabled.
+///
+/// The callbacks are called without the timer wheel locked, so that they may add and cancel
+/// timers.
+pub fn run_timers() {
+  let now = jiffies();
+  loop {
+    let expired = TIMER_WHEEL.lock().pop_expired(now);
+    match expired {
+      Some((callback, data)) => callback(data),
+      None => break,
+    }
+  }
+}
+
+/// Threads sleeping until a timer expires.
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// Wake the sleeping threads, which check their own deadline.
+fn wake_sleepers(_data: usize) {
+  SLEEPERS.wake_all();
+}
+
+/// Wait until the monotonic deadline, in nanoseconds.
+///
+/// A thread which may block sleeps until a timer expires at the deadline, giving its processor to
+/// the other threads. Otherwise the processor halts between ticks if interrupts are enabled, and
+/// spins if not.
+pub fn sleep_until(deadline: NanoSecond) {
+  if sched::can_block() {
+    let expires = deadline_to_jiffies(deadline);
+    // A deadline beyond the range of the wheel expires early, and the timer is armed again.
+    while jiffies() < expires {
+      let timer = TIMER_WHEEL.lock().add(expires, 0, wake_sleepers, 0);
+      SLEEPERS.wait_while(|| jiffies() < expires);
+      cancel_timer(timer);
+    }
+  }
+
+  while monotonic() < deadline {
+    if interrupt::are_enabled() {
+      unsafe { interrupt::halt() };
+    } else {
+      interrupt::pause();
+    }
+  }
+}
+
+/// Wait for the duration.
+pub fn sleep(duration: Duration) {
+  sleep_until(monotonic() + duration.as_nanos());
+}
+
+#[cfg(test)]
+mod tests {
+  use core::sync::atomic::AtomicUsize;
+  use core::sync::atomic::Ordering;
+
+  use super::*;
+
+  static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+  fn count(data: usize) {
+    FIRED.fetch_add(data, Ordering::Relaxed);
+  }
+
+  #[test_case]
+  fn test_timer_wheel_cascade() {
+    let mut wheel = TimerWheel::new();
+    wheel.add(10, 0, count, 1);
+    let far = wheel.add(100_000, 0, count, 10);
+    let periodic = wheel.add(300, 300, count, 100);
+    let cancelled = wheel.add(20, 0, count, 1000);
+    assert!(wheel.cancel(cancelled));
+    assert!(!wheel.cancel(cancelled));
+
+    FIRED.store(0, Ordering::Relaxed);
+    let mut run = |now| {
+      while let Some((callback, data)) = wheel.pop_expired(now) {
+        callback(data);
+      }
+    };
+    run(9);
+    assert_eq!(FIRED.load(Ordering::Relaxed), 0);
+    run(10);
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+    run(99_999);
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1 + 333 * 100);
+    run(100_000);
+    assert_eq!(FIRED.load(Ordering::Relaxed), 1 + 10 + 333 * 100);
+    assert!(!wheel.cancel(far));
+    assert!(wheel.cancel(periodic));
+    assert_eq!(wheel.next_expiry(), None);
+  }
+
+  #[test_case]
+  fn test_sleep_blocks() {
+    let deadline = monotonic() + Duration::from_millis(5).as_nanos();
+    let thread = sched::spawn("test_sleep", move || sleep_until(deadline)).unwrap();
+    sched::yield_until_blocked(&thread);
+    thread.join();
+    assert!(monotonic() >= deadline);
+  }
+}
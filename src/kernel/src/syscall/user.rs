@@ -0,0 +1,89 @@
+//! # User Memory Access
+//!
+//! With SMAP, the kernel faults on any access to a user page outside of a user access window. The
+//! system calls access user memory only through these helpers, which check that the range lies in
+//! the user region of the address space, and open the window just for the copy. The kernel lives
+//! in the lower half too, so the user region excludes every kernel mapping, see
+//! [`USER_SPACE_START`].
+//!
+//! Faults on user pages are not recovered yet, so the user range must be mapped.
+
+use crate::arch::cpu::user_access_begin;
+use crate::arch::cpu::user_access_end;
+use crate::arch::shared::irq::without_interrupts;
+use crate::arch::VirtualAddress;
+use crate::mem::USER_SPACE_END;
+use crate::mem::USER_SPACE_START;
+
+/// Error occurred when copying from or to user memory.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UserCopyError {
+  /// The range is not entirely in the user region of the address space.
+  BadAddress,
+}
+
+/// Check that the range of `len` bytes at the address lies in the user region.
+fn check_user_range(address: VirtualAddress, len: usize) -> Result<(), UserCopyError> {
+  match address.as_raw().checked_add(len as u64) {
+    Some(end) if address.as_raw() >= USER_SPACE_START && end <= USER_SPACE_END => Ok(()),
+    _ => Err(UserCopyError::BadAddress),
+  }
+}
+
+/// Copy `dst.len()` bytes from the user address into the kernel buffer.
+///
+/// ## Safety
+/// The user range must be mapped in the current address space.
+pub unsafe fn copy_from_user(dst: &mut [u8], src: VirtualAddress) -> Result<(), UserCopyError> {
+  check_user_range(src, dst.len())?;
+  // Interrupt handlers must not run inside the window, where they could touch user pages.
+  without_interrupts(|| {
+    user_access_begin();
+    core::ptr::copy_nonoverlapping(src.as_ptr::<u8>(), dst.as_mut_ptr(), dst.len());
+    user_access_end();
+  });
+  Ok(())
+}
+
+/// Copy the kernel buffer to the user address.
+///
+/// ## Safety
+/// The user range must be mapped writable in the current address space.
+pub unsafe fn copy_to_user(dst: VirtualAddress, src: &[u8]) -> Result<(), UserCopyError> {
+  check_user_range(dst, src.len())?;
+  without_interrupts(|| {
+    user_access_begin();
+    core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr::<u8>(), src.len());
+    user_access_end();
+  });
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::allocator::KERNEL_HEAP_START;
+
+  #[test_case]
+  fn test_check_user_range() {
+    assert_eq!(
+      check_user_range(VirtualAddress::new(USER_SPACE_START), 0x1000),
+      Ok(())
+    );
+    assert_eq!(
+      check_user_range(VirtualAddress::new(USER_SPACE_START - 8), 16),
+      Err(UserCopyError::BadAddress)
+    );
+    assert_eq!(
+      check_user_range(VirtualAddress::new(USER_SPACE_END - 8), 16),
+      Err(UserCopyError::BadAddress)
+    );
+    let kernel = VirtualAddress::new(0xFFFF_8000_0000_0000);
+    assert_eq!(check_user_range(kernel, 1), Err(UserCopyError::BadAddress));
+    // The kernel lives in the lower half too.
+    let heap = VirtualAddress::new(KERNEL_HEAP_START);
+    assert_eq!(check_user_range(heap, 8), Err(UserCopyError::BadAddress));
+    let image = VirtualAddress::new(check_user_range as *const () as u64);
+    assert_eq!(check_user_range(image, 8), Err(UserCopyError::BadAddress));
+  }
+}